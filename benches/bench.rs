@@ -1,9 +1,12 @@
 use std::hint::black_box;
 use std::str::FromStr;
+use std::sync::Arc;
 use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
 use serde_json::json;
 use query_lang::{Dynamic, TestObj, TestObj2};
 use query_lang::query::{Context, Script};
+use query_lang::query::ast::Predicate;
+use query_lang::raw::RawFilter;
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     let a = r#"{
@@ -59,6 +62,24 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             }
         }))
     ]))))));
+
+    let predicate = Predicate::from_str(r#"{ "field1": "TODWA", "field2.field5": { "$elemMatch": { "field10": 10 } } }"#).unwrap();
+    c.bench_function("Predicate on converted JSON", |b| b.iter(|| black_box(predicate.test_with_context(Dynamic::from(black_box(&json)), &mut Context::new()))));
+    let shared = Arc::new(json.clone());
+    c.bench_function("Predicate on JSON in place", |b| b.iter(|| black_box(predicate.test_with_context(Dynamic::from_json_shared(black_box(shared.clone())), &mut Context::new()))));
+
+    let line = r#"{"ts":"2024-01-01T00:00:00Z","level":"info","service":{"name":"api","region":"eu"},"payload":{"items":[1,2,3,4,5,6,7,8],"text":"lorem ipsum dolor sit amet"},"status":200}"#;
+    let ndjson = vec![line; 1000].join("\n");
+    let predicate = Predicate::from_str(r#"{ "level": "error", "status": { "$gte": 500 } }"#).unwrap();
+    let filter = RawFilter::new(&predicate);
+    c.bench_function("NDJSON parsed", |b| b.iter(|| {
+        let mut context = Context::new();
+        black_box(ndjson.lines().filter(|line| {
+            let document = Dynamic::from(&serde_json::from_str::<serde_json::Value>(line).unwrap());
+            predicate.test_with_context(document, &mut context).unwrap()
+        }).count())
+    }));
+    c.bench_function("NDJSON raw filter", |b| b.iter(|| black_box(filter.filter_lines(black_box(&ndjson)).count())));
 }
 
 
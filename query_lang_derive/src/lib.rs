@@ -0,0 +1,459 @@
+//! `#[derive(DynamicObject)]` for `query_lang`, which exposes the fields of structs and enums to queries.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, GenericArgument, LitStr, PathArguments, Type};
+
+/// Implements `DynamicObject` by exposing the named fields of a struct, or of the current variant of an enum.
+///
+/// Field values are converted with `Into<Dynamic>` from a clone of the field, `Option<T>` is converted
+/// to null when it is `None` and `Vec<T>` to an array. Views of fields borrow the fields whose types
+/// implement `AsDynamicRef`, such as strings, nested objects and vectors of them, which are viewed as dynamic arrays. Supported attributes:
+///
+/// - `#[dynamic(rename = "name")]` on a field or on an enum variant exposes it under another name
+/// - `#[dynamic(skip)]` on a field hides it
+/// - `#[dynamic(flatten)]` on a field exposes the fields of a derived struct as fields of this object
+/// - `#[dynamic(tag = "name")]` on an enum exposes the name of the variant as a field
+/// - `#[dynamic(mutable)]` on a struct which implements `Clone` also implements `DynamicObjectMut` and `FromDynamic`,
+///   so updates can modify its fields. The types of its fields and of its flattened structs must implement
+///   `FromDynamic`, and flattened structs must be mutable too. Skipped fields are set to their default value
+///   when the struct is built with `FromDynamic`.
+///
+/// Variants of enums with a single unnamed field are flattened.
+#[proc_macro_derive(DynamicObject, attributes(dynamic))]
+pub fn derive_dynamic_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[derive(Default)]
+struct Attributes {
+    rename: Option<String>,
+    skip: bool,
+    flatten: bool,
+    tag: Option<String>,
+    mutable: bool,
+}
+
+impl Attributes {
+    fn parse(attributes: &[Attribute]) -> syn::Result<Attributes> {
+        let mut parsed = Attributes::default();
+        for attribute in attributes.iter().filter(|attribute| attribute.path().is_ident("dynamic")) {
+            attribute.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    parsed.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("tag") {
+                    parsed.tag = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("skip") {
+                    parsed.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    parsed.flatten = true;
+                } else if meta.path.is_ident("mutable") {
+                    parsed.mutable = true;
+                } else {
+                    return Err(meta.error("unsupported dynamic attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// A member of the object, with the expression which borrows its value
+enum Member {
+    Field { name: String, value: TokenStream2, ty: Type },
+    Flatten { value: TokenStream2, ty: Type },
+    Tag { name: String, variant: String },
+}
+
+/// Members of a struct or of an enum variant, in the order of the fields
+struct Object {
+    pattern: TokenStream2,
+    members: Vec<Member>,
+}
+
+impl Object {
+    fn new(pattern: TokenStream2, fields: &Fields, access: impl Fn(usize, &syn::Field) -> TokenStream2) -> syn::Result<Object> {
+        let mut members = Vec::new();
+        match fields {
+            Fields::Named(fields) => {
+                for (index, field) in fields.named.iter().enumerate() {
+                    let attributes = Attributes::parse(&field.attrs)?;
+                    if attributes.skip {
+                        continue;
+                    }
+                    let value = access(index, field);
+                    let ty = field.ty.clone();
+                    members.push(if attributes.flatten {
+                        Member::Flatten { value, ty }
+                    } else {
+                        let name = attributes.rename.unwrap_or_else(|| field.ident.as_ref().unwrap().unraw().to_string());
+                        Member::Field { name, value, ty }
+                    });
+                }
+            }
+            Fields::Unnamed(fields) => return Err(Error::new(fields.span(), "tuple structs are not supported")),
+            Fields::Unit => {}
+        }
+        Ok(Object { pattern, members })
+    }
+
+    /// Returns the value of the field, or its borrowed view
+    fn get_field(&self, borrowed: bool) -> TokenStream2 {
+        let mut arms = Vec::new();
+        let mut flattened = Vec::new();
+        for member in &self.members {
+            match member {
+                Member::Field { name, value, ty } => {
+                    let value = if borrowed { to_dynamic_ref(ty, value.clone()) } else { to_dynamic(ty, value.clone()) };
+                    arms.push(quote!(#name => ::std::option::Option::Some(#value),));
+                }
+                Member::Flatten { value, .. } => {
+                    let get_field = if borrowed { quote!(get_field_ref) } else { quote!(get_field) };
+                    flattened.push(quote! {
+                        if let ::std::option::Option::Some(value) = ::query_lang::DynamicObject::#get_field(#value, field) {
+                            return ::std::option::Option::Some(value);
+                        }
+                    })
+                }
+                Member::Tag { name, variant } => {
+                    let value = if borrowed {
+                        quote!(::query_lang::DynamicRef::String(#variant))
+                    } else {
+                        quote!(::query_lang::Dynamic::from(#variant))
+                    };
+                    arms.push(quote!(#name => ::std::option::Option::Some(#value),));
+                }
+            }
+        }
+        quote! {
+            match field {
+                #(#arms)*
+                _ => {
+                    #(#flattened)*
+                    ::std::option::Option::None
+                }
+            }
+        }
+    }
+
+    fn has_flattened(&self) -> bool {
+        self.members.iter().any(|member| matches!(member, Member::Flatten { .. }))
+    }
+
+    /// Builds the list of the names of the fields, including the fields of flattened structs
+    fn field_names(&self) -> TokenStream2 {
+        let names = self.members.iter().map(|member| match member {
+            Member::Field { name, .. } | Member::Tag { name, .. } => quote!(names.push(#name);),
+            Member::Flatten { ty, .. } => quote!(names.extend(<#ty as ::query_lang::derive::FieldNames>::field_names());),
+        });
+        quote! {
+            let mut names = ::std::vec::Vec::new();
+            #(#names)*
+            names
+        }
+    }
+
+    fn fields(&self) -> TokenStream2 {
+        if self.has_flattened() {
+            let field_names = self.field_names();
+            quote! {
+                static FIELDS: ::std::sync::OnceLock<::std::vec::Vec<&'static str>> = ::std::sync::OnceLock::new();
+                ::std::borrow::Cow::Borrowed(FIELDS.get_or_init(|| { #field_names }))
+            }
+        } else {
+            let names = self.members.iter().map(|member| match member {
+                Member::Field { name, .. } | Member::Tag { name, .. } => name,
+                Member::Flatten { .. } => unreachable!(),
+            });
+            quote!(::std::borrow::Cow::Borrowed(&[#(#names),*]))
+        }
+    }
+}
+
+/// Converts the borrowed value of the type into a `Dynamic`
+fn to_dynamic(ty: &Type, value: TokenStream2) -> TokenStream2 {
+    if let Some(inner) = generic_argument(ty, "Option") {
+        let inner = to_dynamic(inner, quote!(value));
+        return quote! {
+            match #value {
+                ::std::option::Option::Some(value) => #inner,
+                ::std::option::Option::None => ::query_lang::Dynamic::Null,
+            }
+        };
+    }
+    if let Some(inner) = generic_argument(ty, "Vec") {
+        let inner = to_dynamic(inner, quote!(value));
+        return quote! {
+            ::query_lang::Dynamic::from(<[_]>::iter(#value).map(|value| #inner).collect::<::std::vec::Vec<::query_lang::Dynamic>>())
+        };
+    }
+    quote!(::query_lang::Dynamic::from(::std::clone::Clone::clone(#value)))
+}
+
+/// Converts the borrowed value of the type into a `DynamicRef` which borrows it where possible
+fn to_dynamic_ref(ty: &Type, value: TokenStream2) -> TokenStream2 {
+    if let Some(inner) = generic_argument(ty, "Option") {
+        let inner = to_dynamic_ref(inner, quote!(value));
+        return quote! {
+            match #value {
+                ::std::option::Option::Some(value) => #inner,
+                ::std::option::Option::None => ::query_lang::DynamicRef::Null,
+            }
+        };
+    }
+    if generic_argument(ty, "Vec").is_some() {
+        let items = to_dynamic(ty, value.clone());
+        return quote!((&&::query_lang::derive::ArrayFieldRef(#value, || #items)).to_dynamic_ref());
+    }
+    quote!((&&::query_lang::derive::FieldRef(#value)).to_dynamic_ref())
+}
+
+/// Returns the type argument of the type if it is the generic type of the name, e.g. `T` of `Vec<T>`
+fn generic_argument<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last().filter(|segment| segment.ident == name)?;
+    let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return None;
+    };
+    match arguments.args.first() {
+        Some(GenericArgument::Type(ty)) if arguments.args.len() == 1 => Some(ty),
+        _ => None,
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attributes = Attributes::parse(&input.attrs)?;
+    let objects = match &input.data {
+        Data::Struct(data) => {
+            let fields = data.fields.iter().enumerate().map(|(index, _)| format_ident!("field{}", index));
+            let names = data.fields.iter().map(|field| &field.ident);
+            let pattern = match data.fields {
+                Fields::Named(_) => quote!(Self { #(#names: #fields,)* }),
+                _ => quote!(Self { .. }),
+            };
+            vec![Object::new(pattern, &data.fields, |index, _| {
+                let field = format_ident!("field{}", index);
+                quote!(#field)
+            })?]
+        }
+        Data::Enum(data) => {
+            let mut objects = Vec::new();
+            for variant in &data.variants {
+                let variant_attributes = Attributes::parse(&variant.attrs)?;
+                let ident = &variant.ident;
+                let variant_name = variant_attributes.rename.unwrap_or_else(|| ident.unraw().to_string());
+                let mut object = match &variant.fields {
+                    Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Object {
+                        pattern: quote!(Self::#ident(field0)),
+                        members: vec![Member::Flatten {
+                            value: quote!(field0),
+                            ty: fields.unnamed[0].ty.clone(),
+                        }],
+                    },
+                    Fields::Unnamed(fields) => {
+                        return Err(Error::new(fields.span(), "only variants with a single unnamed field are supported"))
+                    }
+                    fields => {
+                        let bindings = fields.iter().enumerate().map(|(index, field)| {
+                            let name = &field.ident;
+                            let binding = format_ident!("field{}", index);
+                            quote!(#name: #binding)
+                        });
+                        let pattern = quote!(Self::#ident { #(#bindings,)* });
+                        Object::new(pattern, fields, |index, _| {
+                            let field = format_ident!("field{}", index);
+                            quote!(#field)
+                        })?
+                    }
+                };
+                if let Some(tag) = &attributes.tag {
+                    object.members.insert(0, Member::Tag { name: tag.clone(), variant: variant_name });
+                }
+                objects.push(object);
+            }
+            objects
+        }
+        Data::Union(data) => return Err(Error::new(data.union_token.span, "unions are not supported")),
+    };
+
+    let flattened = objects.iter().any(Object::has_flattened);
+    if flattened && input.generics.type_params().next().is_some() {
+        return Err(Error::new(input.generics.span(), "flattened fields are not supported in generic types"));
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let patterns = objects.iter().map(|object| &object.pattern).collect::<Vec<_>>();
+    let get_field = objects.iter().map(|object| object.get_field(false));
+    let get_field_ref = objects.iter().map(|object| object.get_field(true));
+    let fields = objects.iter().map(Object::fields);
+    let mut expanded = quote! {
+        impl #impl_generics ::query_lang::DynamicObject for #name #type_generics #where_clause {
+            #[allow(unused_variables)]
+            fn get_field(&self, field: &str) -> ::std::option::Option<::query_lang::Dynamic> {
+                match self {
+                    #(#patterns => { #get_field })*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn fields(&self) -> ::std::borrow::Cow<'_, [&str]> {
+                match self {
+                    #(#patterns => { #fields })*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn get_field_ref(&self, field: &str) -> ::std::option::Option<::query_lang::DynamicRef<'_>> {
+                use ::query_lang::derive::{BorrowedField as _, OwnedField as _};
+                match self {
+                    #(#patterns => { #get_field_ref })*
+                }
+            }
+        }
+    };
+    if !attributes.mutable {
+        expanded.extend(quote! {
+            impl #impl_generics ::query_lang::AsDynamicObjectMut for #name #type_generics #where_clause {}
+        });
+    }
+    if let (Data::Struct(_), [object]) = (&input.data, objects.as_slice()) {
+        let field_names = object.field_names();
+        expanded.extend(quote! {
+            impl #impl_generics ::query_lang::derive::FieldNames for #name #type_generics #where_clause {
+                fn field_names() -> ::std::vec::Vec<&'static str> {
+                    #field_names
+                }
+            }
+        });
+    }
+
+    if attributes.mutable {
+        let (Data::Struct(data), [object]) = (&input.data, objects.as_slice()) else {
+            return Err(Error::new(input.ident.span(), "only structs can be mutable"));
+        };
+        if input.generics.type_params().next().is_some() {
+            return Err(Error::new(input.generics.span(), "mutable generic types are not supported"));
+        }
+        expanded.extend(expand_mutable(name, &data.fields, object)?);
+    }
+
+    Ok(expanded)
+}
+
+/// Implements `DynamicObjectMut` and `FromDynamic` for a mutable struct
+fn expand_mutable(name: &syn::Ident, fields: &Fields, object: &Object) -> syn::Result<TokenStream2> {
+    let mut set_arms = Vec::new();
+    let mut remove_arms = Vec::new();
+    let mut set_flattened = Vec::new();
+    let mut remove_flattened = Vec::new();
+    for member in &object.members {
+        match member {
+            Member::Field { name, value, ty } => {
+                let previous = to_dynamic(ty, quote!(&*#value));
+                set_arms.push(quote! {
+                    #name => {
+                        let converted = ::query_lang::derive::field_from_dynamic::<#ty>(field, &value)?;
+                        let previous = #previous;
+                        *#value = converted;
+                        ::std::result::Result::Ok(::std::option::Option::Some(previous))
+                    }
+                });
+                remove_arms.push(match generic_argument(ty, "Option") {
+                    Some(inner) => {
+                        let previous = to_dynamic(inner, quote!(&value));
+                        quote!(#name => ::std::result::Result::Ok(::std::option::Option::take(#value).map(|value| #previous)),)
+                    }
+                    None => quote! {
+                        #name => ::std::result::Result::Err(::query_lang::DynamicError::TypeMismatch {
+                            field: field.into(),
+                            expected: ::std::any::type_name::<#ty>(),
+                        }),
+                    },
+                });
+            }
+            Member::Flatten { value, ty } => {
+                set_flattened.push(quote! {
+                    if <#ty as ::query_lang::DynamicObject>::fields(#value).contains(&field) {
+                        return <#ty as ::query_lang::DynamicObjectMut>::set_field(#value, field, value);
+                    }
+                });
+                remove_flattened.push(quote! {
+                    if <#ty as ::query_lang::DynamicObject>::fields(#value).contains(&field) {
+                        return <#ty as ::query_lang::DynamicObjectMut>::remove_field(#value, field);
+                    }
+                });
+            }
+            Member::Tag { .. } => unreachable!(),
+        }
+    }
+
+    let mut initializers = Vec::new();
+    for field in fields {
+        let attributes = Attributes::parse(&field.attrs)?;
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        initializers.push(if attributes.skip {
+            quote!(#ident: ::std::default::Default::default())
+        } else if attributes.flatten {
+            quote!(#ident: <#ty as ::query_lang::FromDynamic>::from_dynamic(value)?)
+        } else {
+            let name = attributes.rename.unwrap_or_else(|| ident.unraw().to_string());
+            quote!(#ident: ::query_lang::derive::field_from_object::<#ty>(value, #name)?)
+        });
+    }
+
+    let pattern = &object.pattern;
+    Ok(quote! {
+        impl ::query_lang::DynamicObjectMut for #name {
+            #[allow(unused_variables)]
+            fn set_field(
+                &mut self,
+                field: &str,
+                value: ::query_lang::Dynamic,
+            ) -> ::std::result::Result<::std::option::Option<::query_lang::Dynamic>, ::query_lang::DynamicError> {
+                let #pattern = self;
+                match field {
+                    #(#set_arms)*
+                    _ => {
+                        #(#set_flattened)*
+                        ::std::result::Result::Err(::query_lang::DynamicError::ImmutableObject)
+                    }
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn remove_field(
+                &mut self,
+                field: &str,
+            ) -> ::std::result::Result<::std::option::Option<::query_lang::Dynamic>, ::query_lang::DynamicError> {
+                let #pattern = self;
+                match field {
+                    #(#remove_arms)*
+                    _ => {
+                        #(#remove_flattened)*
+                        ::std::result::Result::Ok(::std::option::Option::None)
+                    }
+                }
+            }
+
+            fn clone_object(&self) -> ::std::sync::Arc<dyn ::query_lang::DynamicObject> {
+                ::std::sync::Arc::new(::std::clone::Clone::clone(self))
+            }
+        }
+
+        impl ::query_lang::FromDynamic for #name {
+            fn from_dynamic(value: &::query_lang::Dynamic) -> ::std::option::Option<Self> {
+                value.as_object()?;
+                ::std::option::Option::Some(Self { #(#initializers,)* })
+            }
+        }
+    })
+}
@@ -0,0 +1,521 @@
+//! BSON types which matter for Mongo compatibility, stored in `Dynamic::Bson`, and their Extended JSON v2 representation.
+//! Encoding and decoding BSON documents is behind the `bson` feature, see `Dynamic::from_bson`.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Arc;
+use derive_more::From;
+use hashlink::LinkedHashMap;
+use serde_json::json;
+use smartstring::alias::String;
+use crate::query::ast::Value;
+use crate::Number;
+
+#[derive(Debug, Clone, PartialEq, From)]
+pub enum Bson {
+    ObjectId(ObjectId),
+    DateTime(DateTime),
+    Binary(Binary),
+    Timestamp(Timestamp),
+    Decimal128(Decimal128),
+}
+
+impl Bson {
+    /// Position of the type in the order of types of Mongo, consistent with `Dynamic::comparison_order`.
+    /// Decimals are ordered with the other numbers.
+    pub(crate) fn comparison_order(&self) -> u8 {
+        match self {
+            Bson::Decimal128(_) => 2,
+            Bson::Binary(_) => 6,
+            Bson::ObjectId(_) => 7,
+            Bson::DateTime(_) => 9,
+            Bson::Timestamp(_) => 10,
+        }
+    }
+
+    /// Compares values of the same type, decimals exactly by their value
+    pub(crate) fn compare(&self, other: &Bson) -> Option<Ordering> {
+        match (self, other) {
+            (Bson::ObjectId(first), Bson::ObjectId(second)) => Some(first.cmp(second)),
+            (Bson::DateTime(first), Bson::DateTime(second)) => Some(first.cmp(second)),
+            (Bson::Binary(first), Bson::Binary(second)) => Some(first.cmp(second)),
+            (Bson::Timestamp(first), Bson::Timestamp(second)) => Some(first.cmp(second)),
+            (Bson::Decimal128(first), Bson::Decimal128(second)) => Some(first.total_cmp(second)),
+            _ => None,
+        }
+    }
+
+    /// Returns the relaxed Extended JSON v2 representation, e.g. `{ "$oid": "..." }`
+    pub fn to_extended_json(&self) -> serde_json::Value {
+        match self {
+            Bson::ObjectId(id) => json!({ "$oid": id.to_string() }),
+            Bson::DateTime(date) if (0..=253_402_300_799_999).contains(&date.0) => json!({ "$date": date.to_string() }),
+            Bson::DateTime(date) => json!({ "$date": { "$numberLong": date.0.to_string() } }),
+            Bson::Binary(binary) => json!({
+                "$binary": { "base64": encode_base64(&binary.bytes), "subType": format!("{:02x}", binary.subtype) }
+            }),
+            Bson::Timestamp(timestamp) => json!({ "$timestamp": { "t": timestamp.time, "i": timestamp.increment } }),
+            Bson::Decimal128(decimal) => json!({ "$numberDecimal": decimal.to_string() }),
+        }
+    }
+}
+
+impl Display for Bson {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.to_extended_json(), formatter)
+    }
+}
+
+/// Keys of the objects which `from_extended_json` converts, so other objects can be told apart by their first key
+pub(crate) const EXTENDED_JSON_KEYS: [&str; 8] =
+    ["$oid", "$date", "$numberDecimal", "$numberLong", "$numberInt", "$numberDouble", "$binary", "$timestamp"];
+
+/// Parses the Extended JSON v2 representation of a value, in canonical or relaxed mode, e.g. `{ "$oid": "..." }`.
+/// `$numberInt`, `$numberLong` and `$numberDouble` are numbers. Returns `None` for other objects.
+/// Nested values are expected to be converted already, e.g. `{"$date": {"$numberLong": "..."}}` holds a number.
+pub(crate) fn from_extended_json(object: &LinkedHashMap<String, Value>) -> Option<Value> {
+    let mut fields = object.iter();
+    let ((key, value), None) = (fields.next()?, fields.next()) else {
+        return None;
+    };
+    let string = |value: &Value| match value {
+        Value::String(string) => Some(string.to_string()),
+        _ => None,
+    };
+    let bson = match (key.as_str(), value) {
+        ("$numberInt", value) => return string(value)?.parse::<i32>().ok().map(|int| Value::Number(Number::Int(int.into()))),
+        ("$numberLong", value) => return string(value)?.parse().ok().map(|int| Value::Number(Number::Int(int))),
+        ("$numberDouble", value) => return string(value)?.parse().ok().map(|float| Value::Number(Number::Float(float))),
+        ("$oid", value) => Bson::from(string(value)?.parse::<ObjectId>().ok()?),
+        ("$numberDecimal", value) => Bson::from(string(value)?.parse::<Decimal128>().ok()?),
+        ("$date", Value::String(date)) => Bson::from(date.parse::<DateTime>().ok()?),
+        ("$date", Value::Number(Number::Int(millis))) => Bson::from(DateTime(*millis)),
+        ("$binary", Value::Object(binary)) if binary.len() == 2 => Bson::from(Binary {
+            subtype: u8::from_str_radix(&string(binary.get("subType")?)?, 16).ok()?,
+            bytes: decode_base64(&string(binary.get("base64")?)?)?.into(),
+        }),
+        ("$timestamp", Value::Object(timestamp)) if timestamp.len() == 2 => {
+            let part = |name| match timestamp.get(name)? {
+                Value::Number(Number::Int(int)) => u32::try_from(*int).ok(),
+                _ => None,
+            };
+            Bson::from(Timestamp { time: part("t")?, increment: part("i")? })
+        }
+        _ => return None,
+    };
+    Some(Value::Bson(bson))
+}
+
+/// 12 byte identifier, which starts with the seconds since the Unix epoch at which it was created
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId(pub [u8; 12]);
+
+impl ObjectId {
+    /// Returns the time at which the identifier was created
+    pub fn timestamp(&self) -> DateTime {
+        let seconds = u32::from_be_bytes([self.0[0], self.0[1], self.0[2], self.0[3]]);
+        DateTime(seconds as i64 * 1000)
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = ();
+
+    /// Parses the 24 hexadecimal digits of the identifier
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if string.len() != 24 || !string.is_ascii() {
+            return Err(());
+        }
+        let mut bytes = [0; 12];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&string[index * 2..index * 2 + 2], 16).map_err(drop)?;
+        }
+        Ok(ObjectId(bytes))
+    }
+}
+
+impl Display for ObjectId {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(formatter, "{:02x}", byte))
+    }
+}
+
+impl Debug for ObjectId {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(formatter, "ObjectId(\"{}\")", self)
+    }
+}
+
+/// Milliseconds since the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime(pub i64);
+
+/// Days since the Unix epoch of the date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Number of days of the month of the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Date of the proleptic Gregorian calendar of the days since the Unix epoch
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+impl FromStr for DateTime {
+    type Err = ();
+
+    /// Parses an RFC 3339 date, e.g. `2024-05-01T12:30:00.250Z` or `2024-05-01T14:30:00+02:00`.
+    /// Fractions of seconds are truncated to milliseconds.
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let bytes = string.as_bytes();
+        let number = |range: std::ops::Range<usize>| -> Result<i64, ()> {
+            let digits = string.get(range).ok_or(())?;
+            if digits.bytes().all(|byte| byte.is_ascii_digit()) { digits.parse().map_err(drop) } else { Err(()) }
+        };
+        let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+        if bytes.len() < 20 || separators.iter().any(|&(index, separator)| bytes[index] != separator) || !matches!(bytes[10], b'T' | b't' | b' ') {
+            return Err(());
+        }
+        let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+        let (hours, minutes, seconds) = (number(11..13)?, number(14..16)?, number(17..19)?);
+        if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+            return Err(());
+        }
+
+        let mut position = 19;
+        let mut millis = 0;
+        if bytes[position] == b'.' {
+            let digits = bytes[position + 1..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+            if digits == 0 {
+                return Err(());
+            }
+            millis = bytes[position + 1..position + 1 + digits.min(3)]
+                .iter()
+                .chain(std::iter::repeat(&b'0'))
+                .take(3)
+                .fold(0, |millis, digit| millis * 10 + i64::from(digit - b'0'));
+            position += 1 + digits;
+        }
+        let offset = match &string[position..] {
+            "Z" | "z" => 0,
+            offset if offset.len() == 6 && offset.as_bytes()[3] == b':' => {
+                let minutes = number(position + 1..position + 3)? * 60 + number(position + 4..position + 6)?;
+                match offset.as_bytes()[0] {
+                    b'+' => minutes,
+                    b'-' => -minutes,
+                    _ => return Err(()),
+                }
+            }
+            _ => return Err(()),
+        };
+
+        let seconds = days_from_civil(year, month, day) * 86_400 + hours * 3600 + (minutes - offset) * 60 + seconds;
+        Ok(DateTime(seconds * 1000 + millis))
+    }
+}
+
+/// Formats the date as RFC 3339 in UTC with milliseconds, e.g. `2024-05-01T12:30:00.250Z`
+impl Display for DateTime {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let (days, millis) = (self.0.div_euclid(86_400_000), self.0.rem_euclid(86_400_000));
+        let (year, month, day) = civil_from_days(days);
+        let seconds = millis / 1000;
+        write!(
+            formatter,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            millis % 1000
+        )
+    }
+}
+
+/// Binary data with the subtype which describes it, e.g. `0x04` for UUIDs
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Binary {
+    pub subtype: u8,
+    pub bytes: Arc<[u8]>,
+}
+
+/// Orders binary data by length, then by subtype and then by bytes, as Mongo does
+impl Ord for Binary {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.bytes.len(), self.subtype, &self.bytes).cmp(&(other.bytes.len(), other.subtype, &other.bytes))
+    }
+}
+
+impl PartialOrd for Binary {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Internal timestamp of Mongo, ordered by time and then by increment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    pub time: u32,
+    pub increment: u32,
+}
+
+/// IEEE 754-2008 128-bit decimal in the binary integer decimal encoding used by BSON
+#[derive(Clone, Copy)]
+pub struct Decimal128(u128);
+
+const EXPONENT_BIAS: i32 = 6176;
+const MAX_COEFFICIENT: u128 = 10u128.pow(34) - 1;
+
+enum Decimal {
+    NaN,
+    Infinity { negative: bool },
+    Finite { negative: bool, coefficient: u128, exponent: i32 },
+}
+
+impl Decimal128 {
+    pub fn from_bytes(bytes: [u8; 16]) -> Decimal128 {
+        Decimal128(u128::from_le_bytes(bytes))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        self.0.to_le_bytes()
+    }
+
+    fn decode(&self) -> Decimal {
+        let negative = self.0 >> 127 == 1;
+        let combination = (self.0 >> 122) & 0b11111;
+        if combination == 0b11111 {
+            return Decimal::NaN;
+        }
+        if combination == 0b11110 {
+            return Decimal::Infinity { negative };
+        }
+        let (exponent, coefficient) = if (self.0 >> 125) & 0b11 == 0b11 {
+            // Coefficients of this form are larger than the largest coefficient, so they are zero
+            ((self.0 >> 111) & 0x3fff, 0)
+        } else {
+            ((self.0 >> 113) & 0x3fff, self.0 & ((1 << 113) - 1))
+        };
+        let coefficient = if coefficient > MAX_COEFFICIENT { 0 } else { coefficient };
+        Decimal::Finite { negative, coefficient, exponent: exponent as i32 - EXPONENT_BIAS }
+    }
+
+    fn encode(negative: bool, coefficient: u128, exponent: i32) -> Decimal128 {
+        let sign = u128::from(negative) << 127;
+        Decimal128(sign | ((exponent + EXPONENT_BIAS) as u128) << 113 | coefficient)
+    }
+
+    /// Converts the decimal into an integer if it is integral and fits, and otherwise into the nearest float
+    pub fn to_number(&self) -> Number {
+        match self.decode() {
+            Decimal::NaN => Number::Float(f64::NAN),
+            Decimal::Infinity { negative } => Number::Float(if negative { f64::NEG_INFINITY } else { f64::INFINITY }),
+            Decimal::Finite { negative, coefficient, exponent } => {
+                let sign = if negative { -1 } else { 1 };
+                let int = match exponent {
+                    0.. => 10i128.checked_pow(exponent as u32).and_then(|scale| (coefficient as i128).checked_mul(scale)),
+                    ..0 => 10i128
+                        .checked_pow(exponent.unsigned_abs())
+                        .filter(|&scale| coefficient as i128 % scale == 0)
+                        .map(|scale| coefficient as i128 / scale),
+                };
+                match int.and_then(|int| i64::try_from(sign * int).ok()) {
+                    Some(int) => Number::Int(int),
+                    None => Number::Float(self.to_string().parse().unwrap_or(f64::NAN)),
+                }
+            }
+        }
+    }
+
+    /// Compares decimals exactly by their value, where NaN is equal to itself and less than any other number
+    pub fn total_cmp(&self, other: &Decimal128) -> Ordering {
+        self.order_key().cmp(&other.order_key())
+    }
+
+    /// Orders NaN, negative infinity, negative numbers, zeros, positive numbers and positive infinity.
+    /// Other numbers are ordered by their adjusted exponent and then by their coefficient scaled to 34 digits,
+    /// both negated for negative numbers, so equal numbers with different exponents have the same key.
+    fn order_key(&self) -> (u8, i32, i128) {
+        match self.decode() {
+            Decimal::NaN => (0, 0, 0),
+            Decimal::Infinity { negative: true } => (1, 0, 0),
+            Decimal::Infinity { negative: false } => (5, 0, 0),
+            Decimal::Finite { coefficient: 0, .. } => (3, 0, 0),
+            Decimal::Finite { negative, coefficient, exponent } => {
+                let digits = coefficient.ilog10() + 1;
+                let adjusted = exponent + digits as i32 - 1;
+                let scaled = (coefficient * 10u128.pow(34 - digits)) as i128;
+                if negative { (2, -adjusted, -scaled) } else { (4, adjusted, scaled) }
+            }
+        }
+    }
+
+    /// Compares the decimal exactly with integers and as the nearest float with floats,
+    /// where NaN is equal to itself and less than any other number
+    pub(crate) fn total_cmp_number(&self, number: &Number) -> Ordering {
+        match number {
+            Number::Int(int) => self.total_cmp(&Decimal128::from(*int)),
+            Number::Float(_) => self.to_number().total_cmp(number),
+        }
+    }
+
+    /// Compares the decimal exactly with integers and as the nearest float with floats, NaN is unordered
+    pub(crate) fn partial_cmp_number(&self, number: &Number) -> Option<Ordering> {
+        match (self.decode(), number) {
+            (Decimal::NaN, _) | (_, Number::Float(_)) => self.to_number().partial_cmp(number),
+            (_, Number::Int(int)) => Some(self.total_cmp(&Decimal128::from(*int))),
+        }
+    }
+}
+
+impl From<i64> for Decimal128 {
+    fn from(int: i64) -> Self {
+        Decimal128::encode(int < 0, u128::from(int.unsigned_abs()), 0)
+    }
+}
+
+impl PartialEq for Decimal128 {
+    fn eq(&self, other: &Self) -> bool {
+        self.total_cmp(other).is_eq()
+    }
+}
+
+impl FromStr for Decimal128 {
+    type Err = ();
+
+    /// Parses a decimal with at most 34 significant digits, e.g. `-12.50`, `1E+3` or `Infinity`
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match string.as_bytes().first() {
+            Some(b'-') => (true, &string[1..]),
+            Some(b'+') => (false, &string[1..]),
+            _ => (false, string),
+        };
+        if unsigned.eq_ignore_ascii_case("nan") {
+            return Ok(Decimal128(0b11111 << 122));
+        }
+        if unsigned.eq_ignore_ascii_case("inf") || unsigned.eq_ignore_ascii_case("infinity") {
+            return Ok(Decimal128(u128::from(negative) << 127 | 0b11110 << 122));
+        }
+
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(index) => (&unsigned[..index], unsigned[index + 1..].parse::<i32>().map_err(drop)?),
+            None => (unsigned, 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if integer.is_empty() && fraction.is_empty() || !integer.bytes().chain(fraction.bytes()).all(|byte| byte.is_ascii_digit()) {
+            return Err(());
+        }
+        let digits = format!("{}{}", integer, fraction);
+        let mut digits = digits.trim_start_matches('0');
+        let mut exponent = exponent.checked_sub(fraction.len() as i32).ok_or(())?;
+        // Trailing zeros of coefficients which are too long are moved into the exponent
+        while digits.len() > 34 && digits.ends_with('0') {
+            digits = &digits[..digits.len() - 1];
+            exponent = exponent.checked_add(1).ok_or(())?;
+        }
+        if digits.len() > 34 {
+            return Err(());
+        }
+        let coefficient = if digits.is_empty() { 0 } else { digits.parse::<u128>().map_err(drop)? };
+        if !(-EXPONENT_BIAS..=6111).contains(&exponent) {
+            return Err(());
+        }
+        Ok(Decimal128::encode(negative, coefficient, exponent))
+    }
+}
+
+/// Formats the decimal as the string representation of the decimal arithmetic specification, as Mongo does
+impl Display for Decimal128 {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let (negative, coefficient, exponent) = match self.decode() {
+            Decimal::NaN => return formatter.write_str("NaN"),
+            Decimal::Infinity { negative } => return formatter.write_str(if negative { "-Infinity" } else { "Infinity" }),
+            Decimal::Finite { negative, coefficient, exponent } => (negative, coefficient, exponent),
+        };
+        if negative {
+            formatter.write_str("-")?;
+        }
+        let digits = coefficient.to_string();
+        let adjusted = exponent + digits.len() as i32 - 1;
+        if exponent <= 0 && adjusted >= -6 {
+            let point = digits.len() as i32 + exponent;
+            match point {
+                _ if exponent == 0 => formatter.write_str(&digits),
+                1.. => write!(formatter, "{}.{}", &digits[..point as usize], &digits[point as usize..]),
+                _ => write!(formatter, "0.{}{}", "0".repeat(point.unsigned_abs() as usize), digits),
+            }
+        } else {
+            formatter.write_str(&digits[..1])?;
+            if digits.len() > 1 {
+                write!(formatter, ".{}", &digits[1..])?;
+            }
+            write!(formatter, "E{}{}", if adjusted >= 0 { "+" } else { "" }, adjusted)
+        }
+    }
+}
+
+impl Debug for Decimal128 {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(formatter, "Decimal128(\"{}\")", self)
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(bytes: &[u8]) -> std::string::String {
+    let mut encoded = std::string::String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, &byte)| group | u32::from(byte) << (16 - index * 8));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - index * 6)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut group = 0u32;
+        for (index, &character) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&letter| letter == character)? as u32;
+            group |= value << (18 - index * 6);
+        }
+        bytes.extend(group.to_be_bytes()[1..chunk.len()].iter());
+    }
+    Some(bytes)
+}
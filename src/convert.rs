@@ -0,0 +1,117 @@
+//! Conversions of `Dynamic` values into Rust types, used to write values back into Rust objects
+
+use smartstring::alias::String;
+use crate::bson::Bson;
+use crate::{bson, Dynamic, Number};
+
+/// Rust types which can be built from a `Dynamic` value. Returns `None` if the value has another type,
+/// or if it doesn't fit, e.g. a number out of the range of an integer type.
+pub trait FromDynamic: Sized {
+    fn from_dynamic(value: &Dynamic) -> Option<Self>;
+}
+
+impl FromDynamic for Dynamic {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl FromDynamic for bool {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        match value {
+            Dynamic::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+}
+
+impl FromDynamic for Number {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        value.as_number().copied()
+    }
+}
+
+impl FromDynamic for String {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        match value {
+            Dynamic::String(string) => Some(String::clone(string)),
+            _ => None,
+        }
+    }
+}
+
+impl FromDynamic for std::string::String {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        match value {
+            Dynamic::String(string) => Some(string.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Null is converted into `None`
+impl<T: FromDynamic> FromDynamic for Option<T> {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        match value {
+            Dynamic::Null => Some(None),
+            value => T::from_dynamic(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromDynamic> FromDynamic for Vec<T> {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        value.as_dynamic_array()?.iter().map(|item| T::from_dynamic(&item)).collect()
+    }
+}
+
+macro_rules! int_from_dynamic {
+    ($($target:ty),*) => {
+        $(impl FromDynamic for $target {
+            fn from_dynamic(value: &Dynamic) -> Option<Self> {
+                match value {
+                    Dynamic::Number(Number::Int(int)) => <$target>::try_from(*int).ok(),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+
+int_from_dynamic!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+macro_rules! float_from_dynamic {
+    ($($target:ty),*) => {
+        $(impl FromDynamic for $target {
+            fn from_dynamic(value: &Dynamic) -> Option<Self> {
+                value.as_number().map(|number| number.as_f64() as $target)
+            }
+        })*
+    };
+}
+
+float_from_dynamic!(f32, f64);
+
+impl FromDynamic for Bson {
+    fn from_dynamic(value: &Dynamic) -> Option<Self> {
+        match value {
+            Dynamic::Bson(bson) => Some(bson.clone()),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! bson_from_dynamic {
+    ($($variant:ident),*) => {
+        $(impl FromDynamic for bson::$variant {
+            fn from_dynamic(value: &Dynamic) -> Option<Self> {
+                match value {
+                    Dynamic::Bson(Bson::$variant(value)) => Some(value.clone()),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+
+bson_from_dynamic!(ObjectId, DateTime, Binary, Timestamp, Decimal128);
@@ -0,0 +1,66 @@
+//! Support for `#[derive(DynamicObject)]`, which is re-exported at the root of the crate
+
+use crate::{AsDynamicRef, Dynamic, DynamicArray, DynamicError, DynamicRef, FromDynamic};
+
+/// Names of the fields of a derived struct, which are known without an instance of it.
+/// Used to list the fields of the structs flattened into other objects.
+pub trait FieldNames {
+    fn field_names() -> Vec<&'static str>;
+}
+
+/// Borrowed field of a derived object. Its view borrows the field when the type of the field
+/// implements `AsDynamicRef` and otherwise owns a conversion of the field with `Into<Dynamic>`,
+/// as `(&&FieldRef(field)).to_dynamic_ref()` resolves to `BorrowedField` first.
+pub struct FieldRef<'a, T>(pub &'a T);
+
+pub trait BorrowedField<'a> {
+    fn to_dynamic_ref(&self) -> DynamicRef<'a>;
+}
+
+impl<'a, T: AsDynamicRef> BorrowedField<'a> for &FieldRef<'a, T> {
+    fn to_dynamic_ref(&self) -> DynamicRef<'a> {
+        self.0.as_dynamic_ref()
+    }
+}
+
+pub trait OwnedField<'a> {
+    fn to_dynamic_ref(&self) -> DynamicRef<'a>;
+}
+
+impl<'a, T: Clone + Into<Dynamic>> OwnedField<'a> for FieldRef<'a, T> {
+    fn to_dynamic_ref(&self) -> DynamicRef<'a> {
+        DynamicRef::Owned(self.0.clone().into())
+    }
+}
+
+/// Borrowed vector field of a derived object, which is viewed as a dynamic array when its items
+/// can be viewed and is otherwise converted into an array by the function
+pub struct ArrayFieldRef<'a, T, F>(pub &'a Vec<T>, pub F);
+
+impl<'a, T, F> BorrowedField<'a> for &ArrayFieldRef<'a, T, F>
+where
+    Vec<T>: DynamicArray,
+{
+    fn to_dynamic_ref(&self) -> DynamicRef<'a> {
+        DynamicRef::Array(self.0)
+    }
+}
+
+impl<'a, T, F: Fn() -> Dynamic> OwnedField<'a> for ArrayFieldRef<'a, T, F> {
+    fn to_dynamic_ref(&self) -> DynamicRef<'a> {
+        DynamicRef::Owned((self.1)())
+    }
+}
+
+/// Converts the value of a field of a derived object, reporting values of another type as `DynamicError::TypeMismatch`
+pub fn field_from_dynamic<T: FromDynamic>(field: &str, value: &Dynamic) -> Result<T, DynamicError> {
+    T::from_dynamic(value).ok_or_else(|| DynamicError::TypeMismatch {
+        field: field.into(),
+        expected: std::any::type_name::<T>(),
+    })
+}
+
+/// Converts the field of an object into the type of the field of a derived object. Missing fields are null.
+pub fn field_from_object<T: FromDynamic>(object: &Dynamic, field: &str) -> Option<T> {
+    T::from_dynamic(&object.get_object_field(field).unwrap_or(Dynamic::Null))
+}
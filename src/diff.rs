@@ -0,0 +1,275 @@
+use std::collections::{HashMap, VecDeque};
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::ord::OrdDynamic;
+use crate::query::ast::{ArrayIndex, Field, InnerField, MemberAccess, Variable, VariablePath};
+use crate::Dynamic;
+
+/// Difference between two values. The path is `None` when the compared values themselves differ.
+///
+/// Items of arrays are addressed at the index where the change applies when the changes are applied in order:
+/// removed items are listed first, from the last one, at their index in the old array, and added and changed
+/// items follow in the order of the new array, at their index in it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added { path: Option<VariablePath>, value: Dynamic },
+    Removed { path: Option<VariablePath>, value: Dynamic },
+    Changed { path: Option<VariablePath>, from: Dynamic, to: Dynamic },
+    /// Change of an item of the compared arrays, whose path is relative to the item
+    Item { index: usize, change: Box<Change> },
+}
+
+impl Change {
+    /// Returns the path as written in the results of `$diff`, e.g. `items[0].qty`, or `[0].qty` for a change
+    /// of an item of the compared arrays. Returns `None` when the compared values themselves differ.
+    pub fn path_string(&self) -> Option<std::string::String> {
+        match self {
+            Change::Added { path, .. } | Change::Removed { path, .. } | Change::Changed { path, .. } => {
+                path.as_ref().map(VariablePath::to_string)
+            }
+            Change::Item { index, change } => Some(match change.path_string() {
+                Some(path) if path.starts_with('[') => format!("[{}]{}", index, path),
+                Some(path) => format!("[{}].{}", index, path),
+                None => format!("[{}]", index),
+            }),
+        }
+    }
+}
+
+/// How items of arrays are matched with each other
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ArrayDiff {
+    /// Items at the same index are compared
+    #[default]
+    Index,
+    /// Items which are not part of the longest common subsequence are removed or added
+    Lcs,
+    /// Items with the same value of the key field are compared. Items which moved before other matched items
+    /// are removed and added again.
+    Key(Field),
+}
+
+impl Dynamic {
+    /// Lists the paths which were added, removed or changed in the other value, comparing arrays by index
+    pub fn diff(&self, other: &Dynamic) -> Vec<Change> {
+        self.diff_with(other, &ArrayDiff::Index)
+    }
+
+    pub fn diff_with(&self, other: &Dynamic, arrays: &ArrayDiff) -> Vec<Change> {
+        let mut changes = Vec::new();
+        diff(self, other, None, arrays, &mut changes);
+
+        changes
+    }
+}
+
+fn diff(from: &Dynamic, to: &Dynamic, path: Option<&VariablePath>, arrays: &ArrayDiff, changes: &mut Vec<Change>) {
+    match (from, to) {
+        (Dynamic::Object(from_object), Dynamic::Object(to_object)) => {
+            let from_map = from_object.to_map();
+            let to_map = to_object.to_map();
+            for (key, value) in from_map.iter().filter(|(key, _)| !to_map.contains_key(*key)) {
+                changes.push(Change::Removed { path: Some(member(path, key)), value: value.clone() });
+            }
+            for (key, value) in &to_map {
+                match from_map.get(key) {
+                    Some(from_value) => diff(from_value, value, Some(&member(path, key)), arrays, changes),
+                    None => changes.push(Change::Added { path: Some(member(path, key)), value: value.clone() }),
+                }
+            }
+        }
+        (Dynamic::Array(from_array), Dynamic::Array(to_array)) => {
+            diff_arrays(&from_array.items(), &to_array.items(), path, arrays, changes);
+        }
+        (from, to) if from == to => {}
+        (from, to) => changes.push(Change::Changed { path: path.cloned(), from: from.clone(), to: to.clone() }),
+    }
+}
+
+fn diff_arrays(from: &[Dynamic], to: &[Dynamic], path: Option<&VariablePath>, arrays: &ArrayDiff, changes: &mut Vec<Change>) {
+    let matched = match arrays {
+        ArrayDiff::Index => (0..from.len().min(to.len())).map(|index| (index, index)).collect(),
+        ArrayDiff::Lcs => longest_common_subsequence(from, to),
+        ArrayDiff::Key(key) => matching_keys(from, to, key),
+    };
+    let mut matched_from = vec![false; from.len()];
+    let mut matched_to = vec![None; to.len()];
+    for (from_index, to_index) in matched {
+        matched_from[from_index] = true;
+        matched_to[to_index] = Some(from_index);
+    }
+
+    for (index, value) in from.iter().enumerate().rev().filter(|(index, _)| !matched_from[*index]) {
+        item_changes(path, index, changes, |path, changes| {
+            changes.push(Change::Removed { path: path.cloned(), value: value.clone() })
+        });
+    }
+    for (index, (value, from_index)) in to.iter().zip(matched_to).enumerate() {
+        item_changes(path, index, changes, |path, changes| match from_index {
+            Some(from_index) => diff(&from[from_index], value, path, arrays, changes),
+            None => changes.push(Change::Added { path: path.cloned(), value: value.clone() }),
+        });
+    }
+}
+
+/// Records the changes of the item at the index. Items of the compared arrays have no path,
+/// so their changes are recorded relative to the item and wrapped in `Change::Item`.
+fn item_changes(
+    path: Option<&VariablePath>,
+    index: usize,
+    changes: &mut Vec<Change>,
+    record: impl FnOnce(Option<&VariablePath>, &mut Vec<Change>),
+) {
+    match path {
+        Some(path) => record(Some(&item(path, index)), changes),
+        None => {
+            let mut item_changes = Vec::new();
+            record(None, &mut item_changes);
+            changes.extend(item_changes.into_iter().map(|change| Change::Item { index, change: Box::new(change) }));
+        }
+    }
+}
+
+/// Returns the pairs of indices of equal items which form a longest common subsequence, in increasing order.
+/// Uses the greedy algorithm of Myers on identifiers of the items, which takes O((n + m) · d) time
+/// for d removed and added items.
+fn longest_common_subsequence(from: &[Dynamic], to: &[Dynamic]) -> Vec<(usize, usize)> {
+    let mut identifiers = HashMap::new();
+    let mut identify = |items: &[Dynamic]| {
+        items
+            .iter()
+            .map(|item| {
+                let next = identifiers.len();
+                *identifiers.entry(OrdDynamic(item.clone())).or_insert(next)
+            })
+            .collect::<Vec<_>>()
+    };
+    let (from, to) = (identify(from), identify(to));
+    let (length, other_length) = (from.len() as isize, to.len() as isize);
+    let max = from.len() + to.len();
+    let diagonal = |k: isize| (k + max as isize + 1) as usize;
+
+    // Furthest index in `from` reached on each diagonal k = x - y, before each number of differences
+    let mut furthest = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+    'search: for differences in 0..=max as isize {
+        trace.push(furthest.clone());
+        for k in (-differences..=differences).step_by(2) {
+            let mut x = if k == -differences || (k != differences && furthest[diagonal(k - 1)] < furthest[diagonal(k + 1)]) {
+                furthest[diagonal(k + 1)]
+            } else {
+                furthest[diagonal(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < length && y < other_length && from[x as usize] == to[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[diagonal(k)] = x;
+            if x >= length && y >= other_length {
+                break 'search;
+            }
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut x, mut y) = (length, other_length);
+    for (differences, furthest) in trace.iter().enumerate().rev() {
+        let differences = differences as isize;
+        let k = x - y;
+        let previous_k = if k == -differences || (k != differences && furthest[diagonal(k - 1)] < furthest[diagonal(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = furthest[diagonal(previous_k)];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    pairs.reverse();
+
+    pairs
+}
+
+/// Returns the pairs of indices of items with the same value of the key field, in increasing order.
+/// Every item of the first array is matched at most once, with the first item of the second array with its key.
+/// Pairs which would change the order of the matched items are dropped, keeping the most of them.
+fn matching_keys(from: &[Dynamic], to: &[Dynamic], key: &Field) -> Vec<(usize, usize)> {
+    let mut from_indices = HashMap::<OrdDynamic, VecDeque<usize>>::new();
+    for (index, item) in from.iter().enumerate() {
+        from_indices.entry(OrdDynamic(key.resolve(item))).or_default().push_back(index);
+    }
+    let pairs = to
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| Some((from_indices.get_mut(&OrdDynamic(key.resolve(item)))?.pop_front()?, index)))
+        .collect::<Vec<_>>();
+
+    // Longest subsequence of the pairs with increasing indices in the first array, by patience sorting
+    let mut tails = Vec::<usize>::new();
+    let mut previous = vec![None; pairs.len()];
+    for (position, &(from_index, _)) in pairs.iter().enumerate() {
+        let length = tails.partition_point(|&tail| pairs[tail].0 < from_index);
+        previous[position] = length.checked_sub(1).map(|length| tails[length]);
+        if length == tails.len() {
+            tails.push(position);
+        } else {
+            tails[length] = position;
+        }
+    }
+    let mut kept = Vec::with_capacity(tails.len());
+    let mut position = tails.last().copied();
+    while let Some(current) = position {
+        kept.push(pairs[current]);
+        position = previous[current];
+    }
+    kept.reverse();
+
+    kept
+}
+
+fn member(path: Option<&VariablePath>, key: &str) -> VariablePath {
+    match path {
+        Some(path) => VariablePath::InnerField {
+            base: Box::new(path.clone()),
+            field: InnerField::MemberAccess(MemberAccess { member: key.into() }),
+        },
+        None => VariablePath::BaseVariable(Variable::from(key)),
+    }
+}
+
+fn item(path: &VariablePath, index: usize) -> VariablePath {
+    VariablePath::InnerField { base: Box::new(path.clone()), field: InnerField::ArrayIndex(ArrayIndex { index }) }
+}
+
+impl From<&Change> for Dynamic {
+    /// `{ "op": "changed", "path": "a.b[0]", "from": 1, "to": 2 }`
+    fn from(change: &Change) -> Self {
+        let path = match change.path_string() {
+            Some(path) => Dynamic::from(String::from(path)),
+            None => Dynamic::Null,
+        };
+        let mut change = change;
+        while let Change::Item { change: item_change, .. } = change {
+            change = item_change;
+        }
+        let fields = match change {
+            Change::Added { value, .. } => vec![("op", Dynamic::from(String::from("added"))), ("path", path), ("value", value.clone())],
+            Change::Removed { value, .. } => vec![("op", Dynamic::from(String::from("removed"))), ("path", path), ("value", value.clone())],
+            Change::Changed { from, to, .. } => vec![("op", Dynamic::from(String::from("changed"))), ("path", path), ("from", from.clone()), ("to", to.clone())],
+            Change::Item { .. } => unreachable!("items are unwrapped"),
+        };
+
+        Dynamic::from(
+            fields
+                .into_iter()
+                .map(|(key, value)| (String::from(key), value))
+                .collect::<LinkedHashMap<_, _>>(),
+        )
+    }
+}
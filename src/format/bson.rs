@@ -0,0 +1,93 @@
+use ::bson::spec::BinarySubtype;
+use ::bson::Document;
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::bson::{Binary, Bson, DateTime, Decimal128, ObjectId, Timestamp};
+use crate::format::FormatError;
+use crate::{Dynamic, Number};
+
+/// Converts BSON values, keeping the types of `crate::bson`. Other types which Mongo deprecated or which
+/// have no equivalent, e.g. regular expressions or min and max keys, are objects of their relaxed Extended JSON.
+impl From<&::bson::Bson> for Dynamic {
+    fn from(value: &::bson::Bson) -> Self {
+        match value {
+            ::bson::Bson::Null | ::bson::Bson::Undefined => Dynamic::Null,
+            ::bson::Bson::Boolean(bool) => Dynamic::from(*bool),
+            ::bson::Bson::Int32(int) => Dynamic::from(*int),
+            ::bson::Bson::Int64(int) => Dynamic::from(*int),
+            ::bson::Bson::Double(float) => Dynamic::from(*float),
+            ::bson::Bson::String(string) => Dynamic::from(string.as_str()),
+            ::bson::Bson::Array(array) => Dynamic::from(array.iter().map(Dynamic::from).collect::<Vec<_>>()),
+            ::bson::Bson::Document(document) => Dynamic::from(document),
+            ::bson::Bson::ObjectId(id) => Dynamic::from(ObjectId(id.bytes())),
+            ::bson::Bson::DateTime(date) => Dynamic::from(DateTime(date.timestamp_millis())),
+            ::bson::Bson::Binary(binary) => Dynamic::from(Binary {
+                subtype: u8::from(binary.subtype),
+                bytes: binary.bytes.as_slice().into(),
+            }),
+            ::bson::Bson::Timestamp(timestamp) => Dynamic::from(Timestamp {
+                time: timestamp.time,
+                increment: timestamp.increment,
+            }),
+            ::bson::Bson::Decimal128(decimal) => Dynamic::from(Decimal128::from_bytes(decimal.bytes())),
+            value => Dynamic::from(&value.clone().into_relaxed_extjson()),
+        }
+    }
+}
+
+impl From<&Document> for Dynamic {
+    fn from(document: &Document) -> Self {
+        let map: LinkedHashMap<String, Dynamic> = document
+            .iter()
+            .map(|(key, value)| (String::from(key.as_str()), Dynamic::from(value)))
+            .collect();
+        Dynamic::from(map)
+    }
+}
+
+/// Integers are 32-bit if they fit, BSON types of `crate::bson` keep their type
+impl From<&Dynamic> for ::bson::Bson {
+    fn from(value: &Dynamic) -> Self {
+        match value {
+            Dynamic::Null => ::bson::Bson::Null,
+            Dynamic::Bool(bool) => ::bson::Bson::Boolean(*bool),
+            Dynamic::Number(Number::Int(int)) => i32::try_from(*int).map_or(::bson::Bson::Int64(*int), ::bson::Bson::Int32),
+            Dynamic::Number(Number::Float(float)) => ::bson::Bson::Double(*float),
+            Dynamic::String(string) => ::bson::Bson::String(string.to_string()),
+            Dynamic::Array(array) => ::bson::Bson::Array(array.iter().map(|item| ::bson::Bson::from(&item)).collect()),
+            Dynamic::Object(object) => ::bson::Bson::Document(
+                object.field_values().map(|(key, value)| (key.to_string(), ::bson::Bson::from(&value))).collect(),
+            ),
+            Dynamic::Bson(Bson::ObjectId(id)) => ::bson::Bson::ObjectId(::bson::oid::ObjectId::from_bytes(id.0)),
+            Dynamic::Bson(Bson::DateTime(date)) => ::bson::Bson::DateTime(::bson::DateTime::from_millis(date.0)),
+            Dynamic::Bson(Bson::Binary(binary)) => ::bson::Bson::Binary(::bson::Binary {
+                subtype: BinarySubtype::from(binary.subtype),
+                bytes: binary.bytes.to_vec(),
+            }),
+            Dynamic::Bson(Bson::Timestamp(timestamp)) => ::bson::Bson::Timestamp(::bson::Timestamp {
+                time: timestamp.time,
+                increment: timestamp.increment,
+            }),
+            Dynamic::Bson(Bson::Decimal128(decimal)) => {
+                ::bson::Bson::Decimal128(::bson::Decimal128::from_bytes(decimal.to_bytes()))
+            }
+        }
+    }
+}
+
+impl Dynamic {
+    /// Decodes a BSON document
+    pub fn from_bson(mut bytes: &[u8]) -> Result<Dynamic, FormatError> {
+        Ok(Dynamic::from(&Document::from_reader(&mut bytes)?))
+    }
+
+    /// Encodes the value as a BSON document, so it must be an object
+    pub fn to_bson(&self) -> Result<Vec<u8>, FormatError> {
+        let ::bson::Bson::Document(document) = ::bson::Bson::from(self) else {
+            return Err(FormatError::NotADocument);
+        };
+        let mut bytes = Vec::new();
+        document.to_writer(&mut bytes)?;
+        Ok(bytes)
+    }
+}
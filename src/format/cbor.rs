@@ -0,0 +1,15 @@
+use crate::format::FormatError;
+use crate::Dynamic;
+
+impl Dynamic {
+    /// Decodes a CBOR value. Byte strings are arrays of integers.
+    pub fn from_cbor(bytes: &[u8]) -> Result<Dynamic, FormatError> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>, FormatError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes)?;
+        Ok(bytes)
+    }
+}
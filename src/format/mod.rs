@@ -0,0 +1,55 @@
+//! Conversions between `Dynamic` and other data formats, each behind the cargo feature of the same name.
+//! The formats are built on the `Serialize` and `Deserialize` implementations of the `serde` feature,
+//! except BSON which is converted directly to keep the types of `crate::bson`.
+
+#[cfg(feature = "bson")]
+mod bson;
+#[cfg(feature = "cbor")]
+mod cbor;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+#[cfg(feature = "serde")]
+mod serde;
+#[cfg(feature = "toml")]
+mod toml;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+use derive_more::From;
+use crate::query::ParseError;
+
+#[derive(Debug, From)]
+pub enum FormatError {
+    #[cfg(feature = "yaml")]
+    Yaml(serde_yaml::Error),
+    #[cfg(feature = "toml")]
+    TomlDeserialize(::toml::de::Error),
+    #[cfg(feature = "toml")]
+    TomlSerialize(::toml::ser::Error),
+    #[cfg(feature = "cbor")]
+    CborDeserialize(ciborium::de::Error<std::io::Error>),
+    #[cfg(feature = "cbor")]
+    CborSerialize(ciborium::ser::Error<std::io::Error>),
+    #[cfg(feature = "msgpack")]
+    MessagePackDeserialize(rmp_serde::decode::Error),
+    #[cfg(feature = "msgpack")]
+    MessagePackSerialize(rmp_serde::encode::Error),
+    #[cfg(feature = "bson")]
+    BsonDeserialize(::bson::de::Error),
+    #[cfg(feature = "bson")]
+    BsonSerialize(::bson::ser::Error),
+    /// Only objects can be encoded as BSON documents
+    #[cfg(feature = "bson")]
+    NotADocument,
+    Json(serde_json::Error),
+    /// The document isn't a valid script
+    Script(ParseError),
+}
+
+/// Parses a script from a document of another format, which is written as the JSON text of the script
+#[cfg(any(feature = "yaml", feature = "toml"))]
+fn script_from(document: &crate::Dynamic) -> Result<crate::query::Script, FormatError> {
+    use std::str::FromStr;
+
+    Ok(crate::query::Script::from_str(&serde_json::to_string(document)?)?)
+}
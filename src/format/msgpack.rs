@@ -0,0 +1,14 @@
+use crate::format::FormatError;
+use crate::Dynamic;
+
+impl Dynamic {
+    /// Decodes a MessagePack value. Binary values are arrays of integers.
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Dynamic, FormatError> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+
+    /// Encodes the value as MessagePack, with objects as maps from field names to values
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, FormatError> {
+        Ok(rmp_serde::to_vec(self)?)
+    }
+}
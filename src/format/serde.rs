@@ -0,0 +1,160 @@
+use std::fmt;
+use ::serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use ::serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::{Dynamic, Number};
+
+/// Key which toml uses to deserialize datetimes as a map with a single field
+const TOML_DATETIME: &str = "$__toml_private_datetime";
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Number::Int(int) => serializer.serialize_i64(int),
+            Number::Float(float) => serializer.serialize_f64(float),
+        }
+    }
+}
+
+/// Serializes objects as maps, in the order of their fields, and BSON values as their Extended JSON
+impl Serialize for Dynamic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Dynamic::Null => serializer.serialize_unit(),
+            Dynamic::Bool(bool) => serializer.serialize_bool(*bool),
+            Dynamic::Number(number) => number.serialize(serializer),
+            Dynamic::String(string) => serializer.serialize_str(string),
+            Dynamic::Array(array) => {
+                let mut sequence = serializer.serialize_seq(Some(array.len()))?;
+                for item in array.iter() {
+                    sequence.serialize_element(&item)?;
+                }
+                sequence.end()
+            }
+            Dynamic::Object(object) => {
+                let mut map = serializer.serialize_map(None)?;
+                for (key, value) in object.field_values() {
+                    map.serialize_entry(key, &value)?;
+                }
+                map.end()
+            }
+            // In the relaxed Extended JSON form, e.g. `{"$oid": "..."}`
+            Dynamic::Bson(bson) => bson.to_extended_json().serialize(serializer),
+        }
+    }
+}
+
+/// Deserializes any self-describing value. Integers which don't fit in an `i64` are floats, bytes are arrays
+/// of integers and keys of maps which are numbers or booleans are converted into strings.
+impl<'de> Deserialize<'de> for Dynamic {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DynamicVisitor)
+    }
+}
+
+struct DynamicVisitor;
+
+impl<'de> Visitor<'de> for DynamicVisitor {
+    type Value = Dynamic;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Dynamic, E> {
+        Ok(Dynamic::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Dynamic, E> {
+        Ok(Dynamic::from(Number::Int(value)))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Dynamic, E> {
+        Ok(Dynamic::from(i64::try_from(value).map_or(Number::Float(value as f64), Number::Int)))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Dynamic, E> {
+        Ok(Dynamic::from(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Dynamic, E> {
+        Ok(Dynamic::from(value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Dynamic, E> {
+        Ok(Dynamic::from(value.iter().map(|&byte| Dynamic::from(byte)).collect::<Vec<_>>()))
+    }
+
+    fn visit_none<E>(self) -> Result<Dynamic, E> {
+        Ok(Dynamic::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Dynamic, D::Error> {
+        Dynamic::deserialize(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> Result<Dynamic, E> {
+        Ok(Dynamic::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut sequence: A) -> Result<Dynamic, A::Error> {
+        let mut items = Vec::with_capacity(sequence.size_hint().unwrap_or_default());
+        while let Some(item) = sequence.next_element()? {
+            items.push(item);
+        }
+        Ok(Dynamic::from(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Dynamic, A::Error> {
+        let mut fields = LinkedHashMap::<String, Dynamic>::new();
+        while let Some(MapKey(key)) = map.next_key()? {
+            let value = map.next_value()?;
+            // Datetimes of toml are strings
+            if key == TOML_DATETIME && fields.is_empty() {
+                return Ok(value);
+            }
+            fields.replace(key, value);
+        }
+        Ok(Dynamic::from(fields))
+    }
+}
+
+/// Key of a map, converted into a string
+struct MapKey(String);
+
+impl<'de> Deserialize<'de> for MapKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MapKeyVisitor)
+    }
+}
+
+struct MapKeyVisitor;
+
+impl Visitor<'_> for MapKeyVisitor {
+    type Value = MapKey;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string, number or boolean key")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<MapKey, E> {
+        Ok(MapKey(value.to_string().into()))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<MapKey, E> {
+        Ok(MapKey(value.to_string().into()))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<MapKey, E> {
+        Ok(MapKey(value.to_string().into()))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<MapKey, E> {
+        Ok(MapKey(value.to_string().into()))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<MapKey, E> {
+        Ok(MapKey(value.into()))
+    }
+}
@@ -0,0 +1,22 @@
+use crate::format::{script_from, FormatError};
+use crate::query::Script;
+use crate::Dynamic;
+
+impl Dynamic {
+    /// Parses a TOML document, whose datetimes are strings
+    pub fn from_toml(text: &str) -> Result<Dynamic, FormatError> {
+        Ok(::toml::from_str(text)?)
+    }
+
+    /// Writes the value as a TOML document. Only objects without nulls can be written.
+    pub fn to_toml(&self) -> Result<std::string::String, FormatError> {
+        Ok(::toml::to_string(self)?)
+    }
+}
+
+impl Script {
+    /// Parses a script written as a TOML document
+    pub fn from_toml(text: &str) -> Result<Script, FormatError> {
+        script_from(&Dynamic::from_toml(text)?)
+    }
+}
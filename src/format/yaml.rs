@@ -0,0 +1,20 @@
+use crate::format::{script_from, FormatError};
+use crate::query::Script;
+use crate::Dynamic;
+
+impl Dynamic {
+    pub fn from_yaml(text: &str) -> Result<Dynamic, FormatError> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<std::string::String, FormatError> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+}
+
+impl Script {
+    /// Parses a script written in YAML, e.g. `$match:` followed by the fields of the operator
+    pub fn from_yaml(text: &str) -> Result<Script, FormatError> {
+        script_from(&Dynamic::from_yaml(text)?)
+    }
+}
@@ -0,0 +1,122 @@
+//! Dynamic objects and arrays which read a shared `serde_json` document in place,
+//! so it can be queried without converting the whole document into `Dynamic` values first
+
+use std::borrow::Cow;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use serde_json::Value;
+use crate::{Array, AsDynamicObjectMut, Dynamic, DynamicArray, DynamicObject, DynamicRef, Number, Object};
+
+/// Object or array of a shared JSON document. The node keeps the document alive and points to the value
+/// inside it, so reading a child doesn't walk the path from the root of the document again.
+#[derive(Clone)]
+struct Node {
+    document: Arc<Value>,
+    value: NonNull<Value>,
+}
+
+// The value is only read and is owned by the document, which is shared and never modified
+unsafe impl Send for Node {}
+unsafe impl Sync for Node {}
+
+impl Node {
+    fn new(document: Arc<Value>) -> Node {
+        let value = NonNull::from(document.as_ref());
+        Node { document, value }
+    }
+
+    fn value(&self) -> &Value {
+        // SAFETY: the value is part of the document, which the node keeps alive and which can't be modified
+        // while it is shared
+        unsafe { self.value.as_ref() }
+    }
+
+    fn child(&self, value: &Value) -> Node {
+        Node { document: self.document.clone(), value: NonNull::from(value) }
+    }
+
+    /// Converts a child of the node. Objects and arrays are exposed as nodes, only scalars are copied.
+    fn to_dynamic(&self, value: &Value) -> Dynamic {
+        match value {
+            Value::Object(_) => Dynamic::Object(Object::DynamicObject(Arc::new(JsonObject(self.child(value))))),
+            Value::Array(_) => Dynamic::Array(Array::DynamicArray(Arc::new(JsonArray(self.child(value))))),
+            value => Dynamic::from(value),
+        }
+    }
+
+    /// Returns a view of a child of the node, which borrows strings from the document
+    fn to_dynamic_ref<'a>(&self, value: &'a Value) -> DynamicRef<'a> {
+        match value {
+            Value::Null => DynamicRef::Null,
+            Value::Bool(bool) => DynamicRef::Bool(*bool),
+            Value::Number(number) => DynamicRef::Number(Number::from(number)),
+            Value::String(string) => DynamicRef::String(string),
+            value => DynamicRef::Owned(self.to_dynamic(value)),
+        }
+    }
+}
+
+struct JsonObject(Node);
+
+impl AsDynamicObjectMut for JsonObject {}
+
+impl DynamicObject for JsonObject {
+    fn get_field(&self, field: &str) -> Option<Dynamic> {
+        let value = self.0.value().as_object()?.get(field)?;
+        Some(self.0.to_dynamic(value))
+    }
+
+    fn fields(&self) -> Cow<'_, [&str]> {
+        let fields = self.0.value().as_object().into_iter().flat_map(|object| object.keys());
+        Cow::Owned(fields.map(std::string::String::as_str).collect())
+    }
+
+    fn get_field_ref(&self, field: &str) -> Option<DynamicRef<'_>> {
+        let value = self.0.value().as_object()?.get(field)?;
+        Some(self.0.to_dynamic_ref(value))
+    }
+}
+
+struct JsonArray(Node);
+
+impl JsonArray {
+    fn items(&self) -> &[Value] {
+        self.0.value().as_array().map_or(&[], Vec::as_slice)
+    }
+}
+
+impl DynamicArray for JsonArray {
+    fn len(&self) -> usize {
+        self.items().len()
+    }
+
+    fn get(&self, index: usize) -> Option<Dynamic> {
+        let value = self.items().get(index)?;
+        Some(self.0.to_dynamic(value))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Dynamic> + '_> {
+        Box::new(self.items().iter().map(|value| self.0.to_dynamic(value)))
+    }
+
+    fn get_ref(&self, index: usize) -> Option<DynamicRef<'_>> {
+        let value = self.items().get(index)?;
+        Some(self.0.to_dynamic_ref(value))
+    }
+}
+
+impl Dynamic {
+    /// Exposes the shared document without copying it. Its objects and arrays are read in place
+    /// and can't be modified, use `From<&Value>` to convert the document into modifiable values.
+    /// The document is only read in place if it is moved in or already shared as an `Arc<Value>`:
+    /// the values which borrow a `&Value` would be bound to its lifetime, so it has to be cloned first.
+    /// Keep the `Arc` to query the same document repeatedly without copying it.
+    pub fn from_json_shared(document: impl Into<Arc<Value>>) -> Dynamic {
+        let node = Node::new(document.into());
+        match node.value() {
+            Value::Object(_) => Dynamic::Object(Object::DynamicObject(Arc::new(JsonObject(node)))),
+            Value::Array(_) => Dynamic::Array(Array::DynamicArray(Arc::new(JsonArray(node)))),
+            value => Dynamic::from(value),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+pub mod parser;
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+use nom::Finish;
+use regex::Regex;
+use smartstring::alias::String;
+use crate::json_path::parser::parse_json_path;
+use crate::patch::json_eq;
+use crate::pointer::JsonPointer;
+use crate::query::ParseError;
+use crate::{Dynamic, Number};
+
+/// JSONPath query (RFC 9535), e.g. `$.store.book[?@.price < 10].title`
+#[derive(Debug, Clone)]
+pub struct JsonPath(pub Vec<Segment>);
+
+#[derive(Debug, Clone)]
+pub enum Segment {
+    /// `.name`, `.*` or `[selectors]`
+    Child(Vec<Selector>),
+    /// `..name`, `..*` or `..[selectors]`
+    Descendant(Vec<Selector>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Selector {
+    Name(String),
+    Wildcard,
+    Index(i64),
+    Slice { start: Option<i64>, end: Option<i64>, step: Option<i64> },
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Or(Vec<Filter>),
+    And(Vec<Filter>),
+    Not(Box<Filter>),
+    Comparison(Comparable, ComparisonOperator, Comparable),
+    /// Existence of nodes selected by a query, or the result of a function
+    Test(Comparable),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComparisonOperator {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+#[derive(Debug, Clone)]
+pub enum Comparable {
+    Literal(Dynamic),
+    Query(Query),
+    Function(Function),
+}
+
+/// Query inside of a filter, relative to the current node (`@`) or to the root (`$`)
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub relative: bool,
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Function {
+    Length(Box<Comparable>),
+    Count(Box<Comparable>),
+    /// Whether the whole string matches, the regex is anchored when parsed
+    Match(Box<Comparable>, Regex),
+    Search(Box<Comparable>, Regex),
+    Value(Box<Comparable>),
+}
+
+/// Result of an expression inside of a filter
+enum FilterValue {
+    Nodes(Vec<Dynamic>),
+    Value(Option<Dynamic>),
+    Logical(bool),
+}
+
+impl FilterValue {
+    /// Converts a singular query result to a value, other node lists have no value
+    fn into_value(self) -> Option<Dynamic> {
+        match self {
+            FilterValue::Nodes(mut nodes) if nodes.len() == 1 => nodes.pop(),
+            FilterValue::Nodes(_) | FilterValue::Logical(_) => None,
+            FilterValue::Value(value) => value,
+        }
+    }
+
+    fn into_logical(self) -> bool {
+        match self {
+            FilterValue::Nodes(nodes) => !nodes.is_empty(),
+            FilterValue::Value(value) => value.is_some(),
+            FilterValue::Logical(logical) => logical,
+        }
+    }
+}
+
+impl JsonPath {
+    /// Returns the nodes selected by the query in document order
+    pub fn query(&self, root: &Dynamic) -> Vec<Dynamic> {
+        select(&self.0, root, vec![root.clone()])
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = ParseError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        parse_json_path(string)
+            .map_err(|x| x.to_owned())
+            .finish()
+            .map(|(_, x)| x)
+    }
+}
+
+impl Dynamic {
+    /// Resolves a JSON Pointer (RFC 6901) such as `/a/b/0`, returning `None` for invalid pointers
+    pub fn pointer(&self, pointer: &str) -> Option<Dynamic> {
+        JsonPointer::from_str(pointer).ok()?.resolve(self)
+    }
+
+    pub fn json_path(&self, path: &JsonPath) -> Vec<Dynamic> {
+        path.query(self)
+    }
+}
+
+fn select(segments: &[Segment], root: &Dynamic, mut nodes: Vec<Dynamic>) -> Vec<Dynamic> {
+    for segment in segments {
+        let mut selected = Vec::new();
+        for node in &nodes {
+            match segment {
+                Segment::Child(selectors) => {
+                    for selector in selectors {
+                        selector.select(root, node, &mut selected);
+                    }
+                }
+                Segment::Descendant(selectors) => {
+                    let mut descendants = Vec::new();
+                    descendants_of(node, &mut descendants);
+                    for descendant in &descendants {
+                        for selector in selectors {
+                            selector.select(root, descendant, &mut selected);
+                        }
+                    }
+                }
+            }
+        }
+        nodes = selected;
+    }
+
+    nodes
+}
+
+/// Collects the node and all nodes nested in it in document order
+pub(crate) fn descendants_of(node: &Dynamic, descendants: &mut Vec<Dynamic>) {
+    descendants.push(node.clone());
+    for child in children(node) {
+        descendants_of(&child, descendants);
+    }
+}
+
+fn children(node: &Dynamic) -> Vec<Dynamic> {
+    match node {
+        Dynamic::Array(array) => array.to_vec(),
+        Dynamic::Object(object) => object.to_map().into_iter().map(|(_, value)| value).collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl Selector {
+    fn select(&self, root: &Dynamic, node: &Dynamic, selected: &mut Vec<Dynamic>) {
+        match self {
+            Selector::Name(name) => selected.extend(node.as_object().and_then(|_| node.get_object_field(name))),
+            Selector::Wildcard => selected.extend(children(node)),
+            Selector::Index(index) => {
+                let Dynamic::Array(array) = node else { return };
+                let index = if *index < 0 { array.len() as i64 + index } else { *index };
+                if index >= 0 {
+                    selected.extend(array.get(index as usize));
+                }
+            }
+            Selector::Slice { start, end, step } => {
+                let Dynamic::Array(array) = node else { return };
+                let items = array.items();
+                selected.extend(slice(&items, *start, *end, step.unwrap_or(1)).map(|index| items[index].clone()));
+            }
+            Selector::Filter(filter) => {
+                for child in children(node) {
+                    if filter.test(root, &child) {
+                        selected.push(child);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the indices selected by a slice, following the normalization rules of RFC 9535
+fn slice(array: &[Dynamic], start: Option<i64>, end: Option<i64>, step: i64) -> Box<dyn Iterator<Item = usize>> {
+    let length = array.len() as i64;
+    let normalize = |index: i64| if index >= 0 { index } else { length + index };
+    if step > 0 {
+        let lower = normalize(start.unwrap_or(0)).clamp(0, length);
+        let upper = normalize(end.unwrap_or(length)).clamp(0, length);
+        Box::new((lower..upper).step_by(step as usize).map(|index| index as usize))
+    } else if step < 0 {
+        let upper = normalize(start.unwrap_or(length - 1)).clamp(-1, length - 1);
+        let lower = end.map_or(-1, |end| normalize(end).clamp(-1, length - 1));
+        Box::new(((lower + 1)..=upper).rev().step_by(step.unsigned_abs() as usize).map(|index| index as usize))
+    } else {
+        Box::new(std::iter::empty())
+    }
+}
+
+impl Filter {
+    fn test(&self, root: &Dynamic, current: &Dynamic) -> bool {
+        match self {
+            Filter::Or(filters) => filters.iter().any(|filter| filter.test(root, current)),
+            Filter::And(filters) => filters.iter().all(|filter| filter.test(root, current)),
+            Filter::Not(filter) => !filter.test(root, current),
+            Filter::Comparison(left, operator, right) => {
+                let left = left.eval(root, current).into_value();
+                let right = right.eval(root, current).into_value();
+                compare(left.as_ref(), *operator, right.as_ref())
+            }
+            Filter::Test(comparable) => comparable.eval(root, current).into_logical(),
+        }
+    }
+}
+
+fn compare(left: Option<&Dynamic>, operator: ComparisonOperator, right: Option<&Dynamic>) -> bool {
+    let equal = match (left, right) {
+        (None, None) => true,
+        (Some(left), Some(right)) => json_eq(left, right),
+        _ => false,
+    };
+    let ordering = match (left, right) {
+        (Some(Dynamic::Number(left)), Some(Dynamic::Number(right))) => left.partial_cmp(right),
+        (Some(Dynamic::String(left)), Some(Dynamic::String(right))) => left.partial_cmp(right),
+        _ => None,
+    };
+    match operator {
+        ComparisonOperator::Eq => equal,
+        ComparisonOperator::Ne => !equal,
+        ComparisonOperator::Lt => ordering == Some(Ordering::Less),
+        ComparisonOperator::Lte => ordering == Some(Ordering::Less) || equal,
+        ComparisonOperator::Gt => ordering == Some(Ordering::Greater),
+        ComparisonOperator::Gte => ordering == Some(Ordering::Greater) || equal,
+    }
+}
+
+impl Comparable {
+    fn eval(&self, root: &Dynamic, current: &Dynamic) -> FilterValue {
+        match self {
+            Comparable::Literal(value) => FilterValue::Value(Some(value.clone())),
+            Comparable::Query(query) => {
+                let start = if query.relative { current } else { root };
+                FilterValue::Nodes(select(&query.segments, root, vec![start.clone()]))
+            }
+            Comparable::Function(function) => function.eval(root, current),
+        }
+    }
+}
+
+impl Function {
+    fn eval(&self, root: &Dynamic, current: &Dynamic) -> FilterValue {
+        match self {
+            Function::Length(argument) => {
+                let length = match argument.eval(root, current).into_value() {
+                    Some(Dynamic::String(string)) => Some(string.chars().count()),
+                    Some(Dynamic::Array(array)) => Some(array.len()),
+                    Some(Dynamic::Object(object)) => Some(object.to_map().len()),
+                    _ => None,
+                };
+                FilterValue::Value(length.map(|length| Dynamic::from(Number::Int(length as i64))))
+            }
+            Function::Count(argument) => {
+                let count = match argument.eval(root, current) {
+                    FilterValue::Nodes(nodes) => nodes.len(),
+                    _ => 0,
+                };
+                FilterValue::Value(Some(Dynamic::from(Number::Int(count as i64))))
+            }
+            Function::Match(argument, regex) | Function::Search(argument, regex) => {
+                let matched = match argument.eval(root, current).into_value() {
+                    Some(Dynamic::String(string)) => regex.is_match(&string),
+                    _ => false,
+                };
+                FilterValue::Logical(matched)
+            }
+            Function::Value(argument) => FilterValue::Value(argument.eval(root, current).into_value()),
+        }
+    }
+}
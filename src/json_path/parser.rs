@@ -0,0 +1,230 @@
+use super::{Comparable, ComparisonOperator, Filter, Function, JsonPath, Query, Segment, Selector};
+use crate::query::parser::{number, string as double_quoted, ws};
+use crate::Dynamic;
+use nom::branch::alt;
+use nom::bytes::complete::{escaped_transform, is_not, tag};
+use nom::character::complete::{char, i64, satisfy};
+use nom::combinator::{all_consuming, cut, map, map_res, opt, recognize, value};
+use nom::multi::{many0, separated_list1};
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
+use nom::IResult;
+use regex::Regex;
+use smartstring::alias::String;
+
+pub fn parse_json_path(str: &str) -> IResult<&str, JsonPath> {
+    all_consuming(json_path)(str)
+}
+
+pub fn json_path(str: &str) -> IResult<&str, JsonPath> {
+    map(preceded(char('$'), many0(segment)), JsonPath)(str)
+}
+
+pub fn segment(str: &str) -> IResult<&str, Segment> {
+    alt((
+        map(
+            preceded(
+                tag(".."),
+                cut(alt((
+                    bracketed_selection,
+                    map(wildcard_selector, |selector| vec![selector]),
+                    map(member_name, |name| vec![Selector::Name(name)]),
+                ))),
+            ),
+            Segment::Descendant,
+        ),
+        map(
+            preceded(
+                char('.'),
+                cut(alt((
+                    map(wildcard_selector, |selector| vec![selector]),
+                    map(member_name, |name| vec![Selector::Name(name)]),
+                ))),
+            ),
+            Segment::Child,
+        ),
+        map(bracketed_selection, Segment::Child),
+    ))(str)
+}
+
+fn bracketed_selection(str: &str) -> IResult<&str, Vec<Selector>> {
+    delimited(
+        pair(char('['), blank),
+        separated_list1(delimited(blank, char(','), blank), selector),
+        cut(pair(blank, char(']'))),
+    )(str)
+}
+
+pub fn selector(str: &str) -> IResult<&str, Selector> {
+    alt((
+        map(quoted_string, Selector::Name),
+        wildcard_selector,
+        slice_selector,
+        map(i64, Selector::Index),
+        map(preceded(pair(char('?'), blank), cut(logical_expression)), Selector::Filter),
+    ))(str)
+}
+
+fn wildcard_selector(str: &str) -> IResult<&str, Selector> {
+    value(Selector::Wildcard, char('*'))(str)
+}
+
+fn slice_selector(str: &str) -> IResult<&str, Selector> {
+    map(
+        tuple((
+            terminated(opt(i64), delimited(blank, char(':'), blank)),
+            opt(i64),
+            opt(preceded(delimited(blank, char(':'), blank), opt(i64))),
+        )),
+        |(start, end, step)| Selector::Slice { start, end, step: step.flatten() },
+    )(str)
+}
+
+/// Member name shorthand, e.g. `.book`
+fn member_name(str: &str) -> IResult<&str, String> {
+    map(
+        recognize(pair(
+            satisfy(|char| char.is_alphabetic() || char == '_' || !char.is_ascii()),
+            many0(satisfy(|char| char.is_alphanumeric() || char == '_' || !char.is_ascii())),
+        )),
+        String::from,
+    )(str)
+}
+
+/// String literal in single or double quotes
+fn quoted_string(str: &str) -> IResult<&str, String> {
+    alt((
+        double_quoted,
+        delimited(
+            char('\''),
+            map(
+                opt(escaped_transform(
+                    is_not("\\'"),
+                    '\\',
+                    alt((
+                        value('\'', char('\'')),
+                        value('"', char('"')),
+                        value('\\', char('\\')),
+                        value('/', char('/')),
+                        value('\n', char('n')),
+                        value('\r', char('r')),
+                        value('\t', char('t')),
+                        value('\u{08}', char('b')),
+                        value('\u{0C}', char('f')),
+                    )),
+                )),
+                |string: Option<std::string::String>| String::from(string.unwrap_or_default()),
+            ),
+            cut(char('\'')),
+        ),
+    ))(str)
+}
+
+fn blank(str: &str) -> IResult<&str, ()> {
+    value((), many0(satisfy(|char| matches!(char, ' ' | '\t' | '\n' | '\r'))))(str)
+}
+
+pub fn logical_expression(str: &str) -> IResult<&str, Filter> {
+    map(
+        separated_list1(delimited(blank, tag("||"), blank), logical_and),
+        |mut filters| if filters.len() == 1 { filters.remove(0) } else { Filter::Or(filters) },
+    )(str)
+}
+
+fn logical_and(str: &str) -> IResult<&str, Filter> {
+    map(
+        separated_list1(delimited(blank, tag("&&"), blank), basic_expression),
+        |mut filters| if filters.len() == 1 { filters.remove(0) } else { Filter::And(filters) },
+    )(str)
+}
+
+fn basic_expression(str: &str) -> IResult<&str, Filter> {
+    alt((
+        map(
+            preceded(pair(char('!'), blank), alt((parenthesized, map(test_expression, Filter::Test)))),
+            |filter| Filter::Not(Box::new(filter)),
+        ),
+        parenthesized,
+        map(
+            tuple((comparable, delimited(blank, comparison_operator, blank), cut(comparable))),
+            |(left, operator, right)| Filter::Comparison(left, operator, right),
+        ),
+        map(test_expression, Filter::Test),
+    ))(str)
+}
+
+fn parenthesized(str: &str) -> IResult<&str, Filter> {
+    delimited(pair(char('('), blank), logical_expression, cut(pair(blank, char(')'))))(str)
+}
+
+fn test_expression(str: &str) -> IResult<&str, Comparable> {
+    alt((map(filter_query, Comparable::Query), map(function, Comparable::Function)))(str)
+}
+
+fn comparison_operator(str: &str) -> IResult<&str, ComparisonOperator> {
+    alt((
+        value(ComparisonOperator::Eq, tag("==")),
+        value(ComparisonOperator::Ne, tag("!=")),
+        value(ComparisonOperator::Lte, tag("<=")),
+        value(ComparisonOperator::Gte, tag(">=")),
+        value(ComparisonOperator::Lt, tag("<")),
+        value(ComparisonOperator::Gt, tag(">")),
+    ))(str)
+}
+
+fn comparable(str: &str) -> IResult<&str, Comparable> {
+    alt((
+        map(literal, Comparable::Literal),
+        map(filter_query, Comparable::Query),
+        map(function, Comparable::Function),
+    ))(str)
+}
+
+fn literal(str: &str) -> IResult<&str, Dynamic> {
+    alt((
+        map(number, Dynamic::from),
+        map(quoted_string, Dynamic::from),
+        value(Dynamic::Bool(true), tag("true")),
+        value(Dynamic::Bool(false), tag("false")),
+        value(Dynamic::Null, tag("null")),
+    ))(str)
+}
+
+fn filter_query(str: &str) -> IResult<&str, Query> {
+    alt((
+        map(preceded(char('@'), many0(segment)), |segments| Query { relative: true, segments }),
+        map(preceded(char('$'), many0(segment)), |segments| Query { relative: false, segments }),
+    ))(str)
+}
+
+fn function(str: &str) -> IResult<&str, Function> {
+    let argument = || map(comparable, Box::new);
+    let regex = |anchored: bool| {
+        map_res(quoted_string, move |pattern| match anchored {
+            true => Regex::new(&format!("^(?:{pattern})$")),
+            false => Regex::new(&pattern),
+        })
+    };
+    alt((
+        map(function_call("length", argument()), Function::Length),
+        map(function_call("count", argument()), Function::Count),
+        map(
+            function_call("match", separated_pair(argument(), delimited(blank, char(','), blank), regex(true))),
+            |(argument, regex)| Function::Match(argument, regex),
+        ),
+        map(
+            function_call("search", separated_pair(argument(), delimited(blank, char(','), blank), regex(false))),
+            |(argument, regex)| Function::Search(argument, regex),
+        ),
+        map(function_call("value", argument()), Function::Value),
+    ))(str)
+}
+
+fn function_call<'a, O>(
+    name: &'a str,
+    arguments: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    preceded(
+        pair(tag(name), char('(')),
+        cut(terminated(ws(arguments), char(')'))),
+    )
+}
@@ -6,16 +6,35 @@ use derive_more::From;
 use serde_json::Value;
 use smallvec::SmallVec;
 use smartstring::alias::String;
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
-use std::ops::Deref;
-use std::sync::{Arc, RwLock};
+use std::ops::{Add, Deref, Mul, Range};
+use std::sync::Arc;
 use hashlink::LinkedHashMap;
+use crate::bson::Bson;
 
+extern crate self as query_lang;
+
+pub mod bson;
+pub mod convert;
+pub mod derive;
+pub mod diff;
+pub mod format;
+mod json;
+pub mod json_path;
+pub mod ord;
+pub mod patch;
+pub mod pointer;
 pub mod query;
+pub mod raw;
+pub mod view;
+
+pub use convert::FromDynamic;
+pub use query_lang_derive::DynamicObject;
+pub use view::{AsDynamicRef, DynamicRef};
 
 impl From<&Value> for Dynamic {
     fn from(value: &Value) -> Self {
@@ -28,11 +47,11 @@ impl From<&Value> for Dynamic {
                 Dynamic::from(array.iter().map(|x| Dynamic::from(x)).collect::<Vec<_>>())
             }
             Value::Object(object) => {
-                let map = object
+                let map: LinkedHashMap<String, Dynamic> = object
                     .iter()
                     .map(|(key, value)| (key.into(), Dynamic::from(value)))
                     .collect();
-                Dynamic::from(Object::Map(Arc::new(RwLock::new(map))))
+                Dynamic::from(Object::Map(Map::from(map)))
             }
         }
     }
@@ -42,9 +61,13 @@ impl From<&Value> for Dynamic {
 pub enum DynamicError {
     NotAnObject,
     NotAnArray,
+    NotANumber,
+    IndexOutOfBounds,
     ImmutableObject,
     UnableToWrite,
     UnableTORead,
+    /// The value can't be stored in the field of a Rust object
+    TypeMismatch { field: String, expected: &'static str },
 }
 
 #[derive(Clone, Copy, From)]
@@ -139,91 +162,252 @@ impl PartialOrd for Number {
     }
 }
 
+impl Add for Number {
+    type Output = Number;
+
+    fn add(self, other: Self) -> Self::Output {
+        match (self, other) {
+            (Number::Int(first), Number::Int(second)) => first
+                .checked_add(second)
+                .map(Number::Int)
+                .unwrap_or(Number::Float(first as f64 + second as f64)),
+            (first, second) => Number::Float(first.as_f64() + second.as_f64()),
+        }
+    }
+}
+
+impl Mul for Number {
+    type Output = Number;
+
+    fn mul(self, other: Self) -> Self::Output {
+        match (self, other) {
+            (Number::Int(first), Number::Int(second)) => first
+                .checked_mul(second)
+                .map(Number::Int)
+                .unwrap_or(Number::Float(first as f64 * second as f64)),
+            (first, second) => Number::Float(first.as_f64() * second.as_f64()),
+        }
+    }
+}
+
+/// Fields of an object, shared between clones until one of them is modified
+#[derive(Clone, Default)]
+pub struct Map {
+    fields: Arc<LinkedHashMap<String, Dynamic>>,
+    frozen: bool,
+}
+
+impl Map {
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Returns the fields for modification, copying them first if they are shared
+    fn make_mut(&mut self) -> Result<&mut LinkedHashMap<String, Dynamic>, DynamicError> {
+        if self.frozen {
+            return Err(DynamicError::ImmutableObject);
+        }
+        Ok(Arc::make_mut(&mut self.fields))
+    }
+}
+
+impl Deref for Map {
+    type Target = LinkedHashMap<String, Dynamic>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fields
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| other.get(key).is_some_and(|other_value| value.eq(other_value)))
+    }
+}
+
+impl From<LinkedHashMap<String, Dynamic>> for Map {
+    fn from(fields: LinkedHashMap<String, Dynamic>) -> Self {
+        Map { fields: Arc::new(fields), frozen: false }
+    }
+}
+
+/// Items of an array, shared between clones until one of them is modified
+#[derive(Clone, Default)]
+pub struct Items {
+    items: Arc<SmallVec<Dynamic, 10>>,
+    frozen: bool,
+}
+
+impl Items {
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Returns the items for modification, copying them first if they are shared
+    fn make_mut(&mut self) -> Result<&mut SmallVec<Dynamic, 10>, DynamicError> {
+        if self.frozen {
+            return Err(DynamicError::ImmutableObject);
+        }
+        Ok(Arc::make_mut(&mut self.items))
+    }
+}
+
+impl Deref for Items {
+    type Target = SmallVec<Dynamic, 10>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+
+impl From<SmallVec<Dynamic, 10>> for Items {
+    fn from(items: SmallVec<Dynamic, 10>) -> Self {
+        Items { items: Arc::new(items), frozen: false }
+    }
+}
+
 #[derive(Clone)]
-pub enum Object {
-    Map(Arc<RwLock<LinkedHashMap<String, Dynamic>>>),
-    DynamicObject(Arc<dyn DynamicObject>),
+pub enum Array {
+    Items(Items),
+    DynamicArray(Arc<dyn DynamicArray>),
 }
 
-impl PartialEq for Object {
+impl Array {
+    pub fn len(&self) -> usize {
+        match self {
+            Array::Items(items) => items.len(),
+            Array::DynamicArray(array) => array.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Dynamic> {
+        match self {
+            Array::Items(items) => items.get(index).cloned(),
+            Array::DynamicArray(array) => array.get(index),
+        }
+    }
+
+    /// Iterates over the items, getting the items of dynamic arrays one by one
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Dynamic> + '_> {
+        match self {
+            Array::Items(items) => Box::new(items.iter().cloned()),
+            Array::DynamicArray(array) => array.iter(),
+        }
+    }
+
+    /// Copies the items in the range, which must be within the array
+    pub fn slice(&self, range: Range<usize>) -> Vec<Dynamic> {
+        match self {
+            Array::Items(items) => items[range].to_vec(),
+            Array::DynamicArray(array) => range.filter_map(|index| array.get(index)).collect(),
+        }
+    }
+
+    /// Returns the items, which are collected for dynamic arrays
+    pub fn items(&self) -> Cow<'_, [Dynamic]> {
+        match self {
+            Array::Items(items) => Cow::Borrowed(items.as_slice()),
+            Array::DynamicArray(array) => Cow::Owned(array.iter().collect()),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<Dynamic> {
+        self.slice(0..self.len())
+    }
+
+    /// Copies the items into a new array
+    pub fn to_items(&self) -> SmallVec<Dynamic, 10> {
+        match self {
+            Array::Items(items) => items.deref().clone(),
+            Array::DynamicArray(array) => array.iter().collect(),
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        match self {
+            Array::Items(items) => items.frozen,
+            Array::DynamicArray(_) => true,
+        }
+    }
+
+    /// Returns the items for modification, copying them first if they are shared.
+    /// Dynamic arrays can't be modified.
+    fn make_mut(&mut self) -> Result<&mut SmallVec<Dynamic, 10>, DynamicError> {
+        match self {
+            Array::Items(items) => items.make_mut(),
+            Array::DynamicArray(_) => Err(DynamicError::ImmutableObject),
+        }
+    }
+}
+
+impl PartialEq for Array {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Object::Map(map), Object::Map(other_map)) => {
-                let Ok(map) = map.read() else { return false };
-                let Ok(other_map) = other_map.read() else {
-                    return false;
-                };
-                map.eq(&other_map)
-            }
-            (Object::DynamicObject(object), Object::DynamicObject(other_object)) => {
-                let object_fields = object.field_values();
-                let other_object_fields = other_object.field_values();
-                object_fields.eq(other_object_fields)
-            }
-            (Object::DynamicObject(dyn_object), Object::Map(map_object))
-            | (Object::Map(map_object), Object::DynamicObject(dyn_object)) => {
-                let Ok(map_object) = map_object.read() else {
-                    return false;
-                };
-                let dyn_object_fields = dyn_object.field_values();
-
-                map_object
-                    .iter()
-                    .eq_by(dyn_object_fields, |x, y| x.0.eq(y.0) && x.1.eq(&y.1))
-            }
+            (Array::Items(items), Array::Items(other_items)) => items.as_slice() == other_items.as_slice(),
+            _ => self.len() == other.len() && self.iter().eq(other.iter()),
         }
     }
 }
 
-impl PartialOrd for Object {
+impl PartialOrd for Array {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
-            (Object::Map(map), Object::Map(other_map)) => {
-                let Ok(map) = map.read() else { return None };
-                let Ok(other_map) = other_map.read() else {
-                    return None;
-                };
-                map.iter().partial_cmp(other_map.iter())
-            }
-            (Object::DynamicObject(object), Object::DynamicObject(other_object)) => {
-                let object_fields = object
-                    .fields()
-                    .iter()
-                    .map(|&key| other_object.get_field(key).map(|value| (key, value)))
-                    .flatten();
-                let other_object_fields = other_object
-                    .fields()
-                    .iter()
-                    .map(|&key| other_object.get_field(key).map(|value| (key, value)))
-                    .flatten();
-                object_fields.partial_cmp(other_object_fields)
-            }
-            (Object::DynamicObject(dyn_object), Object::Map(map_object)) => {
-                let Ok(map_object) = map_object.read() else {
-                    return None;
-                };
-                let dyn_object_fields = dyn_object.field_values();
-                dyn_object_fields.partial_cmp_by(map_object.iter(), |x, y| {
-                    (x.0, &x.1).partial_cmp(&(y.0, y.1))
-                })
-            }
-            (Object::Map(map_object), Object::DynamicObject(dyn_object)) => {
-                let Ok(map_object) = map_object.read() else {
-                    return None;
-                };
-                let dyn_object_fields = dyn_object.field_values();
-                map_object.iter()
-                    .partial_cmp_by(dyn_object_fields, |x, y| {
-                        (x.0.deref(), x.1).partial_cmp(&(y.0, &y.1))
-                    })
-            }
+            (Array::Items(items), Array::Items(other_items)) => items.as_slice().partial_cmp(other_items.as_slice()),
+            _ => self.iter().partial_cmp(other.iter()),
         }
     }
 }
 
+impl From<SmallVec<Dynamic, 10>> for Array {
+    fn from(items: SmallVec<Dynamic, 10>) -> Self {
+        Array::Items(Items::from(items))
+    }
+}
+
+impl<T> From<T> for Array
+where
+    T: DynamicArray + 'static,
+{
+    fn from(value: T) -> Self {
+        Array::DynamicArray(Arc::new(value))
+    }
+}
+
+#[derive(Clone)]
+pub enum Object {
+    Map(Map),
+    DynamicObject(Arc<dyn DynamicObject>),
+}
+
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_with(other, ObjectComparison::default())
+    }
+}
+
+impl PartialOrd for Object {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare_with(other, ObjectComparison::default())
+    }
+}
+
+/// How objects are compared, either field by field in their order as MongoDB does,
+/// or regardless of the order of their fields as JSON does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectComparison {
+    #[default]
+    Ordered,
+    Unordered,
+}
+
 impl From<LinkedHashMap<String, Dynamic>> for Object {
     fn from(value: LinkedHashMap<String, Dynamic>) -> Self {
-        Object::Map(Arc::new(RwLock::new(value)))
+        Object::Map(Map::from(value))
     }
 }
 
@@ -236,38 +420,96 @@ where
     }
 }
 impl Object {
+    /// Copies the fields of the object into a new map
+    pub fn to_map(&self) -> LinkedHashMap<String, Dynamic> {
+        match self {
+            Object::Map(map) => map.fields.deref().clone(),
+            Object::DynamicObject(object) => object
+                .field_values()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        }
+    }
+
+    /// Iterates over the fields of the object in their order
+    pub(crate) fn field_values(&self) -> Box<dyn Iterator<Item = (&str, Dynamic)> + '_> {
+        match self {
+            Object::Map(map) => Box::new(map.iter().map(|(key, value)| (key.as_str(), value.clone()))),
+            Object::DynamicObject(object) => Box::new(object.field_values()),
+        }
+    }
+
+    /// Returns the fields of the object in the order in which they are compared
+    pub(crate) fn comparison_fields(&self, comparison: ObjectComparison) -> Vec<(&str, Dynamic)> {
+        let mut fields = self.field_values().collect::<Vec<_>>();
+        if comparison == ObjectComparison::Unordered {
+            fields.sort_by_key(|(key, _)| *key);
+        }
+        fields
+    }
+
+    pub fn eq_with(&self, other: &Object, comparison: ObjectComparison) -> bool {
+        match comparison {
+            ObjectComparison::Ordered => self.field_values().eq_by(other.field_values(), |(key, value), (other_key, other_value)| {
+                key == other_key && value.eq_with(&other_value, comparison)
+            }),
+            ObjectComparison::Unordered => {
+                self.field_values().count() == other.field_values().count()
+                    && self.field_values().all(|(key, value)| {
+                        other.get(key).is_some_and(|other_value| value.eq_with(&other_value, comparison))
+                    })
+            }
+        }
+    }
+
+    pub fn compare_with(&self, other: &Object, comparison: ObjectComparison) -> Option<Ordering> {
+        self.comparison_fields(comparison).into_iter().partial_cmp_by(
+            other.comparison_fields(comparison),
+            |(key, value), (other_key, other_value)| match key.cmp(other_key) {
+                Ordering::Equal => value.compare_with(&other_value, comparison),
+                ordering => Some(ordering),
+            },
+        )
+    }
+
     fn get(&self, key: &str) -> Option<Dynamic> {
         match self {
-            Object::Map(map) => map.read().ok()?.get(key).cloned(),
+            Object::Map(map) => map.get(key).cloned(),
             Object::DynamicObject(object) => object.get_field(key.borrow()),
         }
     }
 
     fn set(
-        &self,
+        &mut self,
         key: impl Into<String>,
         value: impl Into<Dynamic>,
     ) -> Result<Option<Dynamic>, DynamicError> {
         match self {
-            Object::Map(map) => Ok(map
-                .write()
-                .map_err(|_| DynamicError::UnableToWrite)?
-                .insert(key.into(), value.into())),
-            Object::DynamicObject(_) => Err(DynamicError::ImmutableObject),
+            Object::Map(map) => Ok(map.make_mut()?.replace(key.into(), value.into())),
+            Object::DynamicObject(object) => object_mut(object)?.set_field(&key.into(), value.into()),
         }
     }
 
-    fn remove(&self, key: &str) -> Result<Option<Dynamic>, DynamicError> {
+    fn remove(&mut self, key: &str) -> Result<Option<Dynamic>, DynamicError> {
         match self {
-            Object::Map(map) => Ok(map
-                .write()
-                .map_err(|_| DynamicError::UnableToWrite)?
-                .remove(key)),
-            Object::DynamicObject(_) => Err(DynamicError::ImmutableObject),
+            Object::Map(map) => Ok(map.make_mut()?.remove(key)),
+            Object::DynamicObject(object) => object_mut(object)?.remove_field(key),
         }
     }
 }
 
+/// Returns the dynamic object for modification, copying it first if it is shared.
+/// Objects which don't implement `DynamicObjectMut` can't be modified.
+fn object_mut(object: &mut Arc<dyn DynamicObject>) -> Result<&mut dyn DynamicObjectMut, DynamicError> {
+    if Arc::get_mut(object).is_none() {
+        let copy = object.as_mutable().ok_or(DynamicError::ImmutableObject)?.clone_object();
+        *object = copy;
+    }
+    Arc::get_mut(object)
+        .and_then(|object| object.as_mutable_mut())
+        .ok_or(DynamicError::ImmutableObject)
+}
+
 
 
 #[derive(Clone)]
@@ -276,34 +518,61 @@ pub enum Dynamic {
     Bool(bool),
     Number(Number),
     String(Arc<String>),
-    Array(Arc<RwLock<SmallVec<Dynamic, 10>>>),
+    Array(Array),
     Object(Object),
+    Bson(Bson),
 }
 
 impl PartialEq for Dynamic {
     fn eq(&self, other: &Self) -> bool {
+        self.eq_with(other, ObjectComparison::default())
+    }
+}
+
+impl PartialOrd for Dynamic {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare_with(other, ObjectComparison::default())
+    }
+}
+
+impl Dynamic {
+    fn comparison_order(&self) -> u8 {
+        match self {
+            Dynamic::Null => 1,
+            Dynamic::Number(_) => 2,
+            Dynamic::String(_) => 3,
+            Dynamic::Object(_) => 4,
+            Dynamic::Array(_) => 5,
+            Dynamic::Bool(_) => 8,
+            Dynamic::Bson(bson) => bson.comparison_order(),
+        }
+    }
+    /// Compares values for equality, comparing objects as specified
+    pub fn eq_with(&self, other: &Dynamic, comparison: ObjectComparison) -> bool {
         match (self, other) {
             (Dynamic::Null, Dynamic::Null) => true,
             (Dynamic::Number(number), Dynamic::Number(other_number)) => number.eq(other_number),
             (Dynamic::Bool(bool), Dynamic::Bool(other_bool)) => bool.eq(other_bool),
             (Dynamic::String(string), Dynamic::String(other_string)) => string.eq(other_string),
+            (Dynamic::Array(Array::Items(items)), Dynamic::Array(Array::Items(other_items))) => {
+                items.iter().eq_by(other_items.iter(), |item, other_item| item.eq_with(other_item, comparison))
+            }
             (Dynamic::Array(array), Dynamic::Array(other_array)) => {
-                let Ok(array) = array.read() else {
-                    return false;
-                };
-                let Ok(other_array) = other_array.read() else {
-                    return false;
-                };
-                array.eq(other_array.deref())
-            }
-            (Dynamic::Object(object), Dynamic::Object(other_object)) => object.eq(other_object),
+                array.len() == other_array.len()
+                    && array.iter().eq_by(other_array.iter(), |item, other_item| item.eq_with(&other_item, comparison))
+            }
+            (Dynamic::Object(object), Dynamic::Object(other_object)) => object.eq_with(other_object, comparison),
+            (Dynamic::Bson(Bson::Decimal128(decimal)), Dynamic::Number(number))
+            | (Dynamic::Number(number), Dynamic::Bson(Bson::Decimal128(decimal))) => {
+                decimal.partial_cmp_number(number) == Some(Ordering::Equal)
+            }
+            (Dynamic::Bson(bson), Dynamic::Bson(other_bson)) => bson == other_bson,
             _ => false,
         }
     }
-}
 
-impl PartialOrd for Dynamic {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    /// Orders values, comparing objects as specified. Values of different types are ordered by `comparison_order`.
+    pub fn compare_with(&self, other: &Dynamic, comparison: ObjectComparison) -> Option<Ordering> {
         match (self, other) {
             (Dynamic::Null, Dynamic::Null) => Some(Ordering::Equal),
             (Dynamic::Number(number), Dynamic::Number(other_number)) => {
@@ -313,32 +582,25 @@ impl PartialOrd for Dynamic {
             (Dynamic::String(string), Dynamic::String(other_string)) => {
                 string.partial_cmp(other_string)
             }
-            (Dynamic::Array(array), Dynamic::Array(other_array)) => {
-                let Ok(array) = array.read() else { return None };
-                let Ok(other_array) = other_array.read() else {
-                    return None;
-                };
-                array.partial_cmp(&other_array)
-            }
+            (Dynamic::Array(Array::Items(items)), Dynamic::Array(Array::Items(other_items))) => items
+                .iter()
+                .partial_cmp_by(other_items.iter(), |item, other_item| item.compare_with(other_item, comparison)),
+            (Dynamic::Array(array), Dynamic::Array(other_array)) => array
+                .iter()
+                .partial_cmp_by(other_array.iter(), |item, other_item| item.compare_with(&other_item, comparison)),
             (Dynamic::Object(object), Dynamic::Object(other_object)) => {
-                object.partial_cmp(other_object)
+                object.compare_with(other_object, comparison)
+            }
+            (Dynamic::Bson(Bson::Decimal128(decimal)), Dynamic::Number(number)) => decimal.partial_cmp_number(number),
+            (Dynamic::Number(number), Dynamic::Bson(Bson::Decimal128(decimal))) => {
+                decimal.partial_cmp_number(number).map(Ordering::reverse)
+            }
+            (Dynamic::Bson(bson), Dynamic::Bson(other_bson)) if bson.comparison_order() == other_bson.comparison_order() => {
+                bson.compare(other_bson)
             }
             (x, y) => x.comparison_order().partial_cmp(&y.comparison_order()),
         }
     }
-}
-
-impl Dynamic {
-    fn comparison_order(&self) -> u8 {
-        match self {
-            Dynamic::Null => 1,
-            Dynamic::Number(_) => 2,
-            Dynamic::String(_) => 3,
-            Dynamic::Object(_) => 4,
-            Dynamic::Array(_) => 5,
-            Dynamic::Bool(_) => 6,
-        }
-    }
     pub fn is_null(&self) -> bool {
         matches!(self, Dynamic::Null)
     }
@@ -382,12 +644,31 @@ impl Dynamic {
         }
         None
     }
-    pub fn as_array(&self) -> Option<&RwLock<SmallVec<Dynamic,10>>> {
+    /// Returns the items of the array. Dynamic arrays have no items to borrow, see `as_dynamic_array`.
+    pub fn as_array(&self) -> Option<&SmallVec<Dynamic,10>> {
+        if let Dynamic::Array(Array::Items(items)) = self {
+            return Some(items.deref());
+        }
+        None
+    }
+    /// Returns the array, either with its items or as a dynamic array
+    pub fn as_dynamic_array(&self) -> Option<&Array> {
         if let Dynamic::Array(array) = self {
-            return Some(array.deref());
+            return Some(array);
         }
         None
     }
+    /// Returns the items of the array for modification, copying them first if they are shared.
+    /// Frozen arrays can't be modified.
+    pub fn as_array_mut(&mut self) -> Option<&mut SmallVec<Dynamic,10>> {
+        self.array_mut().ok()
+    }
+    fn array_mut(&mut self) -> Result<&mut SmallVec<Dynamic,10>, DynamicError> {
+        if let Dynamic::Array(array) = self {
+            return array.make_mut();
+        }
+        Err(DynamicError::NotAnArray)
+    }
     pub fn as_object(&self) -> Option<&Object> {
         if let Dynamic::Object(object) = self {
             return Some(object);
@@ -395,9 +676,17 @@ impl Dynamic {
         None
     }
 
-    pub fn as_map(&self) -> Option<Arc<RwLock<LinkedHashMap<String,Dynamic>>>>{
+    pub fn as_map(&self) -> Option<&LinkedHashMap<String,Dynamic>>{
         if let Dynamic::Object(Object::Map(map)) = self{
-            return Some(map.clone())
+            return Some(map.deref())
+        }
+        None
+    }
+    /// Returns the fields of the object for modification, copying them first if they are shared.
+    /// Frozen objects can't be modified.
+    pub fn as_map_mut(&mut self) -> Option<&mut LinkedHashMap<String,Dynamic>>{
+        if let Dynamic::Object(Object::Map(map)) = self{
+            return map.make_mut().ok()
         }
         None
     }
@@ -416,6 +705,11 @@ impl Dynamic {
         None
     }
 
+    /// Returns the field for modification. Fields of dynamic objects can't be modified.
+    pub fn get_object_field_mut(&mut self, key: &str) -> Option<&mut Dynamic> {
+        self.as_map_mut()?.get_mut(key)
+    }
+
     pub fn set_object_field(
         &mut self,
         key: impl Into<String>,
@@ -428,19 +722,116 @@ impl Dynamic {
     }
 
     pub fn get_array_item(&self, index: usize) -> Option<Dynamic> {
-        if let Dynamic::Array(object) = self {
-            return object.read().ok()?.get(index).cloned();
+        if let Dynamic::Array(array) = self {
+            return array.get(index);
         }
         None
     }
+
+    pub fn get_array_item_mut(&mut self, index: usize) -> Option<&mut Dynamic> {
+        self.as_array_mut()?.get_mut(index)
+    }
+
     pub fn push_array_item(&mut self, item: Dynamic) -> Result<(), DynamicError> {
-        if let Dynamic::Array(object) = self {
-            return Ok(object
-                .write()
-                .map_err(|_| DynamicError::UnableToWrite)?
-                .push(item));
+        let array = self.array_mut()?;
+        array.push(item);
+        Ok(())
+    }
+
+    /// Inserts the item at the index, shifting the following items
+    pub fn insert_array_item(&mut self, index: usize, item: Dynamic) -> Result<(), DynamicError> {
+        let array = self.array_mut()?;
+        if index > array.len() {
+            return Err(DynamicError::IndexOutOfBounds);
+        }
+        array.insert(index, item);
+        Ok(())
+    }
+
+    pub fn remove_array_item(&mut self, index: usize) -> Result<Dynamic, DynamicError> {
+        let array = self.array_mut()?;
+        if index >= array.len() {
+            return Err(DynamicError::IndexOutOfBounds);
+        }
+        Ok(array.remove(index))
+    }
+
+    /// Replaces the item at the index, padding the array with nulls if it is too short
+    pub fn set_array_item(&mut self, index: usize, item: Dynamic) -> Result<Option<Dynamic>, DynamicError> {
+        let array = self.array_mut()?;
+        if index >= array.len() {
+            array.resize(index, Dynamic::Null);
+            array.push(item);
+            return Ok(None);
+        }
+        Ok(Some(std::mem::replace(&mut array[index], item)))
+    }
+
+    /// Copies the value together with all nested arrays and objects, so that no storage is shared with it.
+    /// The copy is never frozen.
+    pub fn deep_clone(&self) -> Dynamic {
+        match self {
+            Dynamic::String(string) => Dynamic::from(string.deref().clone()),
+            Dynamic::Array(array) => Dynamic::from(array.iter().map(|item| item.deep_clone()).collect::<SmallVec<_, 10>>()),
+            Dynamic::Object(object) => Dynamic::from(
+                object
+                    .to_map()
+                    .into_iter()
+                    .map(|(key, value)| (key, value.deep_clone()))
+                    .collect::<LinkedHashMap<_, _>>(),
+            ),
+            value => value.clone(),
+        }
+    }
+
+    /// Returns a read-only copy of the value. Modifying its arrays and objects, including nested ones,
+    /// fails with `DynamicError::ImmutableObject`. Values which are already frozen keep their storage.
+    pub fn freeze(&self) -> Dynamic {
+        match self {
+            Dynamic::Array(Array::Items(items)) if !items.frozen => Dynamic::Array(Array::Items(Items {
+                items: Arc::new(items.iter().map(Dynamic::freeze).collect()),
+                frozen: true,
+            })),
+            Dynamic::Object(Object::Map(map)) if !map.frozen => Dynamic::Object(Object::Map(Map {
+                fields: Arc::new(map.iter().map(|(key, value)| (key.clone(), value.freeze())).collect()),
+                frozen: true,
+            })),
+            Dynamic::Object(object @ Object::DynamicObject(dynamic_object)) if dynamic_object.as_mutable().is_some() => {
+                Dynamic::from(object.to_map()).freeze()
+            }
+            value => value.clone(),
+        }
+    }
+
+    /// Whether the value can't be modified. Dynamic arrays are always frozen, and so are dynamic objects
+    /// which don't implement `DynamicObjectMut`.
+    pub fn is_frozen(&self) -> bool {
+        match self {
+            Dynamic::Array(array) => array.is_frozen(),
+            Dynamic::Object(Object::Map(map)) => map.frozen,
+            Dynamic::Object(Object::DynamicObject(object)) => object.as_mutable().is_none(),
+            _ => false,
+        }
+    }
+
+    /// Whether both values share the same storage. Values without storage, like numbers, never do.
+    pub fn ptr_eq(&self, other: &Dynamic) -> bool {
+        match (self, other) {
+            (Dynamic::String(string), Dynamic::String(other_string)) => Arc::ptr_eq(string, other_string),
+            (Dynamic::Array(Array::Items(items)), Dynamic::Array(Array::Items(other_items))) => {
+                Arc::ptr_eq(&items.items, &other_items.items)
+            }
+            (Dynamic::Array(Array::DynamicArray(array)), Dynamic::Array(Array::DynamicArray(other_array))) => {
+                Arc::ptr_eq(array, other_array)
+            }
+            (Dynamic::Object(Object::Map(map)), Dynamic::Object(Object::Map(other_map))) => {
+                Arc::ptr_eq(&map.fields, &other_map.fields)
+            }
+            (Dynamic::Object(Object::DynamicObject(object)), Dynamic::Object(Object::DynamicObject(other_object))) => {
+                Arc::ptr_eq(object, other_object)
+            }
+            _ => false,
         }
-        Err(DynamicError::NotAnArray)
     }
 }
 
@@ -477,92 +868,203 @@ impl From<i64> for Dynamic {
     }
 }
 
-impl From<Vec<Dynamic>> for Dynamic {
-    fn from(value: Vec<Dynamic>) -> Self {
-        Dynamic::Array(Arc::new(RwLock::new(value.into())))
+impl From<&str> for Dynamic {
+    fn from(value: &str) -> Self {
+        Dynamic::String(Arc::new(String::from(value)))
     }
 }
 
-impl From<SmallVec<Dynamic,10>> for Dynamic {
-    fn from(value: SmallVec<Dynamic,10>) -> Self {
-        Dynamic::Array(Arc::new(RwLock::new(value.into())))
+impl From<std::string::String> for Dynamic {
+    fn from(value: std::string::String) -> Self {
+        Dynamic::String(Arc::new(String::from(value)))
     }
 }
 
-type FieldValues<'a> = impl Iterator<Item = (&'a str, Dynamic)>;
-pub trait DynamicObject {
-    fn get_field(&self, field: &str) -> Option<Dynamic>;
-    fn fields(&self) -> &[&str];
+macro_rules! from_bson {
+    ($($source:ty),*) => {
+        $(impl From<$source> for Dynamic {
+            fn from(value: $source) -> Self {
+                Dynamic::Bson(Bson::from(value))
+            }
+        })*
+    };
 }
 
-impl dyn DynamicObject{
-    fn field_values(&self) -> FieldValues {
-        self.fields()
-            .iter()
-            .map(|&key| self.get_field(key).map(|value| (key, value)))
-            .flatten()
+impl From<Bson> for Dynamic {
+    fn from(value: Bson) -> Self {
+        Dynamic::Bson(value)
     }
 }
 
-impl Debug for dyn DynamicObject {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_map().entries(self.field_values()).finish()
-    }
-}
+from_bson!(bson::ObjectId, bson::DateTime, bson::Binary, bson::Timestamp, bson::Decimal128);
 
-impl Debug for Object {
-    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Object::Map(map) => {
-                formatter.write_str("Object ")?;
-                Debug::fmt(map.read().map_err(|_| fmt::Error)?.deref(), formatter)
+macro_rules! from_number {
+    ($variant:ident as $target:ty: $($source:ty),*) => {
+        $(impl From<$source> for Dynamic {
+            fn from(value: $source) -> Self {
+                Dynamic::Number(Number::$variant(value as $target))
             }
-            Object::DynamicObject(obj) => {
-                formatter.write_str("DynamicObject ")?;
-                Debug::fmt(&obj.deref(), formatter)
+        })*
+    };
+}
+
+from_number!(Int as i64: i8, i16, i32, u8, u16, u32);
+from_number!(Float as f64: f32, f64);
+
+/// Converts integers which don't always fit into `i64` into the nearest float when they don't
+macro_rules! from_wide_int {
+    ($($source:ty),*) => {
+        $(impl From<$source> for Dynamic {
+            fn from(value: $source) -> Self {
+                match i64::try_from(value) {
+                    Ok(int) => Dynamic::Number(Number::Int(int)),
+                    Err(_) => Dynamic::Number(Number::Float(value as f64)),
+                }
             }
-        }
+        })*
+    };
+}
+
+from_wide_int!(u64, usize, isize, i128, u128);
+
+impl From<Vec<Dynamic>> for Dynamic {
+    fn from(value: Vec<Dynamic>) -> Self {
+        Dynamic::Array(Array::from(SmallVec::from(value)))
     }
 }
 
-#[derive(Clone)]
-pub struct TestObj {
-    pub field1: String,
-    pub field2: TestObj2,
+impl From<SmallVec<Dynamic,10>> for Dynamic {
+    fn from(value: SmallVec<Dynamic,10>) -> Self {
+        Dynamic::Array(Array::from(value))
+    }
 }
-#[derive(Clone)]
-pub struct TestObj2 {
-    pub field3: i64,
-    pub field4: bool,
+
+type FieldValues<'a> = impl Iterator<Item = (&'a str, Dynamic)>;
+pub trait DynamicObject: Send + Sync + AsDynamicObjectMut {
+    fn get_field(&self, field: &str) -> Option<Dynamic>;
+    /// Names of the fields, which can be borrowed from a static list or built for map-like types
+    fn fields(&self) -> Cow<'_, [&str]>;
+
+    /// Returns a view of the field which borrows its data where possible, so it can be tested without copying it
+    fn get_field_ref(&self, field: &str) -> Option<DynamicRef<'_>> {
+        self.get_field(field).map(DynamicRef::Owned)
+    }
 }
 
-impl DynamicObject for TestObj {
-    fn get_field(&self, field: &str) -> Option<Dynamic> {
-        match field {
-            "field1" => Some(Dynamic::from(self.field1.clone())),
-            "field2" => Some(Dynamic::from(self.field2.clone())),
-            _ => None,
-        }
+/// Access to dynamic objects as `DynamicObjectMut`, which is implemented for all of them.
+/// Objects which implement `DynamicObjectMut` get it implemented automatically, other objects implement it
+/// without any methods, e.g. `impl AsDynamicObjectMut for Headers {}`, so their fields can't be modified.
+pub trait AsDynamicObjectMut {
+    /// Returns the object as a `DynamicObjectMut` if its fields can be modified
+    fn as_mutable(&self) -> Option<&dyn DynamicObjectMut> {
+        None
     }
 
-    fn fields(&self) -> &[&str] {
-        &["field1", "field2"]
+    fn as_mutable_mut(&mut self) -> Option<&mut dyn DynamicObjectMut> {
+        None
     }
 }
 
-impl DynamicObject for TestObj2 {
-    fn get_field(&self, field: &str) -> Option<Dynamic> {
-        match field {
-            "field3" => Some(Dynamic::from(self.field3.clone())),
-            "field4" => Some(Dynamic::from(self.field4.clone())),
-            _ => None,
-        }
+impl<T: DynamicObjectMut> AsDynamicObjectMut for T {
+    fn as_mutable(&self) -> Option<&dyn DynamicObjectMut> {
+        Some(self)
+    }
+
+    fn as_mutable_mut(&mut self) -> Option<&mut dyn DynamicObjectMut> {
+        Some(self)
+    }
+}
+
+/// Dynamic object whose fields can be modified, e.g. by updates
+pub trait DynamicObjectMut: DynamicObject {
+    /// Sets the field and returns its previous value. Values which can't be stored in the field
+    /// are reported as `DynamicError::TypeMismatch`.
+    fn set_field(&mut self, field: &str, value: Dynamic) -> Result<Option<Dynamic>, DynamicError>;
+    /// Removes the field and returns its previous value
+    fn remove_field(&mut self, field: &str) -> Result<Option<Dynamic>, DynamicError>;
+    /// Copies the object, so a shared object can be modified without modifying its other owners
+    fn clone_object(&self) -> Arc<dyn DynamicObject>;
+}
+
+/// Array whose items are provided on demand, e.g. from a Rust collection, instead of being stored as `Dynamic` values
+pub trait DynamicArray: Send + Sync {
+    fn len(&self) -> usize;
+    fn get(&self, index: usize) -> Option<Dynamic>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Dynamic> + '_> {
+        Box::new((0..self.len()).filter_map(|index| self.get(index)))
+    }
+
+    /// Returns a view of the item which borrows its data where possible
+    fn get_ref(&self, index: usize) -> Option<DynamicRef<'_>> {
+        self.get(index).map(DynamicRef::Owned)
+    }
+}
+
+impl<T> DynamicArray for Vec<T>
+where
+    T: AsDynamicRef + Clone + Into<Dynamic> + Send + Sync,
+{
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn get(&self, index: usize) -> Option<Dynamic> {
+        self.as_slice().get(index).cloned().map(Into::into)
+    }
+
+    fn get_ref(&self, index: usize) -> Option<DynamicRef<'_>> {
+        self.as_slice().get(index).map(AsDynamicRef::as_dynamic_ref)
+    }
+}
+
+impl dyn DynamicObject{
+    #[define_opaque(FieldValues)]
+    fn field_values(&self) -> FieldValues {
+        let fields = self.fields();
+        (0..fields.len()).filter_map(move |index| {
+            let key = fields[index];
+            self.get_field(key).map(|value| (key, value))
+        })
+    }
+}
+
+impl Debug for dyn DynamicObject {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.field_values()).finish()
     }
+}
 
-    fn fields(&self) -> &[&str] {
-        &["field3", "field4"]
+impl Debug for Object {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Map(map) => {
+                formatter.write_str("Object ")?;
+                Debug::fmt(map.deref(), formatter)
+            }
+            Object::DynamicObject(obj) => {
+                formatter.write_str("DynamicObject ")?;
+                Debug::fmt(&obj.deref(), formatter)
+            }
+        }
     }
 }
+
+#[derive(Clone, DynamicObject)]
+pub struct TestObj {
+    pub field1: String,
+    pub field2: TestObj2,
+}
+#[derive(Clone, DynamicObject)]
+pub struct TestObj2 {
+    pub field3: i64,
+    pub field4: bool,
+}
+
 impl Debug for Dynamic {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -570,11 +1072,16 @@ impl Debug for Dynamic {
             Dynamic::Bool(boolean) => write!(formatter, "Bool({})", boolean),
             Dynamic::Number(number) => Debug::fmt(number, formatter),
             Dynamic::String(string) => write!(formatter, "String({:?})", string.deref()),
-            Dynamic::Array(vec) => {
+            Dynamic::Array(Array::Items(items)) => {
                 formatter.write_str("Array ")?;
-                Debug::fmt(vec.read().map_err(|_| fmt::Error)?.deref(), formatter)
+                Debug::fmt(items.deref(), formatter)
+            }
+            Dynamic::Array(Array::DynamicArray(array)) => {
+                formatter.write_str("DynamicArray ")?;
+                formatter.debug_list().entries(array.iter()).finish()
             }
             Dynamic::Object(map) => Debug::fmt(map, formatter),
+            Dynamic::Bson(bson) => Debug::fmt(bson, formatter),
         }
     }
 }
@@ -582,10 +1089,23 @@ impl Debug for Dynamic {
 #[cfg(test)]
 mod tests {
     use crate::query::ast::parser::{expression, script};
-    use crate::query::parser::{field, predicate};
+    use crate::query::parser::{field, predicate, value};
     use crate::query::utils::{separated_permutation, separated_tuple};
-    use crate::query::{Context, Eval, Script};
-    use crate::{Dynamic, TestObj, TestObj2};
+    use crate::query::pipeline::Pipeline;
+    use crate::query::update::{ArrayFilters, Update};
+    use crate::query::ast::{Predicate, Value};
+    use crate::patch::JsonPatch;
+    use crate::diff::{ArrayDiff, Change};
+    use crate::json_path::JsonPath;
+    use crate::patch::merge::{merge_patch, merge_patch_diff};
+    use crate::query::{Context, Eval, EvalError, Script};
+    use crate::raw::RawFilter;
+    use crate::bson::{Binary, Bson, DateTime, Decimal128, ObjectId};
+    use crate::{Array, AsDynamicObjectMut, Dynamic, DynamicArray, DynamicError, DynamicObject, DynamicObjectMut, DynamicRef, Number, ObjectComparison, TestObj, TestObj2};
+    use std::borrow::Cow;
+    use crate::ord::OrdDynamic;
+    use std::cmp::Ordering;
+    use std::collections::HashSet;
     use nom::bytes::complete::tag;
     use nom::character::complete::char;
     use nom::IResult;
@@ -634,4 +1154,1294 @@ mod tests {
         ]);
         println!("{:#?}", script.eval_with_context(&mut context));
     }
+
+    #[test]
+    fn facet_pipeline() {
+        let pipeline = Pipeline::from_str(r#"[
+            { "$match": { "available": true } },
+            {
+                "$facet": {
+                    "byCategory": [ { "$sortByCount": "$category" } ],
+                    "byPrice": [
+                        {
+                            "$bucket": {
+                                "groupBy": "$price",
+                                "boundaries": [0, 10, 100],
+                                "default": "Other",
+                                "output": { "count": { "$sum": 1 }, "titles": { "$push": "$title" } }
+                            }
+                        }
+                    ],
+                    "auto": [ { "$bucketAuto": { "groupBy": "$price", "buckets": 2 } } ]
+                }
+            }
+        ]"#).unwrap();
+        let documents = json!([
+            { "title": "a", "category": "books", "price": 5, "available": true },
+            { "title": "b", "category": "music", "price": 15, "available": true },
+            { "title": "c", "category": "books", "price": 25, "available": true },
+            { "title": "d", "category": "books", "price": 150, "available": true },
+            { "title": "e", "category": "music", "price": 20, "available": false }
+        ]);
+        let documents = documents.as_array().unwrap().iter().map(Dynamic::from);
+
+        let result = pipeline.execute(documents).unwrap();
+
+        let (_, expected) = value(r#"[{
+            "byCategory": [
+                { "_id": "books", "count": 3 },
+                { "_id": "music", "count": 1 }
+            ],
+            "byPrice": [
+                { "_id": 0, "count": 1, "titles": ["a"] },
+                { "_id": 10, "count": 2, "titles": ["b", "c"] },
+                { "_id": "Other", "count": 1, "titles": ["d"] }
+            ],
+            "auto": [
+                { "_id": { "min": 5, "max": 25 }, "count": 2 },
+                { "_id": { "min": 25, "max": 150 }, "count": 2 }
+            ]
+        }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(result));
+
+        let documents = [Dynamic::from(&json!({ "category": "books" })), Dynamic::from(&json!({ "price": 5 }))];
+        let pipeline = Pipeline::from_str(r#"[{ "$sortByCount": "$category" }]"#).unwrap();
+        let (_, expected) = value(r#"[{ "_id": "books", "count": 1 }, { "_id": null, "count": 1 }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(pipeline.execute(documents.clone()).unwrap()));
+        let pipeline = Pipeline::from_str(r#"[{ "$bucket": { "groupBy": "$price", "boundaries": [0, 10], "default": "Other" } }]"#).unwrap();
+        let (_, expected) = value(r#"[{ "_id": 0, "count": 1 }, { "_id": "Other", "count": 1 }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(pipeline.execute(documents).unwrap()));
+    }
+
+    #[test]
+    fn window_fields_pipeline() {
+        let pipeline = Pipeline::from_str(r#"[{
+            "$setWindowFields": {
+                "partitionBy": "$sensor",
+                "sortBy": { "t": 1 },
+                "output": {
+                    "total": { "$sum": "$v", "window": { "documents": ["unbounded", "current"] } },
+                    "avg": { "$avg": "$v", "window": { "documents": [-1, 0] } },
+                    "rank": { "$rank": {} },
+                    "previous": { "$shift": { "output": "$v", "by": -1, "default": 0 } },
+                    "rate": { "$derivative": { "input": "$v" }, "window": { "documents": [-1, 0] } },
+                    "area": { "$integral": { "input": "$v" }, "window": { "range": ["unbounded", "current"] } },
+                    "ema": { "$expMovingAvg": { "input": "$v", "alpha": 0.5 } }
+                }
+            }
+        }]"#).unwrap();
+        let documents = json!([
+            { "sensor": "a", "t": 2, "v": 20 },
+            { "sensor": "b", "t": 1, "v": 5 },
+            { "sensor": "a", "t": 1, "v": 10 },
+            { "sensor": "a", "t": 3, "v": 40 }
+        ]);
+        let documents = documents.as_array().unwrap().iter().map(Dynamic::from);
+
+        let result = pipeline.execute(documents).unwrap();
+
+        let (_, expected) = value(r#"[
+            { "sensor": "a", "t": 1, "v": 10, "total": 10, "avg": 10, "rank": 1, "previous": 0, "rate": null, "area": 0, "ema": 10 },
+            { "sensor": "a", "t": 2, "v": 20, "total": 30, "avg": 15, "rank": 2, "previous": 10, "rate": 10, "area": 15, "ema": 15 },
+            { "sensor": "a", "t": 3, "v": 40, "total": 70, "avg": 30, "rank": 3, "previous": 20, "rate": 20, "area": 45, "ema": 27.5 },
+            { "sensor": "b", "t": 1, "v": 5, "total": 5, "avg": 5, "rank": 1, "previous": 0, "rate": null, "area": 0, "ema": 5 }
+        ]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(result));
+
+        // Moving windows add and remove values as they slide
+        let pipeline = Pipeline::from_str(r#"[{
+            "$setWindowFields": {
+                "sortBy": { "t": 1 },
+                "output": {
+                    "min": { "$min": "$v", "window": { "documents": [-1, 1] } },
+                    "max": { "$max": "$v", "window": { "documents": [-1, 1] } },
+                    "sum": { "$sum": "$w", "window": { "documents": [-1, 1] } },
+                    "first": { "$first": "$v", "window": { "documents": [-1, 1] } },
+                    "last": { "$last": "$v", "window": { "documents": [-1, 1] } },
+                    "values": { "$push": "$v", "window": { "documents": [-1, 1] } },
+                    "groups": { "$addToSet": "$g", "window": { "documents": ["unbounded", "current"] } },
+                    "recent": { "$sum": "$v", "window": { "range": [-1, 0] } }
+                }
+            }
+        }]"#).unwrap();
+        let documents = [(5, "x"), (1, "y"), (4, "x"), (6, "x"), (2, "y")]
+            .iter()
+            .enumerate()
+            .map(|(t, (v, g))| Dynamic::from(&json!({ "t": t, "v": v, "w": 0.5, "g": g })));
+        let result = pipeline.execute(documents).unwrap();
+        let fields = ["min", "max", "sum", "first", "last", "values", "groups", "recent"];
+        let windows = result
+            .iter()
+            .map(|document| Dynamic::from(fields.iter().map(|field| document.get_object_field(field).unwrap()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        let (_, expected) = value(r#"[
+            [1, 5, 1.0, 5, 1, [5, 1], ["x"], 5],
+            [1, 5, 1.5, 5, 4, [5, 1, 4], ["x", "y"], 6],
+            [1, 6, 1.5, 1, 6, [1, 4, 6], ["x", "y"], 5],
+            [2, 6, 1.5, 4, 2, [4, 6, 2], ["x", "y"], 10],
+            [2, 6, 1.0, 6, 2, [6, 2], ["x", "y"], 8]
+        ]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(windows));
+
+        // Range windows of a descending sort precede and follow documents in the order of the sort
+        let pipeline = Pipeline::from_str(r#"[{
+            "$setWindowFields": {
+                "sortBy": { "t": -1 },
+                "output": {
+                    "recent": { "$sum": "$v", "window": { "range": [-2, 0] } },
+                    "later": { "$sum": "$v", "window": { "range": ["current", 3] } }
+                }
+            }
+        }]"#).unwrap();
+        let documents = [(0, 1), (1, 2), (2, 3), (4, 4), (7, 5)]
+            .iter()
+            .map(|(t, v)| Dynamic::from(&json!({ "t": t, "v": v })));
+        let result = pipeline.execute(documents).unwrap();
+        let windows = result
+            .iter()
+            .map(|document| Dynamic::from(["t", "recent", "later"].map(|field| document.get_object_field(field).unwrap()).to_vec()))
+            .collect::<Vec<_>>();
+        let (_, expected) = value(r#"[[7, 5, 9], [4, 4, 9], [2, 7, 6], [1, 5, 3], [0, 6, 1]]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(windows));
+    }
+
+    #[test]
+    fn reshaping_pipeline() {
+        let pipeline = Pipeline::from_str(r#"[
+            { "$unionWith": { "coll": "archive", "pipeline": [ { "$match": { "kept": true } } ] } },
+            { "$unset": ["kept", "meta.secret"] },
+            { "$replaceWith": "$meta" }
+        ]"#).unwrap();
+        let mut context = Context::new();
+        let archive = json!([
+            { "kept": true, "meta": { "name": "b", "secret": 2 } },
+            { "kept": false, "meta": { "name": "c", "secret": 3 } }
+        ]);
+        context.register_collection("archive", archive.as_array().unwrap().iter().map(Dynamic::from));
+        let documents = [Dynamic::from(&json!({ "kept": true, "meta": { "name": "a", "secret": 1 } }))];
+
+        let result = pipeline.execute_with_context(documents, &mut context).unwrap();
+
+        let (_, expected) = value(r#"[{ "name": "a" }, { "name": "b" }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(result));
+
+        let replace_root = Pipeline::from_str(r#"[{ "$replaceRoot": { "newRoot": "$meta" } }]"#).unwrap();
+        let documents = [Dynamic::from(&json!({ "meta": { "name": "a" } }))];
+        let (_, expected) = value(r#"[{ "name": "a" }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(replace_root.execute(documents).unwrap()));
+        let documents = [Dynamic::from(&json!({ "meta": 1 }))];
+        assert!(replace_root.execute(documents).is_err());
+
+        let documents = [Dynamic::from(&json!({ "items": [{ "name": "a", "secret": 1 }, { "name": "b", "secret": 2 }] }))];
+        let unset = Pipeline::from_str(r#"[{ "$unset": "items[0].secret" }]"#).unwrap();
+        let (_, expected) = value(r#"[{ "items": [{ "name": "a" }, { "name": "b", "secret": 2 }] }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(unset.execute(documents.clone()).unwrap()));
+        let unset = Pipeline::from_str(r#"[{ "$unset": "items.secret" }]"#).unwrap();
+        let (_, expected) = value(r#"[{ "items": [{ "name": "a" }, { "name": "b" }] }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(unset.execute(documents.clone()).unwrap()));
+        for path in ["items[*].secret", "items[0]", "items.*"] {
+            let unset = Pipeline::from_str(&format!(r#"[{{ "$unset": "{path}" }}]"#)).unwrap();
+            assert!(matches!(unset.execute(documents.clone()), Err(EvalError::UnsupportedPath)));
+        }
+
+        let documents = (0..10).map(Dynamic::from).collect::<Vec<_>>();
+        let sample = Pipeline::from_str(r#"[{ "$sample": { "size": 3, "seed": 42 } }]"#).unwrap();
+        let first = sample.execute(documents.clone()).unwrap();
+        let second = sample.execute(documents.clone()).unwrap();
+        assert_eq!(first.len(), 3);
+        assert_eq!(first, second);
+
+        let count = Pipeline::from_str(r#"[{ "$count": "total" }]"#).unwrap();
+        let (_, expected) = value(r#"[{ "total": 10 }]"#).unwrap();
+        assert_eq!(expected, Dynamic::from(count.execute(documents).unwrap()));
+    }
+
+    #[test]
+    fn graph_lookup_pipeline() {
+        let pipeline = Pipeline::from_str(r#"[
+            {
+                "$graphLookup": {
+                    "from": "employees",
+                    "startWith": "$reportsTo",
+                    "connectFromField": "reportsTo",
+                    "connectToField": "name",
+                    "as": "managers",
+                    "depthField": "depth"
+                }
+            },
+            {
+                "$graphLookup": {
+                    "from": "employees",
+                    "startWith": "$name",
+                    "connectFromField": "name",
+                    "connectToField": "reportsTo",
+                    "as": "reports",
+                    "maxDepth": 0,
+                    "restrictSearchWithMatch": { "active": true }
+                }
+            }
+        ]"#).unwrap();
+        let employees = json!([
+            { "name": "A", "reportsTo": "B", "active": true },
+            { "name": "B", "reportsTo": "C", "active": true },
+            { "name": "C", "reportsTo": "A", "active": true },
+            { "name": "D", "reportsTo": "C", "active": false },
+            { "name": "E", "reportsTo": "C", "active": true }
+        ]);
+        let mut context = Context::new();
+        context.register_collection("employees", employees.as_array().unwrap().iter().map(Dynamic::from));
+        let documents = [Dynamic::from(&json!({ "name": "C" }))];
+
+        let result = pipeline.execute_with_context(documents, &mut context).unwrap();
+
+        let (_, expected) = value(r#"[{
+            "name": "C",
+            "managers": [],
+            "reports": [
+                { "active": true, "name": "B", "reportsTo": "C" },
+                { "active": true, "name": "E", "reportsTo": "C" }
+            ]
+        }]"#).unwrap();
+        // Fields of the collection are sorted unless serde_json preserves their order
+        assert!(expected.eq_with(&Dynamic::from(result), ObjectComparison::Unordered));
+
+        let documents = [Dynamic::from(&json!({ "name": "A", "reportsTo": "B" }))];
+        let result = pipeline.execute_with_context(documents, &mut context).unwrap();
+        let managers = result[0].get_object_field("managers").unwrap();
+        let (_, expected) = value(r#"[
+            { "active": true, "name": "B", "reportsTo": "C", "depth": 0 },
+            { "active": true, "name": "C", "reportsTo": "A", "depth": 1 },
+            { "active": true, "name": "A", "reportsTo": "B", "depth": 2 }
+        ]"#).unwrap();
+        assert!(expected.eq_with(&managers, ObjectComparison::Unordered));
+
+        // Long chains are followed without searching the whole collection at every depth
+        let chain = (0..5000).rev().map(|id| Dynamic::from(&json!({ "id": id, "next": (id + 1) % 5000 })));
+        context.register_collection("chain", chain);
+        let pipeline = Pipeline::from_str(r#"[{ "$graphLookup": {
+            "from": "chain", "startWith": 0, "connectFromField": "next", "connectToField": "id", "as": "found", "depthField": "depth"
+        } }]"#).unwrap();
+        let result = pipeline.execute_with_context([Dynamic::from(&json!({}))], &mut context).unwrap();
+        let found = result[0].get_object_field("found").unwrap();
+        assert_eq!(found.as_array().unwrap().len(), 5000);
+        assert_eq!(found.get_array_item(1).unwrap().get_object_field("id"), Some(Dynamic::from(1)));
+        assert_eq!(found.get_array_item(4999).unwrap().get_object_field("depth"), Some(Dynamic::from(4999)));
+
+        // Only `startWith` treats a missing field as having no values, other field paths still fail
+        let script = Script::from_str(r#""$reportsTo""#).unwrap();
+        assert!(matches!(script.eval_with_root(Dynamic::from(&json!({ "name": "C" }))), Err(EvalError::UndefinedVariable)));
+    }
+
+    #[test]
+    fn document_stages_pipeline() {
+        let documents = json!([
+            { "_id": 1, "name": "a", "tags": ["x", "y"], "price": 5, "secret": true },
+            { "_id": 2, "name": "b", "tags": [], "price": 20 },
+            { "_id": 3, "name": "c", "tags": ["x"], "price": 10 }
+        ]);
+        let documents = documents.as_array().unwrap().iter().map(Dynamic::from).collect::<Vec<_>>();
+        let execute = |pipeline: &str| Dynamic::from(Pipeline::from_str(pipeline).unwrap().execute(documents.clone()).unwrap());
+
+        let (_, expected) = value(r#"[
+            { "_id": 1, "name": "a", "cheap": true },
+            { "_id": 2, "name": "b", "cheap": false },
+            { "_id": 3, "name": "c", "cheap": false }
+        ]"#).unwrap();
+        assert_eq!(expected, execute(r#"[{ "$project": { "name": 1, "cheap": { "$lt": ["$price", 10] } } }]"#));
+        let (_, expected) = value(r#"[{ "name": "a", "price": 5 }, { "name": "b", "price": 20 }, { "name": "c", "price": 10 }]"#).unwrap();
+        assert_eq!(expected, execute(r#"[{ "$project": { "_id": 0, "tags": 0, "secret": 0 } }]"#));
+        assert!(Pipeline::from_str(r#"[{ "$project": { "name": 1, "price": 0 } }]"#).is_err());
+
+        let (_, expected) = value(r#"[{ "_id": 1, "meta": { "label": "a" } }]"#).unwrap();
+        assert_eq!(
+            expected,
+            execute(r#"[{ "$addFields": { "meta.label": "$name", "missing": "$none" } }, { "$match": { "_id": 1 } }, { "$project": { "meta": 1 } }]"#)
+        );
+
+        let (_, expected) = value(r#"[
+            { "_id": 1, "tags": "x", "index": 0 },
+            { "_id": 1, "tags": "y", "index": 1 },
+            { "_id": 2, "tags": [], "index": null },
+            { "_id": 3, "tags": "x", "index": 0 }
+        ]"#).unwrap();
+        assert_eq!(
+            expected,
+            execute(r#"[
+                { "$unwind": { "path": "$tags", "includeArrayIndex": "index", "preserveNullAndEmptyArrays": true } },
+                { "$project": { "tags": 1, "index": 1 } }
+            ]"#)
+        );
+
+        let (_, expected) = value(r#"[
+            { "_id": "x", "count": 2, "total": 15, "names": ["a", "c"] },
+            { "_id": "y", "count": 1, "total": 5, "names": ["a"] }
+        ]"#).unwrap();
+        assert_eq!(
+            expected,
+            execute(r#"[
+                { "$unwind": "$tags" },
+                { "$group": { "_id": "$tags", "count": { "$count": {} }, "total": { "$sum": "$price" }, "names": { "$push": "$name" } } }
+            ]"#)
+        );
+        let (_, expected) = value(r#"[{ "_id": null, "total": 35 }]"#).unwrap();
+        assert_eq!(expected, execute(r#"[{ "$group": { "_id": null, "total": { "$sum": "$price" } } }]"#));
+        assert!(Pipeline::from_str(r#"[{ "$group": { "total": { "$sum": "$price" } } }]"#).is_err());
+
+        let (_, expected) = value(r#"[{ "name": "b" }, { "name": "c" }, { "name": "a" }]"#).unwrap();
+        assert_eq!(expected, execute(r#"[{ "$sort": { "price": -1 } }, { "$project": { "_id": 0, "name": 1 } }]"#));
+    }
+
+    #[test]
+    fn parallel_pipeline() {
+        let documents = (0..1000)
+            .map(|index| Dynamic::from(&json!({ "group": index % 7, "value": index, "even": index % 2 == 0 })))
+            .collect::<Vec<_>>();
+        let threads = std::num::NonZeroUsize::new(4).unwrap();
+
+        for pipeline in [
+            r#"[{ "$match": { "even": true } }, { "$sortByCount": "$group" }]"#,
+            r#"[{ "$match": { "even": true } }, { "$bucket": { "groupBy": "$value", "boundaries": [0, 100, 500, 1000], "output": { "total": { "$sum": "$value" }, "avg": { "$avg": "$value" }, "first": { "$first": "$value" } } } }]"#,
+            r#"[{ "$unset": "even" }, { "$count": "total" }]"#,
+            r#"[{ "$match": { "even": false } }, { "$bucketAuto": { "groupBy": "$value", "buckets": 3 } }]"#,
+            r#"[{ "$project": { "group": 1, "value": 1 } }, { "$group": { "_id": "$group", "total": { "$sum": "$value" }, "first": { "$first": "$value" }, "last": { "$last": "$value" }, "values": { "$push": "$value" } } }]"#,
+            r#"[{ "$addFields": { "pair": ["$group", "$value"] } }, { "$unwind": "$pair" }, { "$sort": { "pair": -1, "group": 1 } }]"#,
+            r#"[{ "$set": { "large": { "$gt": ["$value", 500] } } }, { "$sort": { "large": 1 } }]"#,
+        ] {
+            let pipeline = Pipeline::from_str(pipeline).unwrap();
+            let sequential = pipeline.execute(documents.clone()).unwrap();
+            let parallel = pipeline
+                .execute_parallel_with_context(documents.clone(), &mut Context::new(), threads)
+                .unwrap();
+            assert_eq!(sequential, parallel);
+        }
+    }
+
+    #[test]
+    fn update_document() {
+        let update = Update::from_str(r#"{
+            "$set": { "config.server.port": 8080, "name": "main" },
+            "$inc": { "version": 1, "stats.updates": 2 },
+            "$mul": { "ratio": 1.5 },
+            "$max": { "limit": 10, "floor": 3 },
+            "$rename": { "old": "renamed" },
+            "$push": { "scores": { "$each": [5, 9, 1], "$sort": -1, "$slice": 3 }, "log": "updated", "history": { "by": "admin" } },
+            "$pull": { "values": { "$gte": 6 } },
+            "$addToSet": { "tags": { "$each": ["a", "c"] } },
+            "$pop": { "queue": -1 },
+            "$unset": { "obsolete": "" }
+        }"#).unwrap();
+        let mut document = Dynamic::from(&value(r#"{
+            "name": "test", "version": 1, "ratio": 2, "limit": 20, "floor": 1, "old": true,
+            "scores": [7], "values": [1, 6, 3, 8], "tags": ["a", "b"], "queue": [1, 2, 3], "obsolete": null
+        }"#).unwrap().1);
+        update.apply(&mut document).unwrap();
+
+        let expected = value(r#"{
+            "name": "main", "version": 2, "ratio": 3.0, "limit": 20, "floor": 3,
+            "scores": [9, 7, 5], "values": [1, 3], "tags": ["a", "b", "c"], "queue": [2, 3],
+            "config": { "server": { "port": 8080 } }, "stats": { "updates": 2 },
+            "renamed": true, "log": ["updated"], "history": [{ "by": "admin" }]
+        }"#).unwrap().1;
+        assert_eq!(expected, document);
+
+        let mut document = Dynamic::from(&value(r#"{ "version": "1" }"#).unwrap().1);
+        assert!(Update::from_str(r#"{ "$inc": { "version": 1 } }"#).unwrap().apply(&mut document).is_err());
+
+        // Fields which are present with a null value exist, as in Mongo
+        let test = |predicate: &str, document: &str| {
+            let document = Dynamic::from(&value(document).unwrap().1);
+            Predicate::from_str(predicate).unwrap().test_with_context(document, &mut Context::new()).unwrap()
+        };
+        assert!(test(r#"{ "a": { "$exists": true } }"#, r#"{ "a": null }"#));
+        assert!(!test(r#"{ "a": { "$exists": false } }"#, r#"{ "a": null }"#));
+        assert!(test(r#"{ "a.b": { "$exists": false } }"#, r#"{ "a": 1 }"#));
+        assert!(test(r#"{ "a": { "$not": { "$exists": true } } }"#, r#"{ "b": 1 }"#));
+        assert!(test(r#"{ "a": { "$exists": false, "$eq": null } }"#, r#"{ "b": 1 }"#));
+        let mut document = Dynamic::from(&value(r#"{ "items": [{ "note": null }, { "id": 2 }] }"#).unwrap().1);
+        Update::from_str(r#"{ "$pull": { "items": { "note": { "$exists": false } } } }"#).unwrap().apply(&mut document).unwrap();
+        assert_eq!(value(r#"{ "items": [{ "note": null }] }"#).unwrap().1, document);
+    }
+
+    #[test]
+    fn positional_update() {
+        let mut document = Dynamic::from(&value(r#"{
+            "grades": [85, 80, 80],
+            "items": [{ "name": "a", "qty": 1, "score": 9 }, { "name": "b", "qty": 5, "score": 4 }, { "name": "c", "qty": 2, "score": 8 }]
+        }"#).unwrap().1);
+        let query = Predicate::from_str(r#"{ "items.name": "b" }"#).unwrap();
+        let array_filters = ArrayFilters::from_str(r#"[{ "item.score": { "$gte": 8 } }]"#).unwrap();
+        let update = Update::from_str(r#"{
+            "$set": { "items.$[item].top": true },
+            "$inc": { "items.$[].qty": 10 },
+            "$mul": { "items.$.score": 2 }
+        }"#).unwrap();
+        update.apply_with_filters(&mut document, Some(&query), &array_filters, &mut Context::new()).unwrap();
+        let query = Predicate::from_str(r#"{ "grades": 80 }"#).unwrap();
+        let update = Update::from_str(r#"{ "$set": { "grades.$": 82 } }"#).unwrap();
+        update.apply_with_filters(&mut document, Some(&query), &ArrayFilters::default(), &mut Context::new()).unwrap();
+
+        let expected = value(r#"{
+            "grades": [85, 82, 80],
+            "items": [{ "name": "a", "qty": 11, "score": 9, "top": true }, { "name": "b", "qty": 15, "score": 8 }, { "name": "c", "qty": 12, "score": 8, "top": true }]
+        }"#).unwrap().1;
+        assert_eq!(expected, document);
+        assert_eq!("items.$[item].top", field(r#""items.$[item].top""#).unwrap().1.to_string());
+
+        let update = Update::from_str(r#"{ "$set": { "items.$[missing].top": false } }"#).unwrap();
+        assert!(update.apply(&mut document).is_err());
+    }
+
+    #[test]
+    fn upsert_update() {
+        let query = Predicate::from_str(r#"{ "sku": "abc", "info.color": { "$eq": "red" }, "qty": { "$gt": 5 }, "$and": [{ "warehouse": "A" }] }"#).unwrap();
+        let update = Update::from_str(r#"{ "$set": { "price": 10 }, "$setOnInsert": { "created": true }, "$inc": { "qty": 1 } }"#).unwrap();
+
+        let mut documents = vec![Dynamic::from(&value(r#"{ "sku": "xyz", "qty": 1 }"#).unwrap().1)];
+        assert!(update.upsert(&mut documents, &query).unwrap());
+        let expected = value(r#"{ "sku": "abc", "info": { "color": "red" }, "warehouse": "A", "price": 10, "created": true, "qty": 1 }"#).unwrap().1;
+        assert_eq!(expected, documents[1]);
+
+        let mut documents = vec![Dynamic::from(&value(r#"{ "sku": "abc", "info": { "color": "red" }, "qty": 7, "warehouse": "A" }"#).unwrap().1)];
+        assert!(!update.upsert(&mut documents, &query).unwrap());
+        let expected = value(r#"{ "sku": "abc", "info": { "color": "red" }, "qty": 8, "warehouse": "A", "price": 10 }"#).unwrap().1;
+        assert_eq!(expected, documents[0]);
+    }
+
+    #[test]
+    fn json_patch() {
+        let parse = |str: &str| Dynamic::from(&value(str).unwrap().1);
+        let mut document = parse(r#"{ "foo": { "bar": "baz", "waldo": "fred" }, "qux": { "corge": "grault" }, "list": [1, 2, 3] }"#);
+        let patch = JsonPatch::try_from(&parse(r#"[
+            { "op": "test", "path": "/foo/bar", "value": "baz" },
+            { "op": "add", "path": "/list/1", "value": 9 },
+            { "op": "add", "path": "/list/-", "value": 4 },
+            { "op": "remove", "path": "/list/0" },
+            { "op": "replace", "path": "/qux/corge", "value": { "a~b": true } },
+            { "op": "copy", "from": "/qux/corge", "path": "/copied" },
+            { "op": "move", "from": "/foo/waldo", "path": "/qux/thud" },
+            { "op": "add", "path": "/copied/a~0b", "value": false }
+        ]"#)).unwrap();
+        let original = parse(r#"{ "foo": { "bar": "baz", "waldo": "fred" }, "qux": { "corge": "grault" }, "list": [1, 2, 3] }"#);
+        patch.apply(&mut document).unwrap();
+        let expected = value(r#"{ "foo": { "bar": "baz" }, "qux": { "corge": { "a~b": true }, "thud": "fred" }, "list": [9, 2, 3, 4], "copied": { "a~b": false } }"#).unwrap().1;
+        assert_eq!(expected, document);
+
+        let generated = JsonPatch::diff(&original, &document);
+        let mut patched = original.clone();
+        JsonPatch::try_from(&Dynamic::from(&generated)).unwrap().apply(&mut patched).unwrap();
+        assert_eq!(expected, patched);
+
+        let failing = JsonPatch::try_from(&parse(r#"[{ "op": "remove", "path": "/list/0" }, { "op": "test", "path": "/list/0", "value": 1 }]"#)).unwrap();
+        assert!(failing.apply(&mut document).is_err());
+        assert_eq!(expected, document);
+
+        let target = parse(r#"{ "title": "Goodbye!", "author": { "givenName": "John", "familyName": "Doe" }, "tags": ["example", "sample"] }"#);
+        let patch = parse(r#"{ "title": "Hello!", "phoneNumber": "+01-123-456-7890", "author": { "familyName": null }, "tags": ["example"] }"#);
+        let merged = merge_patch(&target, &patch);
+        let expected = value(r#"{ "title": "Hello!", "author": { "givenName": "John" }, "tags": ["example"], "phoneNumber": "+01-123-456-7890" }"#).unwrap().1;
+        assert_eq!(expected, merged);
+        assert_eq!(expected, merge_patch(&target, &merge_patch_diff(&target, &merged)));
+
+        let script = Script::from_str(r#"{ "$mergePatch": [{ "$patch": ["$doc", [{ "op": "add", "path": "/b", "value": 2 }]] }, { "a": null }] }"#).unwrap();
+        let result = script.eval_with_root(parse(r#"{ "doc": { "a": 1 } }"#)).unwrap();
+        assert_eq!(value(r#"{ "b": 2 }"#).unwrap().1, result);
+    }
+
+    #[test]
+    fn structural_diff() {
+        let parse = |str: &str| Dynamic::from(&value(str).unwrap().1);
+        let from = Dynamic::from(TestObj { field1: "a".into(), field2: TestObj2 { field3: 1, field4: true } });
+        let to = parse(r#"{ "field1": "b", "field2": { "field3": 1, "field4": false, "field5": null } }"#);
+        let changes = from.diff(&to);
+        assert_eq!(changes, vec![
+            Change::Changed { path: Some(field(r#""field1""#).unwrap().1.path().clone()), from: parse(r#""a""#), to: parse(r#""b""#) },
+            Change::Changed { path: Some(field(r#""field2.field4""#).unwrap().1.path().clone()), from: parse("true"), to: parse("false") },
+            Change::Added { path: Some(field(r#""field2.field5""#).unwrap().1.path().clone()), value: Dynamic::Null },
+        ]);
+
+        let from = parse(r#"{ "items": [{ "id": 1, "qty": 1 }, { "id": 2, "qty": 2 }, { "id": 3, "qty": 3 }] }"#);
+        let to = parse(r#"{ "items": [{ "id": 2, "qty": 5 }, { "id": 3, "qty": 3 }, { "id": 4, "qty": 4 }] }"#);
+        let changes = from.diff_with(&to, &ArrayDiff::Key(field(r#""id""#).unwrap().1)).iter().map(Dynamic::from).collect::<Vec<_>>();
+        let expected = value(r#"[
+            { "op": "removed", "path": "items[0]", "value": { "id": 1, "qty": 1 } },
+            { "op": "changed", "path": "items[0].qty", "from": 2, "to": 5 },
+            { "op": "added", "path": "items[2]", "value": { "id": 4, "qty": 4 } }
+        ]"#).unwrap().1;
+        assert_eq!(expected, Dynamic::from(changes));
+
+        let script = Script::from_str(r#"{ "$diff": { "from": "$old", "to": "$new", "arrays": "lcs" } }"#).unwrap();
+        let result = script.eval_with_root(parse(r#"{ "old": { "tags": ["a", "b", "c"] }, "new": { "tags": ["a", "c", "d"] } }"#)).unwrap();
+        let expected = value(r#"[
+            { "op": "removed", "path": "tags[1]", "value": "b" },
+            { "op": "added", "path": "tags[2]", "value": "d" }
+        ]"#).unwrap().1;
+        assert_eq!(expected, result);
+
+        // Changes apply in order, so moved items are removed and added again
+        let from = parse(r#"[{ "id": 1, "qty": 1 }, { "id": 2, "qty": 2 }, { "id": 3, "qty": 3 }]"#);
+        let to = parse(r#"[{ "id": 3, "qty": 3 }, { "id": 1, "qty": 4 }]"#);
+        let changes = from.diff_with(&to, &ArrayDiff::Key(field(r#""id""#).unwrap().1));
+        assert_eq!(changes[0], Change::Item { index: 2, change: Box::new(Change::Removed { path: None, value: parse(r#"{ "id": 3, "qty": 3 }"#) }) });
+        let expected = value(r#"[
+            { "op": "removed", "path": "[2]", "value": { "id": 3, "qty": 3 } },
+            { "op": "removed", "path": "[1]", "value": { "id": 2, "qty": 2 } },
+            { "op": "added", "path": "[0]", "value": { "id": 3, "qty": 3 } },
+            { "op": "changed", "path": "[1].qty", "from": 1, "to": 4 }
+        ]"#).unwrap().1;
+        assert_eq!(expected, Dynamic::from(changes.iter().map(Dynamic::from).collect::<Vec<_>>()));
+        let changes = parse("[[1, 2], 3, 4]").diff(&parse("[[1, 5]]")).iter().map(Dynamic::from).collect::<Vec<_>>();
+        let expected = value(r#"[
+            { "op": "removed", "path": "[2]", "value": 4 },
+            { "op": "removed", "path": "[1]", "value": 3 },
+            { "op": "changed", "path": "[0][1]", "from": 2, "to": 5 }
+        ]"#).unwrap().1;
+        assert_eq!(expected, Dynamic::from(changes));
+
+        let from = Dynamic::from((0..2000).map(|index| Dynamic::from(index % 7)).collect::<Vec<_>>());
+        let mut items = from.as_array().unwrap().to_vec();
+        items.insert(1500, Dynamic::from(10));
+        items.remove(10);
+        let changes = from.diff_with(&Dynamic::from(items), &ArrayDiff::Lcs);
+        assert_eq!(changes, vec![
+            Change::Item { index: 10, change: Box::new(Change::Removed { path: None, value: Dynamic::from(3) }) },
+            Change::Item { index: 1499, change: Box::new(Change::Added { path: None, value: Dynamic::from(10) }) },
+        ]);
+    }
+
+    #[test]
+    fn json_path_query() {
+        let store = Dynamic::from(&value(r#"{ "store": {
+            "book": [
+                { "category": "reference", "author": "Nigel Rees", "title": "Sayings of the Century", "price": 8.95 },
+                { "category": "fiction", "author": "Evelyn Waugh", "title": "Sword of Honour", "price": 12.99 },
+                { "category": "fiction", "author": "Herman Melville", "title": "Moby Dick", "isbn": "0-553-21311-3", "price": 8.99 },
+                { "category": "fiction", "author": "J. R. R. Tolkien", "title": "The Lord of the Rings", "isbn": "0-395-19395-8", "price": 22.99 }
+            ],
+            "bicycle": { "color": "red", "price": 399 }
+        } }"#).unwrap().1);
+        let query = |path: &str| Dynamic::from(store.json_path(&JsonPath::from_str(path).unwrap()));
+        let expected = |str: &str| value(str).unwrap().1;
+
+        assert_eq!(expected(r#"["Sayings of the Century", "Moby Dick"]"#), query("$.store.book[?@.price < 10].title"));
+        assert_eq!(expected(r#"["Nigel Rees", "Evelyn Waugh", "Herman Melville", "J. R. R. Tolkien"]"#), query("$..author"));
+        assert_eq!(expected(r#"[8.95, 12.99, 8.99, 22.99, 399]"#), query("$.store..price"));
+        assert_eq!(expected(r#"["The Lord of the Rings", "Sword of Honour"]"#), query("$..book[-1:0:-2].title"));
+        assert_eq!(expected(r#"["Sayings of the Century", "Sword of Honour"]"#), query("$['store']['book'][:2]['title']"));
+        assert_eq!(expected(r#"["Moby Dick"]"#), query("$..book[?@.isbn && !(@.price > 10 || search(@.author, 'Tolk'))].title"));
+        assert_eq!(expected(r#"["Herman Melville"]"#), query(r#"$.store.book[?match(@.title, 'M.*k') && length(@.author) == 15].author"#));
+        assert_eq!(expected(r#"[{ "color": "red", "price": 399 }]"#), query("$.store[?count(@.*) == 2 && @.color == 'red']"));
+        assert_eq!(expected(r#""Moby Dick""#), store.pointer("/store/book/2/title").unwrap());
+
+        let script = Script::from_str(r#"{ "$jsonPath": ["$store", "$.book[?@.category != 'fiction'].price"] }"#).unwrap();
+        assert_eq!(expected("[8.95]"), script.eval_with_root(store.clone()).unwrap());
+    }
+
+    #[test]
+    fn multi_valued_paths() {
+        let document = Dynamic::from(&value(r#"{
+            "orders": [
+                { "id": 1, "items": [{ "sku": "a", "qty": 2 }, { "sku": "b", "qty": 5 }] },
+                { "id": 2, "items": [{ "sku": "c", "qty": 1 }] },
+                { "id": 3, "items": [] }
+            ],
+            "totals": { "north": 10, "south": 20 }
+        }"#).unwrap().1);
+        let resolve = |path: &str| field(&format!("\"{path}\"")).unwrap().1.resolve(&document);
+        let expected = |str: &str| value(str).unwrap().1;
+
+        assert_eq!(expected("3"), resolve("orders[-1].id"));
+        assert_eq!(expected("[1, 2]"), resolve("orders[:-1].id"));
+        assert_eq!(expected("[2, 5, 1]"), resolve("orders[*].items[*].qty"));
+        assert_eq!(expected("[10, 20]"), resolve("totals.*"));
+        assert_eq!(expected(r#"["a", "b", "c"]"#), resolve("orders..sku"));
+        assert_eq!("orders[1:].items[*]..sku", field(r#""orders[1:].items[*]..sku""#).unwrap().1.to_string());
+
+        let mut context = Context::new();
+        let test = |str: &str, context: &mut Context| Predicate::from_str(str).unwrap().test_with_context(document.clone(), context).unwrap();
+        assert!(test(r#"{ "orders[*].items[*].sku": "c" }"#, &mut context));
+        assert!(test(r#"{ "orders..qty": { "$gt": 4 } }"#, &mut context));
+        assert!(!test(r#"{ "orders[1:].items[*].qty": { "$gt": 4 } }"#, &mut context));
+        assert!(!test(r#"{ "orders[*].items[*].qty": { "$ne": 2 } }"#, &mut context));
+        assert!(test(r#"{ "orders[*].items[*].qty": { "$ne": 3 } }"#, &mut context));
+        assert!(!test(r#"{ "orders[*].items[*].sku": { "$not": { "$in": ["c", "d"] } } }"#, &mut context));
+        assert!(test(r#"{ "orders[*].items[*].qty": { "$gt": 4, "$ne": 3 } }"#, &mut context));
+        assert!(!test(r#"{ "orders[*].items[*].qty": { "$gt": 4, "$ne": 1 } }"#, &mut context));
+        assert!(test(r#"{ "orders[*].missing": { "$ne": 1 } }"#, &mut context));
+
+        let update = Update::from_str(r#"{ "$inc": { "orders[*].items[-1].qty": 10 } }"#).unwrap();
+        let mut orders = Dynamic::from(&value(r#"{ "orders": [{ "items": [{ "qty": 1 }, { "qty": 2 }] }, { "items": [{ "qty": 3 }] }] }"#).unwrap().1);
+        update.apply(&mut orders).unwrap();
+        assert_eq!(expected(r#"{ "orders": [{ "items": [{ "qty": 1 }, { "qty": 12 }] }, { "items": [{ "qty": 13 }] }] }"#), orders);
+    }
+
+    #[test]
+    fn quoted_path_segments() {
+        let document = Dynamic::from(&value(r#"{ "hosts": { "example.com": { "http.status": [200, 404] } }, "$price": 5, "a[0]": { "": 1 } }"#).unwrap().1);
+        let parse = |path: &str| field(&serde_json::to_string(path).unwrap()).unwrap().1;
+        let expected = |str: &str| value(str).unwrap().1;
+
+        assert_eq!(expected("404"), parse(r#"hosts["example.com"]["http.status"][1]"#).resolve(&document));
+        assert_eq!(expected("[200, 404]"), parse(r#"hosts..["http.status"]"#).resolve(&document).get_array_item(0).unwrap());
+        assert_eq!(expected("1"), parse(r#"["a[0]"][""]"#).resolve(&document));
+
+        for path in [r#"hosts["example.com"]["http.status"][1]"#, r#"["$price"]"#, r#"["a[0]"][""]"#, r#"a..["b.c"].*["*"]"#, r#"a["b]\"c"]"#] {
+            assert_eq!(path, parse(path).to_string());
+        }
+
+        let query = Predicate::from_str(r#"{ "[\"$price\"]": { "$lt": 10 }, "hosts[\"example.com\"][\"http.status\"][1]": 404 }"#).unwrap();
+        assert!(query.test_with_context(document, &mut Context::new()).unwrap());
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let original = Dynamic::from(&value(r#"{ "a": { "b": [1, 2] }, "c": 3 }"#).unwrap().1);
+        let mut copy = original.clone();
+        copy.get_object_field_mut("a").unwrap().get_object_field_mut("b").unwrap().push_array_item(Dynamic::from(3)).unwrap();
+        copy.set_object_field("c", Dynamic::from(4)).unwrap();
+
+        assert_eq!(value(r#"{ "a": { "b": [1, 2] }, "c": 3 }"#).unwrap().1, original);
+        assert_eq!(value(r#"{ "a": { "b": [1, 2, 3] }, "c": 4 }"#).unwrap().1, copy);
+
+        let update = Update::from_str(r#"{ "$set": { "a.b.0": 0 } }"#).unwrap();
+        let mut updated = original.clone();
+        update.apply(&mut updated).unwrap();
+        assert_eq!(value(r#"{ "a": { "b": [1, 2] }, "c": 3 }"#).unwrap().1, original);
+        assert_eq!(value(r#"{ "a": { "b": [0, 2] }, "c": 3 }"#).unwrap().1, updated);
+    }
+
+    #[test]
+    fn freeze_and_identity() {
+        let original = Dynamic::from(&value(r#"{ "a": { "b": [1, 2] }, "c": "text" }"#).unwrap().1);
+        let shared = original.clone();
+        let copy = original.deep_clone();
+        assert!(original.ptr_eq(&shared));
+        assert!(!original.ptr_eq(&copy));
+        assert!(!original.get_object_field("c").unwrap().ptr_eq(&copy.get_object_field("c").unwrap()));
+        assert_eq!(original, copy);
+
+        let mut frozen = original.freeze();
+        assert!(frozen.is_frozen() && !original.is_frozen());
+        assert!(frozen.ptr_eq(&frozen.freeze()));
+        assert!(matches!(frozen.set_object_field("c", Dynamic::Null), Err(DynamicError::ImmutableObject)));
+        assert!(matches!(frozen.remove_object_field("a"), Err(DynamicError::ImmutableObject)));
+        let mut items = frozen.get_object_field("a").unwrap().get_object_field("b").unwrap();
+        assert!(matches!(items.push_array_item(Dynamic::Null), Err(DynamicError::ImmutableObject)));
+        assert!(Update::from_str(r#"{ "$set": { "a.b.0": 0 } }"#).unwrap().apply(&mut frozen).is_err());
+        assert_eq!(original, frozen);
+
+        let mut thawed = frozen.deep_clone();
+        thawed.set_object_field("c", Dynamic::Null).unwrap();
+        assert!(!thawed.is_frozen());
+    }
+
+    #[test]
+    fn total_order() {
+        let parse = |str: &str| Dynamic::from(&value(str).unwrap().1);
+        let nan = Dynamic::from(Number::Float(f64::NAN));
+        let mut values = vec![
+            parse("true"), parse(r#"[1, "a"]"#), parse(r#"{ "a": 2 }"#), parse(r#""b""#), parse("2.5"),
+            parse("null"), nan.clone(), parse(r#"{ "a": 1, "b": 1 }"#), parse("-3"), parse(r#""a""#), parse("[1]"),
+        ];
+        values.sort_by(Dynamic::total_cmp);
+        let expected = parse(r#"[null, null, -3, 2.5, "a", "b", { "a": 1, "b": 1 }, { "a": 2 }, [1], [1, "a"], true]"#);
+        assert_eq!(expected.as_array().unwrap().len(), values.len());
+        assert!(values[1].as_number().is_some_and(|number| number.as_f64().is_nan()));
+        values.remove(1);
+        let mut expected = expected.as_array().unwrap().to_vec();
+        expected.remove(1);
+        assert_eq!(expected, values);
+
+        assert_eq!(Number::Int(i64::MAX).total_cmp(&Number::Float(9.223372036854776e18)), Ordering::Less);
+        assert_eq!(Number::Int(2).total_cmp(&Number::Float(2.0)), Ordering::Equal);
+        assert_eq!(Number::Int(-2).total_cmp(&Number::Float(-1.5)), Ordering::Less);
+
+        let set: HashSet<OrdDynamic> = [parse("1"), parse("1.0"), nan.clone(), nan, parse(r#"{ "a": [1.0] }"#), parse(r#"{ "a": [1] }"#), parse("-0.0"), parse("0")]
+            .into_iter()
+            .map(OrdDynamic)
+            .collect();
+        assert_eq!(4, set.len());
+
+        let pipeline = Pipeline::from_str(r#"[{ "$sortByCount": "$size" }]"#).unwrap();
+        let documents = [r#"{ "size": 1 }"#, r#"{ "size": 1.0 }"#, r#"{ "size": 2 }"#].map(parse).to_vec();
+        let expected = value(r#"[{ "_id": 1, "count": 2 }, { "_id": 2, "count": 1 }]"#).unwrap().1;
+        assert_eq!(expected, Dynamic::from(pipeline.execute(documents).unwrap()));
+    }
+
+    #[test]
+    fn object_comparison() {
+        let parse = |str: &str| Dynamic::from(&value(str).unwrap().1);
+        let first = parse(r#"{ "a": 1, "b": [{ "c": 2, "d": 3 }] }"#);
+        let second = parse(r#"{ "b": [{ "d": 3, "c": 2 }], "a": 1 }"#);
+        assert_ne!(first, second);
+        assert!(first.eq_with(&second, ObjectComparison::Unordered));
+        assert!(!first.eq_with(&second, ObjectComparison::Ordered));
+        assert_eq!(value(r#"{ "b": [{ "d": 3, "c": 2 }], "a": 1 }"#).unwrap().1, second);
+        assert_ne!(value(r#"{ "b": [{ "d": 3, "c": 2 }], "a": 1 }"#).unwrap().1, first);
+        assert_ne!(first, parse(r#"{ "a": 1 }"#));
+        assert_eq!(Some(Ordering::Less), first.partial_cmp(&second));
+        assert_eq!(Some(Ordering::Equal), first.compare_with(&second, ObjectComparison::Unordered));
+
+        let object = |field1: &str, field3| Dynamic::from(TestObj {
+            field1: field1.into(),
+            field2: TestObj2 { field3, field4: true },
+        });
+        assert_eq!(Some(Ordering::Less), object("a", 1).partial_cmp(&object("b", 1)));
+        assert_eq!(Some(Ordering::Greater), object("a", 2).partial_cmp(&object("a", 1)));
+        let map = parse(r#"{ "field2": { "field4": true, "field3": 1 }, "field1": "a" }"#);
+        assert_ne!(object("a", 1), map);
+        assert!(object("a", 1).eq_with(&map, ObjectComparison::Unordered));
+
+        let document = parse(r#"{ "size": { "h": 14, "w": 21 } }"#);
+        let predicate = Predicate::from_str(r#"{ "size": { "$eq": { "w": 21, "h": 14 } } }"#).unwrap();
+        let mut context = Context::new();
+        assert!(!predicate.test_with_context(document.clone(), &mut context).unwrap());
+        context.set_object_comparison(ObjectComparison::Unordered);
+        assert!(predicate.test_with_context(document.clone(), &mut context).unwrap());
+        let predicate = Predicate::from_str(r#"{ "size": { "$in": [{ "h": 14, "w": 21 }] } }"#).unwrap();
+        assert!(predicate.test_with_context(document.clone(), &mut context).unwrap());
+
+        let script = Script::from_str(r#"{ "$eq": ["$size", { "w": 21, "h": 14 }] }"#).unwrap();
+        let mut context = Context::from([("ROOT", document.clone())]);
+        assert_eq!(Dynamic::Bool(false), script.eval_with_context(&mut context).unwrap());
+        context.set_object_comparison(ObjectComparison::Unordered);
+        assert_eq!(Dynamic::Bool(true), script.eval_with_context(&mut context).unwrap());
+
+        let documents = [parse(r#"{ "_id": { "a": 1, "b": 2 } }"#), parse(r#"{ "_id": { "b": 2, "a": 1 } }"#)];
+        let pipeline = Pipeline::from_str(r#"[{ "$sort": { "_id": 1 } }, { "$group": { "_id": null, "max": { "$max": "$_id" } } }]"#).unwrap();
+        let result = pipeline.execute(documents.clone()).unwrap();
+        assert_eq!(parse(r#"[{ "_id": null, "max": { "b": 2, "a": 1 } }]"#), Dynamic::from(result));
+        let result = pipeline.execute_with_context(documents, &mut context).unwrap();
+        assert_eq!(parse(r#"[{ "_id": null, "max": { "a": 1, "b": 2 } }]"#), Dynamic::from(result));
+    }
+
+    #[test]
+    fn derive_dynamic_object() {
+        #[derive(Clone, DynamicObject)]
+        struct Dimensions {
+            width: f64,
+            height: f64,
+        }
+        #[derive(Clone, DynamicObject)]
+        #[dynamic(tag = "type")]
+        enum Availability {
+            #[dynamic(rename = "out_of_stock")]
+            OutOfStock,
+            InStock { quantity: u32, warehouses: Vec<std::string::String> },
+            Ordered(Dimensions),
+        }
+        #[derive(Clone, DynamicObject)]
+        struct Item {
+            #[dynamic(rename = "_id")]
+            id: i32,
+            name: &'static str,
+            #[dynamic(skip)]
+            #[allow(dead_code)]
+            secret: std::string::String,
+            #[dynamic(flatten)]
+            dimensions: Dimensions,
+            tags: Vec<Option<String>>,
+            discount: Option<f64>,
+            stock: Availability,
+        }
+
+        let item = |id, stock| Item {
+            id,
+            name: "box",
+            secret: "secret".into(),
+            dimensions: Dimensions { width: 1.5, height: 2.0 },
+            tags: vec![Some("a".into()), None],
+            discount: None,
+            stock,
+        };
+        let first = item(1, Availability::InStock { quantity: 3, warehouses: vec!["A".into()] });
+        assert_eq!(&["_id", "name", "width", "height", "tags", "discount", "stock"], first.fields().as_ref());
+        let expected = value(r#"{ "_id": 1, "name": "box", "width": 1.5, "height": 2.0, "tags": ["a", null], "discount": null, "stock": { "type": "InStock", "quantity": 3, "warehouses": ["A"] } }"#).unwrap().1;
+        assert!(expected.eq_with(&Dynamic::from(first.clone()), ObjectComparison::Ordered));
+        assert!(first.get_field("secret").is_none());
+
+        let second = item(2, Availability::Ordered(Dimensions { width: 3.0, height: 4.0 }));
+        assert_eq!(value(r#"{ "type": "Ordered", "width": 3.0, "height": 4.0 }"#).unwrap().1, second.get_field("stock").unwrap());
+        let third = item(3, Availability::OutOfStock);
+        assert_eq!(value(r#"{ "type": "out_of_stock" }"#).unwrap().1, third.get_field("stock").unwrap());
+
+        let pipeline = Pipeline::from_str(r#"[{ "$match": { "stock.type": { "$ne": "out_of_stock" }, "width": { "$gt": 1 } } }]"#).unwrap();
+        let documents = [first, second, third].map(Dynamic::from).to_vec();
+        let ids = pipeline.execute(documents).unwrap().iter().map(|document| document.get_object_field("_id").unwrap()).collect::<Vec<_>>();
+        assert_eq!(value("[1, 2]").unwrap().1, Dynamic::from(ids));
+    }
+
+    #[test]
+    fn borrowed_views() {
+        /// Map-like object which can only be tested through views of its fields
+        struct Headers(Vec<(std::string::String, std::string::String)>);
+
+        impl AsDynamicObjectMut for Headers {}
+
+        impl DynamicObject for Headers {
+            fn get_field(&self, _field: &str) -> Option<Dynamic> {
+                panic!("fields are copied");
+            }
+
+            fn fields(&self) -> Cow<'_, [&str]> {
+                Cow::Owned(self.0.iter().map(|(name, _)| name.as_str()).collect())
+            }
+
+            fn get_field_ref(&self, field: &str) -> Option<DynamicRef<'_>> {
+                self.0.iter().find(|(name, _)| name == field).map(|(_, value)| DynamicRef::String(value))
+            }
+        }
+
+        let headers = Headers(vec![("host".into(), "example.com".into()), ("accept".into(), "*/*".into())]);
+        assert_eq!(&["host", "accept"], headers.fields().as_ref());
+        let document = Dynamic::from(hashlink::LinkedHashMap::from_iter([("headers".into(), Dynamic::from(headers))]));
+        let test = |str: &str| Predicate::from_str(str).unwrap().test_with_context(document.clone(), &mut Context::new()).unwrap();
+        assert!(test(r#"{ "headers.host": "example.com" }"#));
+        assert!(test(r#"{ "headers.accept": { "$eq": "*/*" } }"#));
+        assert!(!test(r#"{ "headers.host": "example.org" }"#));
+        assert!(test(r#"{ "headers.missing": null }"#));
+        assert!(test(r#"{ "headers": { "$eq": { "host": "example.com", "accept": "*/*" } } }"#));
+
+        let object = TestObj {
+            field1: "value".into(),
+            field2: TestObj2 { field3: 12, field4: true },
+        };
+        let Some(DynamicRef::String(field1)) = object.get_field_ref("field1") else {
+            panic!("field1 is not borrowed");
+        };
+        assert!(std::ptr::eq(field1, object.field1.as_str()));
+        let Some(DynamicRef::Object(field2)) = object.get_field_ref("field2") else {
+            panic!("field2 is not borrowed");
+        };
+        assert_eq!(value(r#"{ "field3": 12, "field4": true }"#).unwrap().1, DynamicRef::Object(field2).to_dynamic());
+        let test = |str: &str| Predicate::from_str(str).unwrap().test_with_context(object.clone(), &mut Context::new()).unwrap();
+        assert!(test(r#"{ "field2.field3": 12 }"#));
+        assert!(test(r#"{ "field2": { "field4": true, "field3": 12 } }"#));
+        assert!(!test(r#"{ "field2.field4": false }"#));
+    }
+
+    #[test]
+    fn dynamic_arrays() {
+        use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+        use std::sync::Arc;
+
+        /// Squares of the indices, counting how many items were requested
+        struct Squares(usize, Arc<AtomicUsize>);
+
+        impl DynamicArray for Squares {
+            fn len(&self) -> usize {
+                self.0
+            }
+
+            fn get(&self, index: usize) -> Option<Dynamic> {
+                self.1.fetch_add(1, AtomicOrdering::Relaxed);
+                (index < self.0).then(|| Dynamic::from((index * index) as i64))
+            }
+        }
+
+        let requested = Arc::new(AtomicUsize::new(0));
+        let squares = Dynamic::Array(Array::from(Squares(1_000_000, requested.clone())));
+        let document = Dynamic::from(hashlink::LinkedHashMap::from_iter([("squares".into(), squares)]));
+        let test = |str: &str| Predicate::from_str(str).unwrap().test_with_context(document.clone(), &mut Context::new()).unwrap();
+        assert!(test(r#"{ "squares": { "$size": 1000000 } }"#));
+        assert!(test(r#"{ "squares": { "$isEmpty": false } }"#));
+        assert!(test(r#"{ "squares[3]": 9 }"#));
+        assert!(test(r#"{ "squares[-1]": { "$gt": 999997000002 } }"#));
+        assert!(test(r#"{ "squares": { "$elemMatch": { "$gt": 10, "$lt": 20 } } }"#));
+        assert_eq!(7, requested.load(AtomicOrdering::Relaxed));
+        let slice = field(r#""squares[1:4]""#).unwrap().1.resolve(&document);
+        assert_eq!(value("[1, 4, 9]").unwrap().1, slice);
+        let small = Dynamic::Array(Array::from(Squares(3, requested.clone())));
+        let document = Dynamic::from(hashlink::LinkedHashMap::from_iter([("squares".into(), small)]));
+        let test = |str: &str| Predicate::from_str(str).unwrap().test_with_context(document.clone(), &mut Context::new()).unwrap();
+        assert!(test(r#"{ "squares": [0, 1, 4] }"#));
+        assert!(test(r#"{ "squares": { "$eq": [0, 1, 4] } }"#));
+        assert!(!test(r#"{ "squares": [0, 1, 5] }"#));
+        let squares = document.get_object_field("squares").unwrap();
+        let items = Dynamic::from(&json!([0, 1, 4]));
+        assert_eq!(HashSet::from([OrdDynamic(squares.clone()), OrdDynamic(items.clone())]).len(), 1);
+        assert_eq!(items.compare_with(&Dynamic::from(&json!([0, 2])), ObjectComparison::default()), Some(Ordering::Less));
+        assert_eq!(squares.compare_with(&Dynamic::from(&json!([0, 1, 4, 9])), ObjectComparison::default()), Some(Ordering::Less));
+
+        #[derive(Clone, DynamicObject)]
+        struct Event {
+            kind: &'static str,
+            at: i64,
+        }
+        #[derive(Clone, DynamicObject)]
+        struct Session {
+            events: Vec<Event>,
+            tags: Vec<Option<i32>>,
+            names: Vec<String>,
+        }
+
+        let session = Session {
+            events: vec![Event { kind: "start", at: 1 }, Event { kind: "stop", at: 5 }],
+            tags: vec![Some(1), None],
+            names: vec!["a".into(), "b".into()],
+        };
+        assert!(matches!(session.get_field_ref("events"), Some(DynamicRef::Array(_))));
+        assert!(matches!(session.get_field_ref("tags"), Some(DynamicRef::Owned(_))));
+        let test = |str: &str| Predicate::from_str(str).unwrap().test_with_context(session.clone(), &mut Context::new()).unwrap();
+        assert!(test(r#"{ "events": { "$size": 2 } }"#));
+        assert!(test(r#"{ "events[1].kind": "stop" }"#));
+        assert!(test(r#"{ "events": { "$elemMatch": { "kind": "stop", "at": { "$gte": 5 } } } }"#));
+        assert!(!test(r#"{ "events": { "$elemMatch": { "kind": "start", "at": { "$gte": 5 } } } }"#));
+        assert!(test(r#"{ "tags": [1, null] }"#));
+        assert!(test(r#"{ "names": ["a", "b"] }"#));
+        assert!(test(r#"{ "names": { "$eq": ["a", "b"] } }"#));
+        assert!(!test(r#"{ "names": { "$ne": ["a", "b"] } }"#));
+        assert!(!test(r#"{ "names": ["a"] }"#));
+        assert!(test(r#"{ "events": [{ "kind": "start", "at": 1 }, { "kind": "stop", "at": 5 }] }"#));
+
+        let events = Dynamic::Array(Array::from(session.events));
+        assert_eq!(value(r#"[{ "kind": "start", "at": 1 }, { "kind": "stop", "at": 5 }]"#).unwrap().1, events);
+        assert!(events.is_frozen());
+        assert!(matches!(events.clone().push_array_item(Dynamic::Null), Err(DynamicError::ImmutableObject)));
+    }
+
+    #[test]
+    fn mutable_dynamic_object() {
+        #[derive(Clone, Debug, PartialEq, DynamicObject)]
+        #[dynamic(mutable)]
+        struct Limits {
+            max: i32,
+            ratio: f64,
+        }
+
+        #[derive(Clone, Debug, PartialEq, DynamicObject)]
+        #[dynamic(mutable)]
+        struct Service {
+            name: std::string::String,
+            #[dynamic(rename = "tags")]
+            labels: Vec<std::string::String>,
+            owner: Option<std::string::String>,
+            limits: Limits,
+            #[dynamic(skip)]
+            restarts: u32,
+        }
+
+        let mut service = Service {
+            name: "api".into(),
+            labels: vec!["web".into()],
+            owner: Some("ops".into()),
+            limits: Limits { max: 10, ratio: 0.5 },
+            restarts: 3,
+        };
+        let update = Update::from_str(r#"{
+            "$set": { "name": "gateway", "limits.ratio": 0.75 },
+            "$inc": { "limits.max": 5 },
+            "$push": { "tags": "edge" },
+            "$unset": { "owner": "" }
+        }"#).unwrap();
+        update.apply_to(&mut service).unwrap();
+        assert_eq!(service, Service {
+            name: "gateway".into(),
+            labels: vec!["web".into(), "edge".into()],
+            owner: None,
+            limits: Limits { max: 15, ratio: 0.75 },
+            restarts: 3,
+        });
+
+        let error = Update::from_str(r#"{ "$set": { "limits.max": "many" } }"#).unwrap().apply_to(&mut service);
+        assert!(matches!(
+            error,
+            Err(EvalError::DynamicError(DynamicError::TypeMismatch { ref field, expected: "i32" })) if field == "max"
+        ));
+        assert!(Update::from_str(r#"{ "$unset": { "name": "" } }"#).unwrap().apply_to(&mut service).is_err());
+        assert!(Update::from_str(r#"{ "$set": { "unknown": 1 } }"#).unwrap().apply_to(&mut service).is_err());
+        assert_eq!(service.limits.max, 15);
+
+        /// Record whose identifier can't be modified
+        #[derive(Clone)]
+        struct Record {
+            id: i64,
+            name: std::string::String,
+        }
+
+        impl DynamicObject for Record {
+            fn get_field(&self, field: &str) -> Option<Dynamic> {
+                match field {
+                    "id" => Some(Dynamic::from(self.id)),
+                    "name" => Some(Dynamic::from(self.name.as_str())),
+                    _ => None,
+                }
+            }
+
+            fn fields(&self) -> Cow<'_, [&str]> {
+                Cow::Borrowed(&["id", "name"])
+            }
+        }
+
+        impl DynamicObjectMut for Record {
+            fn set_field(&mut self, field: &str, value: Dynamic) -> Result<Option<Dynamic>, DynamicError> {
+                match (field, value.as_string()) {
+                    ("name", Some(name)) => Ok(Some(Dynamic::from(std::mem::replace(&mut self.name, name.to_string())))),
+                    _ => Err(DynamicError::ImmutableObject),
+                }
+            }
+
+            fn remove_field(&mut self, _field: &str) -> Result<Option<Dynamic>, DynamicError> {
+                Err(DynamicError::ImmutableObject)
+            }
+
+            fn clone_object(&self) -> std::sync::Arc<dyn DynamicObject> {
+                std::sync::Arc::new(self.clone())
+            }
+        }
+
+        #[derive(Clone, Debug, PartialEq, DynamicObject)]
+        #[dynamic(mutable)]
+        struct Counter {
+            hits: usize,
+            bytes: u64,
+        }
+
+        let mut counter = Counter { hits: 1, bytes: u64::MAX };
+        assert_eq!(counter.get_field("hits"), Some(Dynamic::from(1)));
+        assert!(matches!(counter.get_field("bytes"), Some(Dynamic::Number(Number::Float(bytes))) if bytes == u64::MAX as f64));
+        assert!(Predicate::from_str(r#"{ "hits": { "$lt": 2 } }"#).unwrap().test_with_context(counter.clone(), &mut Context::new()).unwrap());
+        Update::from_str(r#"{ "$inc": { "hits": 2 } }"#).unwrap().apply_to(&mut counter).unwrap();
+        assert_eq!(counter, Counter { hits: 3, bytes: u64::MAX });
+        assert!(Update::from_str(r#"{ "$inc": { "hits": -4 } }"#).unwrap().apply_to(&mut counter).is_err());
+        assert_eq!(Dynamic::from(u128::MAX), Dynamic::from(u128::MAX as f64));
+        assert_eq!(Dynamic::from(-3isize), Dynamic::from(-3));
+
+        // Only the modified fields are written back
+        let mut record = Record { id: 1, name: "first".into() };
+        Update::from_str(r#"{ "$set": { "name": "second" } }"#).unwrap().apply_to(&mut record).unwrap();
+        assert_eq!((record.id, record.name.as_str()), (1, "second"));
+        assert!(Update::from_str(r#"{ "$inc": { "id": 1 } }"#).unwrap().apply_to(&mut record).is_err());
+        assert!(record.as_mutable().is_some());
+
+        // Shared objects are copied before they are modified
+        let original = Dynamic::from(service.clone());
+        let mut modified = original.clone();
+        assert!(!original.is_frozen());
+        modified.set_object_field("name", "proxy").unwrap();
+        assert_eq!(original.get_object_field("name").unwrap(), Dynamic::from("gateway"));
+        assert_eq!(modified.get_object_field("name").unwrap(), Dynamic::from("proxy"));
+        assert!(matches!(modified.freeze().set_object_field("name", "x"), Err(DynamicError::ImmutableObject)));
+        assert!(matches!(
+            Dynamic::from(TestObj2 { field3: 1, field4: true }).set_object_field("field3", 2),
+            Err(DynamicError::ImmutableObject)
+        ));
+    }
+
+    #[test]
+    fn json_in_place() {
+        let json = serde_json::json!({
+            "id": 7,
+            "customer": { "name": "Ada", "tags": ["vip", "eu"] },
+            "items": [{ "sku": "a1", "qty": 2 }, { "sku": "b2", "qty": 5 }]
+        });
+        let shared = std::sync::Arc::new(json);
+        let document = Dynamic::from_json_shared(shared.clone());
+        let json = shared.as_ref();
+        assert_eq!(document, Dynamic::from(json));
+        assert_eq!(value(&json.to_string()).unwrap().1, document);
+        assert!(document.is_frozen());
+        assert!(!Dynamic::from(json).is_frozen());
+
+        let predicate = Predicate::from_str(r#"{
+            "customer.name": "Ada",
+            "customer.tags": { "$size": 2 },
+            "items": { "$elemMatch": { "qty": { "$gt": 3 } } }
+        }"#).unwrap();
+        assert!(predicate.test_with_context(document.clone(), &mut Context::new()).unwrap());
+        let script = Script::from_str(r#"{ "sku": "$$ROOT.items[1].sku", "tags": "$$ROOT.customer.tags" }"#).unwrap();
+        assert_eq!(
+            value(r#"{ "sku": "b2", "tags": ["vip", "eu"] }"#).unwrap().1,
+            script.eval_with_root(document.clone()).unwrap()
+        );
+
+        let mut customer = document.get_object_field("customer").unwrap();
+        assert!(matches!(customer.set_object_field("name", "Bob"), Err(DynamicError::ImmutableObject)));
+        // Nested objects and arrays read the shared document in place instead of copying it
+        assert_eq!(std::sync::Arc::strong_count(&shared), 3);
+        let tags = customer.get_object_field("tags").unwrap();
+        assert_eq!(std::sync::Arc::strong_count(&shared), 4);
+        assert!(matches!(DynamicRef::from(&tags).get_item(0), Some(DynamicRef::String(tag)) if std::ptr::eq(tag, json["customer"]["tags"][0].as_str().unwrap())));
+        drop((document, customer, tags));
+        assert_eq!(std::sync::Arc::strong_count(&shared), 1);
+        assert_eq!(Dynamic::from_json_shared(serde_json::json!("text")), Dynamic::from("text"));
+    }
+
+    #[test]
+    fn raw_json() {
+        let text = r#"{ "level": "error", "service": { "name": "api", "region": "eu" }, "tags": ["a", "b\"c"], "ms": 12.5, "msg": "line\nbreak" }"#;
+        let document = crate::raw::parse_lazy(text).unwrap();
+        assert!(document.eq_with(&Dynamic::from(&serde_json::from_str::<serde_json::Value>(text).unwrap()), ObjectComparison::Unordered));
+        assert_eq!(document.get_object_field("msg").unwrap(), Dynamic::from("line\nbreak"));
+        assert!(crate::raw::parse_lazy("{ \"a\": 1 } x").is_none());
+        assert_eq!(value("42").unwrap().1, crate::raw::parse_lazy(" 42 ").unwrap());
+
+        let predicate = Predicate::from_str(r#"{ "level": "error", "$or": [{ "service.region": "eu" }, { "ms": { "$gt": 100 } }] }"#).unwrap();
+        assert_eq!(predicate.paths().unwrap().len(), 3);
+        assert!(Predicate::from_str(r#"{ "$size": 2 }"#).unwrap().paths().is_none());
+        let filter = RawFilter::new(&predicate);
+        let lines = [
+            r#"{ "level": "error", "service": { "region": "eu" }, "ignored": [1, {"x": "]"}] }"#,
+            r#"{ "level": "info", "service": { "region": "eu" } }"#,
+            "",
+            r#"{ "ms": 250, "payload": "{\"level\": \"info\"}", "level": "error" }"#,
+            r#"{ "level": "error", "ms": 5 }"#,
+        ]
+        .join("\n");
+        let matching = filter.filter_lines(&lines).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(matching.len(), 2);
+        assert!(matching[1].starts_with(r#"{ "ms": 250"#));
+        assert!(matches!(filter.matches(r#"{ "level": "error", "#), Err(EvalError::InvalidJson)));
+        assert!(matches!(filter.matches(r#"{ "level": nul }"#), Err(EvalError::InvalidJson)));
+        assert!(matches!(filter.matches(r#"{ "level": "error", "service": { "region": tru } }"#), Err(EvalError::InvalidJson)));
+        assert!(matches!(filter.matches(r#"{ "level": "error", "service": { "zone": "\x" } }"#), Err(EvalError::InvalidJson)));
+        assert!(filter.matches(r#"{ "level": "error", "service": { "region": "eu" }, "ignored": [nul] }"#).unwrap());
+        assert!(crate::raw::parse_lazy("tru").is_none());
+        assert!(crate::raw::parse_lazy(r#"{ "a": 1x }"#).unwrap().get_object_field("a").is_none());
+
+        let whole = Predicate::from_str(r#"{ "tags": { "$elemMatch": { "$eq": "b\"c" } }, "service": { "$eq": { "name": "api", "region": "eu" } } }"#).unwrap();
+        assert!(RawFilter::new(&whole).matches(text).unwrap());
+        assert!(matches!(RawFilter::new(&whole).matches(r#"{ "tags": [1, nul] }"#), Err(EvalError::InvalidJson)));
+
+        let tags = Predicate::from_str(r#"{ "tags": ["a", "b"] }"#).unwrap();
+        assert!(RawFilter::new(&tags).matches(r#"{ "tags": ["a", "b"] }"#).unwrap());
+        assert!(!RawFilter::new(&tags).matches(r#"{ "tags": ["a"] }"#).unwrap());
+
+        // The last of duplicate keys wins, whether the fields are projected or not
+        let duplicates = r#"{ "level": "info", "level": "error" }"#;
+        let level = Predicate::from_str(r#"{ "level": "error" }"#).unwrap();
+        assert!(RawFilter::new(&level).matches(duplicates).unwrap());
+        assert!(level.test_with_context(crate::raw::parse_lazy(duplicates).unwrap(), &mut Context::new()).unwrap());
+    }
+
+    #[test]
+    #[cfg(all(feature = "yaml", feature = "toml", feature = "cbor", feature = "msgpack"))]
+    fn data_formats() {
+        let expected = value(r#"{ "name": "api", "replicas": 3, "ratio": 0.5, "tags": ["web", null], "limits": { "cpu": true } }"#).unwrap().1;
+        let yaml = Dynamic::from_yaml("name: api\nreplicas: 3\nratio: 0.5\ntags: [web, ~]\nlimits:\n  cpu: true\n").unwrap();
+        assert_eq!(expected, yaml);
+        assert_eq!(Dynamic::from_yaml(&yaml.to_yaml().unwrap()).unwrap(), yaml);
+        assert!(matches!(yaml.get_object_field("replicas"), Some(Dynamic::Number(Number::Int(3)))));
+
+        let toml = Dynamic::from_toml("name = \"api\"\nreleased = 2024-05-01\n[limits]\ncpu = true\n").unwrap();
+        assert_eq!(value(r#"{ "name": "api", "released": "2024-05-01", "limits": { "cpu": true } }"#).unwrap().1, toml);
+        assert_eq!(Dynamic::from_toml(&toml.to_toml().unwrap()).unwrap(), toml);
+
+        assert_eq!(Dynamic::from_cbor(&yaml.to_cbor().unwrap()).unwrap(), yaml);
+        assert_eq!(Dynamic::from_msgpack(&yaml.to_msgpack().unwrap()).unwrap(), yaml);
+
+        let script = Script::from_yaml("\"$match\":\n  object: $$ROOT\n  predicate:\n    limits.cpu: true\n").unwrap();
+        assert_eq!(Dynamic::Bool(true), script.eval_with_root(yaml.clone()).unwrap());
+        let script = Script::from_toml("[matches.\"$match\"]\nobject = \"$$ROOT\"\npredicate = { replicas = { \"$gt\" = 2 } }\n").unwrap();
+        assert_eq!(value(r#"{ "matches": true }"#).unwrap().1, script.eval_with_root(yaml).unwrap());
+    }
+
+    #[test]
+    fn bson_types() {
+        let document = Dynamic::from(&value(r#"{
+            "_id": { "$oid": "65f1c2a0e4b0a1b2c3d4e5f6" },
+            "created": { "$date": "2024-03-13T17:30:40.500+02:00" },
+            "price": { "$numberDecimal": "19.50" },
+            "payload": { "$binary": { "base64": "AQIDBA==", "subType": "04" } },
+            "version": { "$timestamp": { "t": 1710343840, "i": 2 } },
+            "count": { "$numberLong": "42" }
+        }"#).unwrap().1);
+        let id = ObjectId::from_str("65f1c2a0e4b0a1b2c3d4e5f6").unwrap();
+        assert_eq!(document.get_object_field("_id"), Some(Dynamic::from(id)));
+        assert_eq!(document.get_object_field("created"), Some(Dynamic::from(DateTime(1_710_343_840_500))));
+        assert_eq!(DateTime(1_710_343_840_500).to_string(), "2024-03-13T15:30:40.500Z");
+        assert_eq!(DateTime(-1).to_string(), "1969-12-31T23:59:59.999Z");
+        assert!(matches!(document.get_object_field("payload"), Some(Dynamic::Bson(Bson::Binary(Binary { subtype: 4, bytes }))) if *bytes == [1, 2, 3, 4]));
+        assert!(matches!(document.get_object_field("count"), Some(Dynamic::Number(Number::Int(42)))));
+
+        for (decimal, expected) in [("19.50", "19.50"), ("1E+3", "1E+3"), ("-0.000001", "-0.000001"), ("0.0000001", "1E-7"), ("-inf", "-Infinity")] {
+            assert_eq!(Decimal128::from_str(decimal).unwrap().to_string(), expected);
+        }
+        assert!(matches!(Decimal128::from_str("19.50").unwrap().to_number(), Number::Float(float) if float == 19.5));
+        assert!(matches!(Decimal128::from_str("1.20E+2").unwrap().to_number(), Number::Int(120)));
+        assert!(Decimal128::from_str("10000000000000000000000000000000000E2147483647").is_err());
+        assert!(Predicate::from_str(r#"{ "p": { "$numberDecimal": "10000000000000000000000000000000000E2147483647" } }"#).is_ok());
+
+        let tests = [
+            (r#"{ "_id": { "$oid": "65f1c2a0e4b0a1b2c3d4e5f6" } }"#, true),
+            (r#"{ "created": { "$gt": { "$date": "2024-01-01T00:00:00Z" } } }"#, true),
+            (r#"{ "created": { "$gt": "2024-01-01" } }"#, false),
+            (r#"{ "price": 19.5 }"#, true),
+            (r#"{ "price": { "$lt": { "$numberDecimal": "2E+1" } } }"#, true),
+            (r#"{ "payload": { "$binary": { "base64": "AQIDBA==", "subType": "04" } } }"#, true),
+            (r#"{ "version": { "$gte": { "$timestamp": { "t": 1710343840, "i": 3 } } } }"#, false),
+        ];
+        for (predicate, expected) in tests {
+            assert_eq!(Predicate::from_str(predicate).unwrap().test_with_context(document.clone(), &mut Context::new()).unwrap(), expected, "{}", predicate);
+        }
+
+        let mut values = vec![
+            OrdDynamic(Dynamic::Bool(true)),
+            OrdDynamic(Dynamic::from(DateTime(0))),
+            OrdDynamic(Dynamic::from(id)),
+            OrdDynamic(Dynamic::from(Decimal128::from_str("2.5").unwrap())),
+            OrdDynamic(Dynamic::from(3)),
+        ];
+        values.sort();
+        assert!(matches!(values.as_slice(), [
+            OrdDynamic(Dynamic::Bson(Bson::Decimal128(_))),
+            OrdDynamic(Dynamic::Number(_)),
+            OrdDynamic(Dynamic::Bson(Bson::ObjectId(_))),
+            OrdDynamic(Dynamic::Bool(_)),
+            OrdDynamic(Dynamic::Bson(Bson::DateTime(_))),
+        ]));
+        assert_eq!(OrdDynamic(Dynamic::from(Decimal128::from_str("3.00").unwrap())), OrdDynamic(Dynamic::from(3)));
+        let decimal = |string| Dynamic::from(Decimal128::from_str(string).unwrap());
+        assert_eq!(decimal("9007199254740993"), Dynamic::from(9_007_199_254_740_993i64));
+        assert_ne!(decimal("9007199254740993"), Dynamic::from(9_007_199_254_740_992i64));
+        assert_ne!(decimal("0.1"), decimal("0.1000000000000000000000000000000001"));
+        assert_eq!(decimal("-1.50E+2"), decimal("-150"));
+        assert_eq!(decimal("0.1").compare_with(&decimal("0.1000000000000000000000000000000001"), ObjectComparison::default()), Some(Ordering::Less));
+        assert_eq!(decimal("-2E+1").compare_with(&decimal("-19.99"), ObjectComparison::default()), Some(Ordering::Less));
+        assert_eq!(decimal("9007199254740993").compare_with(&Dynamic::from(9_007_199_254_740_992i64), ObjectComparison::default()), Some(Ordering::Greater));
+        assert_eq!(OrdDynamic(decimal("0")), OrdDynamic(decimal("-0E+5")));
+        assert_eq!(decimal("0.5"), Dynamic::from(0.5));
+
+        assert!(DateTime::from_str("2024-02-29T00:00:00Z").is_ok());
+        assert!(DateTime::from_str("2023-02-29T00:00:00Z").is_err());
+        assert!(DateTime::from_str("2024-02-31T00:00:00Z").is_err());
+        assert!(DateTime::from_str("2024-04-31T00:00:00Z").is_err());
+        assert!(matches!(value(r#"{ "$numberInt": "2147483647" }"#).unwrap().1, Value::Number(Number::Int(2_147_483_647))));
+        assert!(matches!(value(r#"{ "$numberInt": "2147483648" }"#).unwrap().1, Value::Object(_)));
+
+        let script = Script::from_str(r#"{ "$toDate": "$_id" }"#).unwrap();
+        assert_eq!(script.eval_with_root(document.clone()).unwrap(), Dynamic::from(DateTime(1_710_342_816_000)));
+        let script = Script::from_str(r#"{ "$eq": [{ "$toObjectId": "65f1c2a0e4b0a1b2c3d4e5f6" }, "$_id"] }"#).unwrap();
+        assert_eq!(script.eval_with_root(document.clone()).unwrap(), Dynamic::Bool(true));
+        let script = Script::from_str(r#"{ "$gt": [{ "$toDate": "$version" }, { "$date": { "$numberLong": "1710343840000" } }] }"#).unwrap();
+        assert_eq!(script.eval_with_root(document.clone()).unwrap(), Dynamic::Bool(false));
+        let script = Script::from_str(r#"{ "$toObjectId": "65f1" }"#).unwrap();
+        assert!(matches!(script.eval_with_root(document), Err(EvalError::ConversionFailed)));
+
+        let extended_json = value(r#"{ "$binary": { "base64": "AQIDBA==", "subType": "04" } }"#).unwrap().1;
+        assert_eq!(serde_json::Value::from(extended_json), json!({ "$binary": { "base64": "AQIDBA==", "subType": "04" } }));
+    }
+
+    #[test]
+    #[cfg(feature = "bson")]
+    fn bson_documents() {
+        use crate::format::FormatError;
+
+        let document = Dynamic::from(&value(r#"{
+            "_id": { "$oid": "65f1c2a0e4b0a1b2c3d4e5f6" },
+            "created": { "$date": "2024-03-13T15:30:40.500Z" },
+            "price": { "$numberDecimal": "-19.50E+10" },
+            "payload": { "$binary": { "base64": "AQID", "subType": "00" } },
+            "version": { "$timestamp": { "t": 1710343840, "i": 2 } },
+            "tags": ["web", null, 10000000000, 0.5]
+        }"#).unwrap().1);
+        let bytes = document.to_bson().unwrap();
+        assert_eq!(Dynamic::from_bson(&bytes).unwrap(), document);
+        assert!(matches!(Dynamic::from(1).to_bson(), Err(FormatError::NotADocument)));
+
+        let encoded = ::bson::Document::from_reader(&mut bytes.as_slice()).unwrap();
+        assert_eq!(encoded.get_object_id("_id").unwrap().to_hex(), "65f1c2a0e4b0a1b2c3d4e5f6");
+        assert_eq!(encoded.get_decimal128("price").unwrap().to_string(), "-1.950E+11");
+        assert_eq!(encoded.get_datetime("created").unwrap().timestamp_millis(), 1_710_343_840_500);
+        assert_eq!(encoded.get_array("tags").unwrap()[2], ::bson::Bson::Int64(10_000_000_000));
+    }
 }
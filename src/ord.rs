@@ -0,0 +1,177 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use crate::{Array, Dynamic, Number, ObjectComparison};
+use crate::bson::Bson;
+
+/// Wrapper of `Dynamic` with a total order and a consistent hash, so values can be sorted,
+/// deduplicated and used as keys of maps. Objects are compared field by field in their order.
+#[derive(Debug, Clone)]
+pub struct OrdDynamic(pub Dynamic);
+
+impl Number {
+    /// Compares numbers by their value, where NaN is equal to itself and less than any other number
+    pub fn total_cmp(&self, other: &Number) -> Ordering {
+        match (self, other) {
+            (Number::Int(first), Number::Int(second)) => first.cmp(second),
+            (Number::Int(first), Number::Float(second)) => compare_int_float(*first, *second),
+            (Number::Float(first), Number::Int(second)) => compare_int_float(*second, *first).reverse(),
+            (Number::Float(first), Number::Float(second)) => match (first.is_nan(), second.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                (false, false) => first.partial_cmp(second).unwrap_or(Ordering::Equal),
+            },
+        }
+    }
+}
+
+/// Compares an integer with a float exactly, without rounding the integer to a float
+fn compare_int_float(int: i64, float: f64) -> Ordering {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if float.is_nan() {
+        return Ordering::Greater;
+    }
+    if float >= LIMIT {
+        return Ordering::Less;
+    }
+    if float < -LIMIT {
+        return Ordering::Greater;
+    }
+    let truncated = float.trunc();
+    int.cmp(&(truncated as i64)).then_with(|| {
+        let fraction = float - truncated;
+        0.0.partial_cmp(&fraction).unwrap_or(Ordering::Equal)
+    })
+}
+
+impl Dynamic {
+    /// Compares values first by the type order of `comparison_order` and then by their content,
+    /// comparing objects field by field in their order
+    pub fn total_cmp(&self, other: &Dynamic) -> Ordering {
+        self.total_cmp_with(other, ObjectComparison::default())
+    }
+
+    /// Compares values first by the type order of `comparison_order` and then by their content.
+    /// Objects are compared field by field in the order given by `comparison`, then by the number of fields.
+    pub fn total_cmp_with(&self, other: &Dynamic, comparison: ObjectComparison) -> Ordering {
+        match (self, other) {
+            (Dynamic::Null, Dynamic::Null) => Ordering::Equal,
+            (Dynamic::Bool(first), Dynamic::Bool(second)) => first.cmp(second),
+            (Dynamic::Number(first), Dynamic::Number(second)) => first.total_cmp(second),
+            (Dynamic::String(first), Dynamic::String(second)) => first.as_str().cmp(second.as_str()),
+            (Dynamic::Array(Array::Items(first)), Dynamic::Array(Array::Items(second))) => {
+                first.iter().cmp_by(second.iter(), |first, second| first.total_cmp_with(second, comparison))
+            }
+            (Dynamic::Array(first), Dynamic::Array(second)) => {
+                first.iter().cmp_by(second.iter(), |first, second| first.total_cmp_with(&second, comparison))
+            }
+            (Dynamic::Object(first), Dynamic::Object(second)) => {
+                let second = second.comparison_fields(comparison);
+                first.comparison_fields(comparison).into_iter().cmp_by(second, |(first_key, first_value), (second_key, second_value)| {
+                    first_key.cmp(second_key).then_with(|| first_value.total_cmp_with(&second_value, comparison))
+                })
+            }
+            (Dynamic::Bson(Bson::Decimal128(first)), Dynamic::Number(second)) => first.total_cmp_number(second),
+            (Dynamic::Number(first), Dynamic::Bson(Bson::Decimal128(second))) => second.total_cmp_number(first).reverse(),
+            (Dynamic::Bson(first), Dynamic::Bson(second)) if first.comparison_order() == second.comparison_order() => {
+                first.compare(second).unwrap_or(Ordering::Equal)
+            }
+            (first, second) => first.comparison_order().cmp(&second.comparison_order()),
+        }
+    }
+}
+
+impl PartialEq for OrdDynamic {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.total_cmp(&other.0).is_eq()
+    }
+}
+
+impl Eq for OrdDynamic {}
+
+impl PartialOrd for OrdDynamic {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrdDynamic {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for OrdDynamic {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash(&self.0, state)
+    }
+}
+
+/// Hashes the value so that values which are equal in the total order have the same hash.
+/// Fields are hashed in the order of their keys, so it holds for both ways of comparing objects.
+fn hash<H: Hasher>(value: &Dynamic, state: &mut H) {
+    value.comparison_order().hash(state);
+    match value {
+        Dynamic::Null => {}
+        Dynamic::Bool(bool) => bool.hash(state),
+        Dynamic::Number(number) => hash_number(*number, state),
+        Dynamic::String(string) => string.as_str().hash(state),
+        Dynamic::Array(Array::Items(items)) => {
+            items.len().hash(state);
+            for item in items.iter() {
+                hash(item, state);
+            }
+        }
+        Dynamic::Array(array) => {
+            array.len().hash(state);
+            for item in array.iter() {
+                hash(&item, state);
+            }
+        }
+        Dynamic::Object(object) => {
+            for (key, value) in object.comparison_fields(ObjectComparison::Unordered) {
+                key.hash(state);
+                hash(&value, state);
+            }
+        }
+        // Decimals equal to each other or to a number round to the same nearest number
+        Dynamic::Bson(Bson::Decimal128(decimal)) => hash_number(decimal.to_number(), state),
+        Dynamic::Bson(Bson::ObjectId(id)) => id.hash(state),
+        Dynamic::Bson(Bson::DateTime(date)) => date.hash(state),
+        Dynamic::Bson(Bson::Binary(binary)) => binary.hash(state),
+        Dynamic::Bson(Bson::Timestamp(timestamp)) => timestamp.hash(state),
+    }
+}
+
+/// Hashes integral floats as the equal integer, all NaNs are equal
+fn hash_number<H: Hasher>(number: Number, state: &mut H) {
+    match number {
+        Number::Int(int) => int.hash(state),
+        Number::Float(float) if float.fract() == 0.0 && float >= i64::MIN as f64 && float < i64::MAX as f64 => {
+            (float as i64).hash(state)
+        }
+        Number::Float(float) if float.is_nan() => f64::NAN.to_bits().hash(state),
+        Number::Float(float) => float.to_bits().hash(state),
+    }
+}
+
+impl Deref for OrdDynamic {
+    type Target = Dynamic;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Dynamic> for OrdDynamic {
+    fn from(value: Dynamic) -> Self {
+        OrdDynamic(value)
+    }
+}
+
+impl From<OrdDynamic> for Dynamic {
+    fn from(value: OrdDynamic) -> Self {
+        value.0
+    }
+}
@@ -0,0 +1,53 @@
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::patch::json_eq;
+use crate::Dynamic;
+
+/// Applies a JSON Merge Patch (RFC 7396) to the target and returns the patched value.
+/// Members of the patch set to null are removed from the target.
+pub fn merge_patch(target: &Dynamic, patch: &Dynamic) -> Dynamic {
+    let Dynamic::Object(patch) = patch else {
+        return patch.clone();
+    };
+    let mut map = match target {
+        Dynamic::Object(object) => object.to_map(),
+        _ => LinkedHashMap::new(),
+    };
+    for (key, value) in patch.to_map() {
+        if value.is_null() {
+            map.remove(&key);
+        } else {
+            let target = map.get(&key).cloned().unwrap_or(Dynamic::Null);
+            map.replace(key, merge_patch(&target, &value));
+        }
+    }
+
+    Dynamic::from(map)
+}
+
+/// Generates a JSON Merge Patch which transforms the first value into the second one.
+/// Null members of the second value can't be represented and are removed instead.
+pub fn merge_patch_diff(from: &Dynamic, to: &Dynamic) -> Dynamic {
+    let (Dynamic::Object(from), Dynamic::Object(to)) = (from, to) else {
+        return to.clone();
+    };
+    let from = from.to_map();
+    let to = to.to_map();
+    let mut patch = LinkedHashMap::<String, Dynamic>::new();
+    for key in from.keys().filter(|key| !to.contains_key(*key)) {
+        patch.insert(key.clone(), Dynamic::Null);
+    }
+    for (key, value) in &to {
+        match from.get(key) {
+            Some(from_value) if json_eq(from_value, value) => {}
+            Some(from_value) => {
+                patch.insert(key.clone(), merge_patch_diff(from_value, value));
+            }
+            None => {
+                patch.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    Dynamic::from(patch)
+}
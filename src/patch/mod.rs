@@ -0,0 +1,256 @@
+pub mod merge;
+
+use derive_more::From;
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::pointer::{array_index, InvalidPointer, JsonPointer};
+use crate::{Dynamic, DynamicError};
+
+/// JSON Patch (RFC 6902), a sequence of operations which are applied in order
+#[derive(Debug, Clone, PartialEq, Default, From)]
+pub struct JsonPatch(pub Vec<PatchOperation>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOperation {
+    Add { path: JsonPointer, value: Dynamic },
+    Remove { path: JsonPointer },
+    Replace { path: JsonPointer, value: Dynamic },
+    Move { from: JsonPointer, path: JsonPointer },
+    Copy { from: JsonPointer, path: JsonPointer },
+    Test { path: JsonPointer, value: Dynamic },
+}
+
+#[derive(Debug, From)]
+pub enum PatchError {
+    InvalidPatch,
+    InvalidPointer(InvalidPointer),
+    #[from(ignore)]
+    PathNotFound(JsonPointer),
+    #[from(ignore)]
+    TestFailed(JsonPointer),
+    DynamicError(DynamicError),
+}
+
+impl JsonPatch {
+    /// Applies all operations to a copy of the document, which replaces the document only if every operation succeeds
+    pub fn apply(&self, document: &mut Dynamic) -> Result<(), PatchError> {
+        let mut patched = document.clone();
+        for operation in &self.0 {
+            operation.apply(&mut patched)?;
+        }
+        *document = patched;
+
+        Ok(())
+    }
+
+    /// Generates a patch which transforms the first document into the second one
+    pub fn diff(from: &Dynamic, to: &Dynamic) -> JsonPatch {
+        let mut operations = Vec::new();
+        diff(from, to, &mut JsonPointer::default(), &mut operations);
+
+        JsonPatch(operations)
+    }
+}
+
+impl PatchOperation {
+    pub fn apply(&self, document: &mut Dynamic) -> Result<(), PatchError> {
+        match self {
+            PatchOperation::Add { path, value } => add(document, path, value.clone()),
+            PatchOperation::Remove { path } => remove(document, path).map(drop),
+            PatchOperation::Replace { path, value } => {
+                remove(document, path)?;
+                add(document, path, value.clone())
+            }
+            PatchOperation::Move { from, path } => {
+                if from.is_ancestor_of(path) {
+                    return Err(PatchError::InvalidPatch);
+                }
+                let value = remove(document, from)?;
+                add(document, path, value)
+            }
+            PatchOperation::Copy { from, path } => {
+                let value = from.resolve(document).ok_or_else(|| PatchError::PathNotFound(from.clone()))?;
+                add(document, path, value)
+            }
+            PatchOperation::Test { path, value } => {
+                let current = path.resolve(document).ok_or_else(|| PatchError::PathNotFound(path.clone()))?;
+                if !json_eq(&current, value) {
+                    return Err(PatchError::TestFailed(path.clone()));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn add(document: &mut Dynamic, path: &JsonPointer, value: Dynamic) -> Result<(), PatchError> {
+    let Some((parent, token)) = path.split_last() else {
+        *document = value;
+        return Ok(());
+    };
+    let parent = parent.resolve_mut(document).ok_or_else(|| PatchError::PathNotFound(path.clone()))?;
+    match parent {
+        Dynamic::Object(_) => parent.set_object_field(token, value).map(drop)?,
+        Dynamic::Array(array) if token == "-" => {
+            let index = array.len();
+            parent.insert_array_item(index, value)?
+        }
+        Dynamic::Array(_) => {
+            let index = array_index(token).ok_or_else(|| PatchError::PathNotFound(path.clone()))?;
+            parent.insert_array_item(index, value)?
+        }
+        _ => return Err(PatchError::PathNotFound(path.clone())),
+    }
+
+    Ok(())
+}
+
+fn remove(document: &mut Dynamic, path: &JsonPointer) -> Result<Dynamic, PatchError> {
+    let Some((parent, token)) = path.split_last() else {
+        return Ok(std::mem::replace(document, Dynamic::Null));
+    };
+    let not_found = || PatchError::PathNotFound(path.clone());
+    let parent = parent.resolve_mut(document).ok_or_else(not_found)?;
+    match parent {
+        Dynamic::Object(_) => parent.remove_object_field(token)?.ok_or_else(not_found),
+        Dynamic::Array(_) => {
+            let index = array_index(token).ok_or_else(not_found)?;
+            parent.remove_array_item(index).map_err(|_| not_found())
+        }
+        _ => Err(not_found()),
+    }
+}
+
+fn diff(from: &Dynamic, to: &Dynamic, path: &mut JsonPointer, operations: &mut Vec<PatchOperation>) {
+    match (from, to) {
+        (Dynamic::Object(from_object), Dynamic::Object(to_object)) => {
+            let from_map = from_object.to_map();
+            let to_map = to_object.to_map();
+            for key in from_map.keys().filter(|key| !to_map.contains_key(*key)) {
+                operations.push(PatchOperation::Remove { path: child(path, key) });
+            }
+            for (key, to_value) in &to_map {
+                match from_map.get(key) {
+                    Some(from_value) => {
+                        path.push(key.clone());
+                        diff(from_value, to_value, path, operations);
+                        path.0.pop();
+                    }
+                    None => operations.push(PatchOperation::Add { path: child(path, key), value: to_value.clone() }),
+                }
+            }
+        }
+        (Dynamic::Array(from_array), Dynamic::Array(to_array)) => {
+            let (from_array, to_array) = (from_array.items(), to_array.items());
+            let common = from_array.len().min(to_array.len());
+            for (index, (from_value, to_value)) in from_array.iter().zip(to_array.iter()).enumerate() {
+                path.push(index.to_string());
+                diff(from_value, to_value, path, operations);
+                path.0.pop();
+            }
+            for index in (common..from_array.len()).rev() {
+                operations.push(PatchOperation::Remove { path: child(path, &index.to_string()) });
+            }
+            for value in &to_array[common..] {
+                operations.push(PatchOperation::Add { path: child(path, "-"), value: value.clone() });
+            }
+        }
+        (from, to) if json_eq(from, to) => {}
+        (_, to) => operations.push(PatchOperation::Replace { path: path.clone(), value: to.clone() }),
+    }
+}
+
+fn child(path: &JsonPointer, token: &str) -> JsonPointer {
+    let mut path = path.clone();
+    path.push(token);
+    path
+}
+
+/// Compares values as JSON, where the order of object members is not significant
+pub(crate) fn json_eq(first: &Dynamic, second: &Dynamic) -> bool {
+    match (first, second) {
+        (Dynamic::Object(first), Dynamic::Object(second)) => {
+            let first = first.to_map();
+            let second = second.to_map();
+            first.len() == second.len()
+                && first
+                    .iter()
+                    .all(|(key, value)| second.get(key).is_some_and(|other| json_eq(value, other)))
+        }
+        (Dynamic::Array(first), Dynamic::Array(second)) => {
+            first.len() == second.len() && first.iter().zip(second.iter()).all(|(first, second)| json_eq(&first, &second))
+        }
+        (first, second) => first == second,
+    }
+}
+
+impl TryFrom<&Dynamic> for JsonPatch {
+    type Error = PatchError;
+
+    /// Reads a patch from its JSON representation, an array of operation objects
+    fn try_from(value: &Dynamic) -> Result<Self, Self::Error> {
+        let Dynamic::Array(operations) = value else {
+            return Err(PatchError::InvalidPatch);
+        };
+        operations
+            .items()
+            .iter()
+            .map(PatchOperation::try_from)
+            .collect::<Result<_, _>>()
+            .map(JsonPatch)
+    }
+}
+
+impl TryFrom<&Dynamic> for PatchOperation {
+    type Error = PatchError;
+
+    fn try_from(value: &Dynamic) -> Result<Self, Self::Error> {
+        let pointer = |name: &str| -> Result<JsonPointer, PatchError> {
+            match value.get_object_field(name) {
+                Some(Dynamic::String(pointer)) => Ok(pointer.parse()?),
+                _ => Err(PatchError::InvalidPatch),
+            }
+        };
+        let value_field = || value.get_object_field("value").ok_or(PatchError::InvalidPatch);
+        let Some(Dynamic::String(op)) = value.get_object_field("op") else {
+            return Err(PatchError::InvalidPatch);
+        };
+
+        Ok(match op.as_str() {
+            "add" => PatchOperation::Add { path: pointer("path")?, value: value_field()? },
+            "remove" => PatchOperation::Remove { path: pointer("path")? },
+            "replace" => PatchOperation::Replace { path: pointer("path")?, value: value_field()? },
+            "move" => PatchOperation::Move { from: pointer("from")?, path: pointer("path")? },
+            "copy" => PatchOperation::Copy { from: pointer("from")?, path: pointer("path")? },
+            "test" => PatchOperation::Test { path: pointer("path")?, value: value_field()? },
+            _ => return Err(PatchError::InvalidPatch),
+        })
+    }
+}
+
+impl From<&JsonPatch> for Dynamic {
+    fn from(patch: &JsonPatch) -> Self {
+        Dynamic::from(patch.0.iter().map(Dynamic::from).collect::<Vec<_>>())
+    }
+}
+
+impl From<&PatchOperation> for Dynamic {
+    fn from(operation: &PatchOperation) -> Self {
+        let pointer = |pointer: &JsonPointer| Dynamic::from(String::from(pointer.to_string()));
+        let fields = match operation {
+            PatchOperation::Add { path, value } => vec![("op", Dynamic::from(String::from("add"))), ("path", pointer(path)), ("value", value.clone())],
+            PatchOperation::Remove { path } => vec![("op", Dynamic::from(String::from("remove"))), ("path", pointer(path))],
+            PatchOperation::Replace { path, value } => vec![("op", Dynamic::from(String::from("replace"))), ("path", pointer(path)), ("value", value.clone())],
+            PatchOperation::Move { from, path } => vec![("op", Dynamic::from(String::from("move"))), ("from", pointer(from)), ("path", pointer(path))],
+            PatchOperation::Copy { from, path } => vec![("op", Dynamic::from(String::from("copy"))), ("from", pointer(from)), ("path", pointer(path))],
+            PatchOperation::Test { path, value } => vec![("op", Dynamic::from(String::from("test"))), ("path", pointer(path)), ("value", value.clone())],
+        };
+
+        Dynamic::from(
+            fields
+                .into_iter()
+                .map(|(key, value)| (String::from(key), value))
+                .collect::<LinkedHashMap<_, _>>(),
+        )
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use smartstring::alias::String;
+use crate::Dynamic;
+
+/// JSON Pointer (RFC 6901), e.g. `/items/0/name`. The empty pointer refers to the whole document.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JsonPointer(pub Vec<String>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidPointer;
+
+impl JsonPointer {
+    pub fn tokens(&self) -> &[String] {
+        &self.0
+    }
+
+    pub fn push(&mut self, token: impl Into<String>) {
+        self.0.push(token.into())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Splits the pointer into the pointer to the parent and the last token
+    pub fn split_last(&self) -> Option<(JsonPointer, &str)> {
+        let (last, parent) = self.0.split_last()?;
+        Some((JsonPointer(parent.to_vec()), last))
+    }
+
+    /// Whether the pointer is a proper prefix of the other pointer
+    pub fn is_ancestor_of(&self, other: &JsonPointer) -> bool {
+        self.0.len() < other.0.len() && other.0.starts_with(&self.0)
+    }
+
+    pub fn resolve(&self, document: &Dynamic) -> Option<Dynamic> {
+        self.0
+            .iter()
+            .try_fold(document.clone(), |value, token| resolve_token(&value, token))
+    }
+
+    /// Resolves the pointer to a value which can be modified in place
+    pub fn resolve_mut<'a>(&self, document: &'a mut Dynamic) -> Option<&'a mut Dynamic> {
+        self.0.iter().try_fold(document, |value, token| match value {
+            Dynamic::Object(_) => value.get_object_field_mut(token),
+            Dynamic::Array(_) => value.get_array_item_mut(array_index(token)?),
+            _ => None,
+        })
+    }
+}
+
+/// Resolves a single reference token against an object member or an array item
+pub fn resolve_token(value: &Dynamic, token: &str) -> Option<Dynamic> {
+    match value {
+        Dynamic::Object(_) => value.get_object_field(token),
+        Dynamic::Array(_) => value.get_array_item(array_index(token)?),
+        _ => None,
+    }
+}
+
+/// Parses an array index token, which has no leading zeros
+pub fn array_index(token: &str) -> Option<usize> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|byte| byte.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+
+    valid.then(|| token.parse().ok()).flatten()
+}
+
+impl FromStr for JsonPointer {
+    type Err = InvalidPointer;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if string.is_empty() {
+            return Ok(JsonPointer::default());
+        }
+        let tokens = string.strip_prefix('/').ok_or(InvalidPointer)?;
+        tokens
+            .split('/')
+            .map(|token| {
+                let mut unescaped = String::new();
+                let mut chars = token.chars();
+                while let Some(char) = chars.next() {
+                    match char {
+                        '~' => match chars.next() {
+                            Some('0') => unescaped.push('~'),
+                            Some('1') => unescaped.push('/'),
+                            _ => return Err(InvalidPointer),
+                        },
+                        char => unescaped.push(char),
+                    }
+                }
+                Ok(unescaped)
+            })
+            .collect::<Result<_, _>>()
+            .map(JsonPointer)
+    }
+}
+
+impl Display for JsonPointer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for token in &self.0 {
+            f.write_fmt(format_args!("/{}", token.replace('~', "~0").replace('/', "~1")))?;
+        }
+        Ok(())
+    }
+}
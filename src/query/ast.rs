@@ -2,12 +2,15 @@ pub mod expression;
 pub mod operators;
 pub mod parser;
 
+use std::cmp::Ordering;
 use std::fmt::{Display, Formatter, Pointer, Write};
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 use derive_more::From;
 use hashlink::LinkedHashMap;
 use smallvec::SmallVec;
-use crate::{Dynamic, Number, Object};
+use crate::{Dynamic, DynamicRef, Number, ObjectComparison};
+use crate::bson::Bson;
+use crate::json_path::descendants_of;
 use crate::query::ast::expression::Expression;
 use crate::query::{Context, Eval, EvalError};
 use smartstring::alias::String;
@@ -58,6 +61,85 @@ pub enum Predicate {
     Operators(Vec<Operator>),
 }
 
+impl Predicate{
+    pub fn test_with_context(&self, object: impl Into<Dynamic>, context: &mut Context) -> Result<bool, EvalError>{
+        context.set_current_in_scope(object, |context| self.test(context))
+    }
+
+    /// Returns the fields which have a fixed value in every matching object,
+    /// i.e. the leaf values and `$eq` operators reachable through fields and `$and`
+    pub fn fixed_fields(&self) -> Vec<(Field, Value)>{
+        let mut fields = Vec::new();
+        self.collect_fixed_fields(None, &mut fields);
+
+        fields
+    }
+
+    /// Returns the paths of the fields which the predicate reads, or `None` if it tests the whole object,
+    /// e.g. with a leaf value or `$size`. Matching only depends on the values at these paths.
+    pub fn paths(&self) -> Option<Vec<&VariablePath>>{
+        let mut paths = Vec::new();
+        self.collect_paths(&mut paths)?;
+
+        Some(paths)
+    }
+
+    fn collect_paths<'a>(&'a self, paths: &mut Vec<&'a VariablePath>) -> Option<()>{
+        let Predicate::Operators(operators) = self else {
+            return None;
+        };
+        for operator in operators {
+            match operator {
+                Operator::Field(field_operator) => paths.push(field_operator.field.path()),
+                Operator::Not(NotOperator(predicate)) => predicate.collect_paths(paths)?,
+                Operator::And(AndOperator(predicates)) | Operator::Or(OrOperator(predicates)) => {
+                    for predicate in predicates {
+                        predicate.collect_paths(paths)?;
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        Some(())
+    }
+
+    fn collect_fixed_fields(&self, path: Option<&VariablePath>, fields: &mut Vec<(Field, Value)>){
+        match self {
+            Predicate::Leaf(LeafValue(value)) => {
+                if let Some(path) = path {
+                    fields.push((Field::from(path.clone()), value.clone()));
+                }
+            }
+            Predicate::Operators(operators) => {
+                for operator in operators {
+                    match operator {
+                        Operator::Field(field_operator) => {
+                            let field_path = match path {
+                                Some(path) => path.join(field_operator.field.path()),
+                                None => field_operator.field.path().clone(),
+                            };
+                            field_operator.predicate.collect_fixed_fields(Some(&field_path), fields);
+                        }
+                        Operator::Eq(EqOperator(value)) => {
+                            Predicate::Leaf(LeafValue(value.clone())).collect_fixed_fields(path, fields);
+                        }
+                        Operator::And(AndOperator(predicates)) => {
+                            for predicate in predicates {
+                                predicate.collect_fixed_fields(path, fields);
+                            }
+                        }
+                        Operator::Or(OrOperator(predicates)) if predicates.len() == 1 => {
+                            predicates[0].collect_fixed_fields(path, fields);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl TestPredicate for Predicate{
     fn test(&self, context: &mut Context) -> Result<bool, EvalError> {
         match self {
@@ -81,7 +163,9 @@ pub enum Value{
     Number(Number),
     String(String),
     Array(Vec<Value>),
-    Object(LinkedHashMap<String,Value>)
+    Object(LinkedHashMap<String,Value>),
+    /// BSON value written in Extended JSON, e.g. `{"$oid": "..."}`
+    Bson(Bson),
 }
 
 impl Display for Value{
@@ -103,6 +187,9 @@ impl Display for Value{
             Value::Object(object) => {
                 object.fmt(f)
             }
+            Value::Bson(bson) => {
+                bson.fmt(f)
+            }
         }
     }
 }
@@ -128,32 +215,95 @@ impl From<Value> for serde_json::Value {
             Value::Object(object) => {
                 serde_json::Value::Object(serde_json::Map::from_iter(object.into_iter().map(|x| (x.0.to_string(), serde_json::Value::from(x.1)))))
             }
+            Value::Bson(bson) => {
+                bson.to_extended_json()
+            }
+        }
+    }
+}
+
+impl From<&Value> for Dynamic {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Dynamic::Null,
+            Value::Bool(bool) => Dynamic::from(*bool),
+            Value::Number(number) => Dynamic::from(*number),
+            Value::String(string) => Dynamic::from(string.clone()),
+            Value::Array(array) => {
+                Dynamic::from(array.iter().map(Dynamic::from).collect::<Vec<_>>())
+            }
+            Value::Object(object) => {
+                Dynamic::from(object.iter().map(|(key, value)| (key.clone(), Dynamic::from(value))).collect::<LinkedHashMap<_, _>>())
+            }
+            Value::Bson(bson) => Dynamic::from(bson.clone()),
         }
     }
 }
 
 impl PartialEq<Dynamic> for Value{
     fn eq(&self, other: &Dynamic) -> bool {
+        self.eq_with(other, ObjectComparison::default())
+    }
+}
+
+impl Value {
+    /// Compares the value with a dynamic value for equality, comparing objects as specified
+    pub fn eq_with(&self, other: &Dynamic, comparison: ObjectComparison) -> bool {
         match (self, other) {
             (Value::Null, Dynamic::Null) => true,
             (Value::Number(number), Dynamic::Number(other_number)) => number.eq(other_number),
             (Value::Bool(bool), Dynamic::Bool(other_bool)) => bool.eq(other_bool),
             (Value::String(string), Dynamic::String(other_string)) => string.eq(other_string.deref()),
             (Value::Array(array), Dynamic::Array(other_array)) => {
-                let other_array = other_array.read().unwrap();
-                array.iter().eq(other_array.iter())
+                array.len() == other_array.len() && array.iter().eq_by(other_array.iter(), |x, y| x.eq_with(&y, comparison))
             },
-            (Value::Object(object), Dynamic::Object(other_object)) => {
-                match other_object {
-                    Object::Map(map) => {
-                        let Ok(map) = map.read() else { return false };
-                        object.iter().eq_by(map.iter(), |x,y| x.0.eq(y.0) && x.1.eq(y.1))
-                    }
-                    Object::DynamicObject(dyn_object) => {
-                        object.iter().eq_by(dyn_object.field_values(), |x,y| x.0.eq(y.0) && x.1.eq(&y.1))
+            (Value::Object(object), Dynamic::Object(other_object)) => match comparison {
+                ObjectComparison::Ordered => {
+                    object.iter().eq_by(other_object.field_values(), |x, y| x.0.eq(y.0) && x.1.eq_with(&y.1, comparison))
+                }
+                ObjectComparison::Unordered => {
+                    object.len() == other_object.field_values().count()
+                        && object.iter().all(|(key, value)| {
+                            other_object.get(key).is_some_and(|other_value| value.eq_with(&other_value, comparison))
+                        })
+                }
+            },
+            (Value::Bson(_), _) | (_, Dynamic::Bson(_)) => Dynamic::from(self).eq_with(other, comparison),
+            _ => false
+        }
+    }
+
+    /// Compares the value with a borrowed view for equality, without copying the viewed data
+    pub fn eq_ref(&self, other: &DynamicRef, comparison: ObjectComparison) -> bool {
+        match (self, other) {
+            (_, DynamicRef::Dynamic(other)) => self.eq_with(other, comparison),
+            (_, DynamicRef::Owned(other)) => self.eq_with(other, comparison),
+            (Value::Null, DynamicRef::Null) => true,
+            (Value::Number(number), DynamicRef::Number(other_number)) => number.eq(other_number),
+            (Value::Bool(bool), DynamicRef::Bool(other_bool)) => bool.eq(other_bool),
+            (Value::String(string), DynamicRef::String(other_string)) => string.eq(other_string),
+            (Value::Array(array), DynamicRef::Array(other_array)) => {
+                array.len() == other_array.len()
+                    && array.iter().enumerate().all(|(index, value)| {
+                        other_array.get_ref(index).is_some_and(|other_value| value.eq_ref(&other_value, comparison))
+                    })
+            }
+            (Value::Object(object), DynamicRef::Object(other_object)) => {
+                let fields = other_object.fields();
+                match comparison {
+                    ObjectComparison::Ordered => object.iter().eq_by(
+                        fields.iter().filter_map(|&key| other_object.get_field_ref(key).map(|value| (key, value))),
+                        |x, y| x.0.eq(y.0) && x.1.eq_ref(&y.1, comparison),
+                    ),
+                    ObjectComparison::Unordered => {
+                        object.len() == fields.iter().filter(|&&key| other_object.get_field_ref(key).is_some()).count()
+                            && object.iter().all(|(key, value)| {
+                                other_object.get_field_ref(key).is_some_and(|other_value| value.eq_ref(&other_value, comparison))
+                            })
                     }
                 }
             }
+            (Value::Bson(_), other) => self.eq_with(&other.to_dynamic(), comparison),
             _ => false
         }
     }
@@ -166,7 +316,7 @@ pub struct LeafValue(pub Value);
 impl TestPredicate for LeafValue{
     fn test(&self, context: &mut Context) -> Result<bool, EvalError> {
         let current_object = context.get_current();
-        Ok(self.0.eq(&current_object))
+        Ok(self.0.eq_with(&current_object, context.object_comparison()))
     }
 }
 #[derive(Debug,From, PartialEq, Clone)]
@@ -184,19 +334,89 @@ pub enum Operator {
     Or(OrOperator),
     And(AndOperator),
     Exists(ExistsOperator),
-    IsEmpty(IsEmptyOperator)
+    IsEmpty(IsEmptyOperator),
+    Size(SizeOperator),
+    ElemMatch(ElemMatchOperator),
 
 }
 
+impl Operator {
+    /// Whether the operator negates another test, so it matches multi-valued paths when none of their values matches it
+    fn is_negation(&self) -> bool {
+        matches!(self, Operator::Ne(_) | Operator::Not(_))
+    }
+}
+
 impl TestPredicate for Operator{
     fn test(&self, context: &mut Context) -> Result<bool, EvalError> {
         match self {
             Operator::Field(field_operator) => field_operator.test(context),
-            _ => { unimplemented!() }
+            Operator::Eq(EqOperator(value)) => Ok(value.eq_with(&context.get_current(), context.object_comparison())),
+            Operator::Ne(NeOperator(value)) => Ok(!value.eq_with(&context.get_current(), context.object_comparison())),
+            Operator::Gt(GtOperator(value)) => Ok(compare(&context.get_current(), value, context).is_some_and(Ordering::is_gt)),
+            Operator::Gte(GteOperator(value)) => Ok(compare(&context.get_current(), value, context).is_some_and(Ordering::is_ge)),
+            Operator::Lt(LtOperator(value)) => Ok(compare(&context.get_current(), value, context).is_some_and(Ordering::is_lt)),
+            Operator::Lte(LteOperator(value)) => Ok(compare(&context.get_current(), value, context).is_some_and(Ordering::is_le)),
+            Operator::Between(BetweenOperator(from, to)) => {
+                let current = context.get_current();
+                Ok(compare(&current, from, context).is_some_and(Ordering::is_ge) && compare(&current, to, context).is_some_and(Ordering::is_le))
+            }
+            Operator::In(InOperator(values)) => {
+                let current = context.get_current();
+                Ok(values.iter().any(|value| value.eq_with(&current, context.object_comparison())))
+            }
+            Operator::Not(NotOperator(predicate)) => Ok(!predicate.test(context)?),
+            Operator::And(AndOperator(predicates)) => {
+                for predicate in predicates{
+                    if !predicate.test(context)? { return Ok(false) }
+                }
+                Ok(true)
+            }
+            Operator::Or(OrOperator(predicates)) => {
+                for predicate in predicates{
+                    if predicate.test(context)? { return Ok(true) }
+                }
+                Ok(false)
+            }
+            Operator::Exists(ExistsOperator(exists)) => Ok(context.has_current() == *exists),
+            Operator::IsEmpty(IsEmptyOperator(is_empty)) => {
+                let current = context.get_current();
+                let empty = match current {
+                    Dynamic::Null => true,
+                    Dynamic::String(ref string) => string.is_empty(),
+                    Dynamic::Array(ref array) => array.is_empty(),
+                    Dynamic::Object(ref object) => object.to_map().is_empty(),
+                    Dynamic::Bool(_) | Dynamic::Number(_) | Dynamic::Bson(_) => false,
+                };
+                Ok(empty == *is_empty)
+            }
+            Operator::Size(SizeOperator(size)) => {
+                Ok(context.get_current().as_dynamic_array().is_some_and(|array| array.len() == *size))
+            }
+            Operator::ElemMatch(ElemMatchOperator(predicate)) => {
+                let Dynamic::Array(array) = context.get_current() else {
+                    return Ok(false);
+                };
+                for item in array.iter() {
+                    if context.set_current_in_scope(item, |context| predicate.test(context))? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
         }
     }
 }
 
+/// Compares values of the same type, values of different types are not comparable
+fn compare(current: &Dynamic, value: &Value, context: &Context) -> Option<Ordering> {
+    let value = Dynamic::from(value);
+    if current.comparison_order() != value.comparison_order() {
+        return None;
+    }
+    current.compare_with(&value, context.object_comparison())
+}
+
 #[derive(From,Debug, PartialEq, Clone)]
 pub struct GtOperator(pub Value);
 #[derive(From,Debug, PartialEq, Clone)]
@@ -223,6 +443,12 @@ pub struct AndOperator(pub Vec<Predicate>);
 pub struct ExistsOperator(pub bool);
 #[derive(From,Debug, PartialEq, Clone)]
 pub struct IsEmptyOperator(pub bool);
+/// `$size`, matches arrays with the number of items
+#[derive(From,Debug, PartialEq, Clone)]
+pub struct SizeOperator(pub usize);
+/// `$elemMatch`, matches arrays with an item which matches the predicate
+#[derive(From,Debug, PartialEq, Clone)]
+pub struct ElemMatchOperator(pub Predicate);
 
 #[derive(Debug,From, PartialEq, Clone)]
 pub struct FieldOperator {
@@ -230,15 +456,93 @@ pub struct FieldOperator {
     pub predicate: Predicate,
 }
 
+impl FieldOperator {
+    /// Tests predicates which only need a view of the field, so fields of dynamic objects and arrays
+    /// aren't copied. Returns `None` for other predicates.
+    fn test_ref(&self, current_object: &Dynamic, context: &mut Context) -> Result<Option<bool>, EvalError> {
+        let field = || self.field.path().resolve_ref(current_object).unwrap_or(DynamicRef::Null);
+        let operator = match &self.predicate {
+            Predicate::Leaf(LeafValue(value)) => return Ok(Some(value.eq_ref(&field(), context.object_comparison()))),
+            Predicate::Operators(operators) => match operators.as_slice() {
+                [operator] => operator,
+                _ => return Ok(None),
+            },
+        };
+        match operator {
+            Operator::Eq(EqOperator(value)) => Ok(Some(value.eq_ref(&field(), context.object_comparison()))),
+            Operator::Size(SizeOperator(size)) => Ok(Some(field().array_len() == Some(*size))),
+            Operator::ElemMatch(ElemMatchOperator(predicate)) => {
+                let field = field();
+                for index in 0..field.array_len().unwrap_or(0) {
+                    let Some(item) = field.array_item(index) else { continue };
+                    if context.set_current_in_scope(item, |context| predicate.test(context))? {
+                        return Ok(Some(true));
+                    }
+                }
+                Ok(Some(false))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Tests whether any of the values matches
+fn any_value(values: &[Dynamic], context: &mut Context, test: impl Fn(&mut Context) -> Result<bool, EvalError>) -> Result<bool, EvalError> {
+    for value in values {
+        if context.set_current_in_scope(value.clone(), &test)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 impl TestPredicate for FieldOperator{
     fn test(&self, context: &mut Context) -> Result<bool, EvalError> {
         let current_object = context.get_current();
-        let next_object = self
-            .field
-            .resolve(&current_object);
-        context.set_current_in_scope(next_object, |context| {
-            self.predicate.test(context)
-        })
+        if self.field.path().is_multi_valued() {
+            // Matches when any of the values matches, a path without values is tested as missing.
+            // `$ne` and `$not` match when none of the values matches what they negate.
+            let values = self.field.path().resolve_all(&current_object);
+            if values.is_empty() {
+                return context.set_missing_in_scope(|context| self.predicate.test(context));
+            }
+            let operators = match &self.predicate {
+                Predicate::Operators(operators) if operators.iter().any(Operator::is_negation) => operators,
+                predicate => return any_value(&values, context, |context| predicate.test(context)),
+            };
+            let positive = operators.iter().filter(|operator| !operator.is_negation()).collect::<Vec<_>>();
+            let all_positive = |context: &mut Context| -> Result<bool, EvalError> {
+                for operator in &positive {
+                    if !operator.test(context)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            };
+            if !positive.is_empty() && !any_value(&values, context, all_positive)? {
+                return Ok(false);
+            }
+            for operator in operators {
+                let negated = match operator {
+                    Operator::Ne(NeOperator(value)) => {
+                        any_value(&values, context, |context| Ok(value.eq_with(&context.get_current(), context.object_comparison())))?
+                    }
+                    Operator::Not(NotOperator(predicate)) => any_value(&values, context, |context| predicate.test(context))?,
+                    _ => false,
+                };
+                if negated {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
+        if let Some(result) = self.test_ref(&current_object, context)? {
+            return Ok(result);
+        }
+        match self.field.path().resolve(&current_object) {
+            Some(next_object) => context.set_current_in_scope(next_object, |context| self.predicate.test(context)),
+            None => context.set_missing_in_scope(|context| self.predicate.test(context)),
+        }
     }
 }
 
@@ -246,7 +550,11 @@ impl TestPredicate for FieldOperator{
 pub struct Field(VariablePath);
 
 impl Field{
-    fn resolve(&self, value: &Dynamic) -> Dynamic{
+    pub fn path(&self) -> &VariablePath{
+        &self.0
+    }
+
+    pub fn resolve(&self, value: &Dynamic) -> Dynamic{
         self.0
             .resolve(value)
             .unwrap_or(Dynamic::Null)
@@ -280,7 +588,11 @@ impl Display for VariablePath{
 }
 
 impl VariablePath{
+    /// Resolves the path. Multi-valued paths resolve to an array of all matched values.
     pub fn resolve(&self, root: &Dynamic) -> Option<Dynamic>{
+        if self.is_multi_valued() {
+            return Some(Dynamic::from(self.resolve_all(root)));
+        }
         match self {
             VariablePath::BaseVariable(var) => root.get_object_field(&var.field),
             VariablePath::InnerField { base, field } => {
@@ -292,10 +604,71 @@ impl VariablePath{
                     InnerField::ArrayIndex(array_index) => {
                         base.get_array_item(array_index.index)
                     }
+                    InnerField::NegativeIndex(negative_index) => negative_index.resolve(&base),
+                    _ => None,
                 }
             }
         }
     }
+
+    /// Resolves the path to a view of the value, which borrows the value where possible
+    pub fn resolve_ref<'a>(&self, root: &'a Dynamic) -> Option<DynamicRef<'a>>{
+        if self.is_multi_valued() {
+            return self.resolve(root).map(DynamicRef::Owned);
+        }
+        match self {
+            VariablePath::BaseVariable(var) => DynamicRef::from(root).get_field(&var.field),
+            VariablePath::InnerField { base, field: InnerField::MemberAccess(member_access) } => {
+                base.resolve_ref(root)?.get_field(&member_access.member)
+            }
+            VariablePath::InnerField { base, field: InnerField::ArrayIndex(array_index) } => {
+                base.resolve_ref(root)?.get_item(array_index.index)
+            }
+            _ => self.resolve(root).map(DynamicRef::Owned),
+        }
+    }
+
+    /// Resolves the path to every value it matches, in document order
+    pub fn resolve_all(&self, root: &Dynamic) -> Vec<Dynamic>{
+        match self {
+            VariablePath::BaseVariable(var) => root.get_object_field(&var.field).into_iter().collect(),
+            VariablePath::InnerField { base, field } => base
+                .resolve_all(root)
+                .iter()
+                .flat_map(|base| field.select(base))
+                .collect(),
+        }
+    }
+
+    /// Whether the path contains wildcards, slices or recursive descent
+    pub fn is_multi_valued(&self) -> bool{
+        match self {
+            VariablePath::BaseVariable(_) => false,
+            VariablePath::InnerField { base, field } => field.is_multi_valued() || base.is_multi_valued(),
+        }
+    }
+
+    /// Appends the path to this path, treating the base variable of the path as a member
+    pub fn join(&self, path: &VariablePath) -> VariablePath{
+        match path {
+            VariablePath::BaseVariable(variable) => VariablePath::InnerField {
+                base: Box::new(self.clone()),
+                field: InnerField::MemberAccess(MemberAccess { member: variable.field.clone() }),
+            },
+            VariablePath::InnerField { base, field } => VariablePath::InnerField {
+                base: Box::new(self.join(base)),
+                field: field.clone(),
+            },
+        }
+    }
+
+    /// Returns the variable which the path starts with
+    pub fn base(&self) -> &Variable{
+        match self {
+            VariablePath::BaseVariable(variable) => variable,
+            VariablePath::InnerField { base, .. } => base.base(),
+        }
+    }
 }
 #[derive(Debug, Clone,From, PartialEq)]
 #[from(forward)]
@@ -305,7 +678,7 @@ pub struct Variable {
 
 impl Display for Variable{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.field)
+        write_member(f, "", "", &self.field)
     }
 }
 
@@ -316,7 +689,22 @@ pub struct MemberAccess {
 
 impl Display for MemberAccess{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!(".{}", self.member))
+        write_member(f, ".", "", &self.member)
+    }
+}
+
+/// Writes the member after the prefix, or quotes it as `["name"]` when it can't be parsed unquoted
+fn write_member(f: &mut Formatter<'_>, prefix: &str, quoted_prefix: &str, member: &str) -> std::fmt::Result {
+    let unquoted = !member.is_empty()
+        && !member.starts_with('$')
+        && member != "*"
+        && !member.contains(['.', '[', ']']);
+    if unquoted {
+        f.write_str(prefix)?;
+        f.write_str(member)
+    } else {
+        let quoted = serde_json::to_string(member).map_err(|_| std::fmt::Error)?;
+        f.write_fmt(format_args!("{}[{}]", quoted_prefix, quoted))
     }
 }
 #[derive(Debug, Clone, PartialEq)]
@@ -330,18 +718,136 @@ impl Display for ArrayIndex{
     }
 }
 
+/// Positional segment of an update path: `.$` for the first item matched by the query,
+/// `.$[]` for all items and `.$[identifier]` for the items matched by an array filter
+#[derive(Debug, Clone, PartialEq)]
+pub enum Positional {
+    First,
+    All,
+    Filtered(String),
+}
+
+impl Display for Positional{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Positional::First => f.write_str(".$"),
+            Positional::All => f.write_str(".$[]"),
+            Positional::Filtered(identifier) => f.write_fmt(format_args!(".$[{}]", identifier)),
+        }
+    }
+}
+
+/// Index counted from the end of an array: `[-1]` is the last item
+#[derive(Debug, Clone, PartialEq)]
+pub struct NegativeIndex {
+    pub index: usize,
+}
+
+impl NegativeIndex{
+    pub fn resolve(&self, array: &Dynamic) -> Option<Dynamic>{
+        let length = array.as_dynamic_array()?.len();
+        array.get_array_item(length.checked_sub(self.index)?)
+    }
+}
+
+impl Display for NegativeIndex{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("[-{}]", self.index))
+    }
+}
+
+/// Items from the start up to, but excluding, the end. Negative bounds count from the end of an array.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slice {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+impl Slice{
+    pub fn indices(&self, length: usize) -> Range<usize>{
+        let length = length as i64;
+        let normalize = |index: i64| if index >= 0 { index } else { length + index }.clamp(0, length) as usize;
+        normalize(self.start.unwrap_or(0))..normalize(self.end.unwrap_or(length))
+    }
+}
+
+impl Display for Slice{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_char('[')?;
+        if let Some(start) = self.start {
+            f.write_fmt(format_args!("{}", start))?;
+        }
+        f.write_char(':')?;
+        if let Some(end) = self.end {
+            f.write_fmt(format_args!("{}", end))?;
+        }
+        f.write_char(']')
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum InnerField {
     MemberAccess(MemberAccess),
     ArrayIndex(ArrayIndex),
+    NegativeIndex(NegativeIndex),
+    Slice(Slice),
+    /// `.*`, every member of an object
+    MemberWildcard,
+    /// `[*]`, every item of an array
+    ElementWildcard,
+    /// `..member`, the member of the value and of every value nested in it
+    Descendant(MemberAccess),
+    Positional(Positional),
+}
+
+impl InnerField{
+    pub fn is_multi_valued(&self) -> bool{
+        matches!(self, InnerField::Slice(_) | InnerField::MemberWildcard | InnerField::ElementWildcard | InnerField::Descendant(_))
+    }
+
+    /// Returns the values selected from the base value
+    fn select(&self, base: &Dynamic) -> Vec<Dynamic>{
+        match self {
+            InnerField::MemberAccess(member_access) => base.get_object_field(&member_access.member).into_iter().collect(),
+            InnerField::ArrayIndex(array_index) => base.get_array_item(array_index.index).into_iter().collect(),
+            InnerField::NegativeIndex(negative_index) => negative_index.resolve(base).into_iter().collect(),
+            InnerField::Slice(slice) => match base {
+                Dynamic::Array(array) => array.slice(slice.indices(array.len())),
+                _ => Vec::new(),
+            },
+            InnerField::MemberWildcard => match base {
+                Dynamic::Object(object) => object.to_map().into_iter().map(|(_, value)| value).collect(),
+                _ => Vec::new(),
+            },
+            InnerField::ElementWildcard => match base {
+                Dynamic::Array(array) => array.to_vec(),
+                _ => Vec::new(),
+            },
+            InnerField::Descendant(member_access) => {
+                let mut descendants = Vec::new();
+                descendants_of(base, &mut descendants);
+                descendants
+                    .iter()
+                    .filter(|descendant| descendant.as_object().is_some())
+                    .filter_map(|descendant| descendant.get_object_field(&member_access.member))
+                    .collect()
+            }
+            InnerField::Positional(_) => Vec::new(),
+        }
+    }
 }
 
 impl Display for InnerField{
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             InnerField::MemberAccess(member_access) => member_access.fmt(f),
-            InnerField::ArrayIndex(array_index) => array_index.fmt(f)
-
+            InnerField::ArrayIndex(array_index) => array_index.fmt(f),
+            InnerField::NegativeIndex(negative_index) => negative_index.fmt(f),
+            InnerField::Slice(slice) => slice.fmt(f),
+            InnerField::MemberWildcard => f.write_str(".*"),
+            InnerField::ElementWildcard => f.write_str("[*]"),
+            InnerField::Descendant(member_access) => write_member(f, "..", "..", &member_access.member),
+            InnerField::Positional(positional) => positional.fmt(f),
         }
     }
 }
@@ -5,7 +5,7 @@ use hashlink::LinkedHashMap;
 use smallvec::SmallVec;
 use crate::{Dynamic, Number, Object};
 use crate::query::ast::{MatchOperator, VariablePath};
-use crate::query::ast::operators::{EqOperator, GtOperator, LtOperator};
+use crate::query::ast::operators::{DiffOperator, EqOperator, JsonPathOperator, GtOperator, LtOperator, MergePatchOperator, PatchOperator, ToDateOperator, ToObjectIdOperator};
 use crate::query::{Context, Eval, EvalError};
 use smartstring::alias::String;
 #[derive(From,Debug)]
@@ -140,6 +140,12 @@ pub enum ExprOperator {
     Gt(GtOperator),
     Lt(LtOperator),
     Eq(EqOperator),
+    Patch(PatchOperator),
+    MergePatch(MergePatchOperator),
+    Diff(DiffOperator),
+    JsonPath(JsonPathOperator),
+    ToObjectId(ToObjectIdOperator),
+    ToDate(ToDateOperator),
 }
 
 impl Eval for ExprOperator{
@@ -148,7 +154,13 @@ impl Eval for ExprOperator{
             ExprOperator::Gt(gt) => gt.eval_with_context(context),
             ExprOperator::Lt(lt) => lt.eval_with_context(context),
             ExprOperator::Eq(eq) => eq.eval_with_context(context),
-            ExprOperator::Match(r#match) => r#match.eval_with_context(context)
+            ExprOperator::Match(r#match) => r#match.eval_with_context(context),
+            ExprOperator::Patch(patch) => patch.eval_with_context(context),
+            ExprOperator::MergePatch(merge_patch) => merge_patch.eval_with_context(context),
+            ExprOperator::Diff(diff) => diff.eval_with_context(context),
+            ExprOperator::JsonPath(json_path) => json_path.eval_with_context(context),
+            ExprOperator::ToObjectId(to_object_id) => to_object_id.eval_with_context(context),
+            ExprOperator::ToDate(to_date) => to_date.eval_with_context(context),
         }
     }
 }
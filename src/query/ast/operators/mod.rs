@@ -1,8 +1,14 @@
 pub mod parser;
 mod match_operator;
 
+use std::cmp::Ordering;
 use derive_more::From;
-use crate::Dynamic;
+use crate::{Dynamic, Number};
+use crate::bson::{Bson, DateTime, ObjectId};
+use crate::diff::ArrayDiff;
+use crate::json_path::JsonPath;
+use crate::patch::JsonPatch;
+use crate::patch::merge::merge_patch;
 use crate::query::ast::expression::{Expression, ExprLiteral};
 use crate::query::{Context, Eval, EvalError};
 
@@ -18,7 +24,7 @@ impl Eval for GtOperator{
         let arg1 = self.arg1.eval_with_context(context)?;
         let arg2 = self.arg2.eval_with_context(context)?;
 
-        Ok(Dynamic::Bool(arg1 > arg2))
+        Ok(Dynamic::Bool(arg1.compare_with(&arg2, context.object_comparison()) == Some(Ordering::Greater)))
     }
 }
 
@@ -34,7 +40,7 @@ impl Eval for LtOperator{
         let arg1 = self.arg1.eval_with_context(context)?;
         let arg2 = self.arg2.eval_with_context(context)?;
 
-        Ok(Dynamic::Bool(arg1 < arg2))
+        Ok(Dynamic::Bool(arg1.compare_with(&arg2, context.object_comparison()) == Some(Ordering::Less)))
     }
 }
 
@@ -50,6 +56,130 @@ impl Eval for EqOperator{
         let arg1 = self.arg1.eval_with_context(context)?;
         let arg2 = self.arg2.eval_with_context(context)?;
 
-        Ok(Dynamic::Bool(arg1 == arg2))
+        Ok(Dynamic::Bool(arg1.eq_with(&arg2, context.object_comparison())))
+    }
+}
+
+
+/// Applies a JSON Patch to a copy of the document
+#[derive(From,Debug)]
+pub struct PatchOperator {
+    document: Expression,
+    patch: Expression,
+}
+
+impl Eval for PatchOperator{
+    fn eval_with_context(&self, context: &mut Context) -> Result<Dynamic, EvalError> {
+        let mut document = self.document.eval_with_context(context)?;
+        let patch = JsonPatch::try_from(&self.patch.eval_with_context(context)?)?;
+        patch.apply(&mut document)?;
+
+        Ok(document)
+    }
+}
+
+/// Applies a JSON Merge Patch to a copy of the document
+#[derive(From,Debug)]
+pub struct MergePatchOperator {
+    document: Expression,
+    patch: Expression,
+}
+
+impl Eval for MergePatchOperator{
+    fn eval_with_context(&self, context: &mut Context) -> Result<Dynamic, EvalError> {
+        let document = self.document.eval_with_context(context)?;
+        let patch = self.patch.eval_with_context(context)?;
+
+        Ok(merge_patch(&document, &patch))
+    }
+}
+
+/// Lists the changes between two values as `{ "op", "path", ... }` objects
+#[derive(Debug)]
+pub struct DiffOperator {
+    pub from: Expression,
+    pub to: Expression,
+    pub arrays: ArrayDiff,
+}
+
+impl From<(Expression, Expression)> for DiffOperator{
+    fn from((from, to): (Expression, Expression)) -> Self {
+        DiffOperator { from, to, arrays: ArrayDiff::Index }
+    }
+}
+
+impl Eval for DiffOperator{
+    fn eval_with_context(&self, context: &mut Context) -> Result<Dynamic, EvalError> {
+        let from = self.from.eval_with_context(context)?;
+        let to = self.to.eval_with_context(context)?;
+        let changes = from.diff_with(&to, &self.arrays);
+
+        Ok(Dynamic::from(changes.iter().map(Dynamic::from).collect::<Vec<_>>()))
+    }
+}
+
+/// Returns the array of nodes selected by a JSONPath query
+#[derive(From,Debug)]
+pub struct JsonPathOperator {
+    document: Expression,
+    path: JsonPath,
+}
+
+impl Eval for JsonPathOperator{
+    fn eval_with_context(&self, context: &mut Context) -> Result<Dynamic, EvalError> {
+        let document = self.document.eval_with_context(context)?;
+
+        Ok(Dynamic::from(self.path.query(&document)))
+    }
+}
+
+/// Converts a hexadecimal string into an ObjectId. Null stays null.
+#[derive(From,Debug)]
+pub struct ToObjectIdOperator {
+    value: Expression,
+}
+
+impl Eval for ToObjectIdOperator{
+    fn eval_with_context(&self, context: &mut Context) -> Result<Dynamic, EvalError> {
+        match self.value.eval_with_context(context)? {
+            Dynamic::String(string) => {
+                let id = string.parse::<ObjectId>().map_err(|_| EvalError::ConversionFailed)?;
+                Ok(Dynamic::from(id))
+            }
+            value @ (Dynamic::Null | Dynamic::Bson(Bson::ObjectId(_))) => Ok(value),
+            _ => Err(EvalError::ConversionFailed),
+        }
+    }
+}
+
+/// Converts a value into a date: the creation time of an ObjectId, the time of a timestamp, milliseconds
+/// since the Unix epoch or an RFC 3339 string. Null stays null.
+#[derive(From,Debug)]
+pub struct ToDateOperator {
+    value: Expression,
+}
+
+impl Eval for ToDateOperator{
+    fn eval_with_context(&self, context: &mut Context) -> Result<Dynamic, EvalError> {
+        let date = match self.value.eval_with_context(context)? {
+            Dynamic::Null => return Ok(Dynamic::Null),
+            value @ Dynamic::Bson(Bson::DateTime(_)) => return Ok(value),
+            Dynamic::Bson(Bson::ObjectId(id)) => id.timestamp(),
+            Dynamic::Bson(Bson::Timestamp(timestamp)) => DateTime(timestamp.time as i64 * 1000),
+            Dynamic::Bson(Bson::Decimal128(decimal)) => millis(decimal.to_number())?,
+            Dynamic::Number(number) => millis(number)?,
+            Dynamic::String(string) => string.parse::<DateTime>().map_err(|_| EvalError::ConversionFailed)?,
+            _ => return Err(EvalError::ConversionFailed),
+        };
+
+        Ok(Dynamic::from(date))
+    }
+}
+
+fn millis(number: Number) -> Result<DateTime, EvalError> {
+    match number {
+        Number::Int(int) => Ok(DateTime(int)),
+        Number::Float(float) if float.is_finite() => Ok(DateTime(float as i64)),
+        Number::Float(_) => Err(EvalError::ConversionFailed),
     }
 }
@@ -1,9 +1,16 @@
-use super::{EqOperator, GtOperator, LtOperator};
-use crate::query::ast::parser::{arguments, expression, named_arguments};
+use super::{DiffOperator, EqOperator, JsonPathOperator, GtOperator, LtOperator, MergePatchOperator, PatchOperator, ToDateOperator, ToObjectIdOperator};
+use crate::query::ast::parser::{arguments, expression, named_arguments, optional_named_arguments};
+use crate::diff::ArrayDiff;
+use crate::json_path::JsonPath;
+use std::str::FromStr;
 use crate::query::ast::MatchOperator;
-use crate::query::parser::{operator_pair, predicate};
+use crate::query::parser::{field, operator_pair, predicate, string};
 use nom::branch::alt;
-use nom::combinator::{cut, map};
+use nom::bytes::complete::tag;
+use nom::combinator::{cut, map, map_opt, map_res, value};
+use nom::sequence::delimited;
+use nom::character::complete::char;
+use crate::query::parser::ws;
 use nom::IResult;
 
 pub fn gt_operator_expr(str: &str) -> IResult<&str, GtOperator> {
@@ -27,6 +34,65 @@ pub fn eq_operator_expr(str: &str) -> IResult<&str, EqOperator> {
     )(str)
 }
 
+pub fn patch_operator_expr(str: &str) -> IResult<&str, PatchOperator> {
+    map(
+        operator_pair("$patch", cut(arguments((expression, expression)))),
+        PatchOperator::from,
+    )(str)
+}
+
+pub fn merge_patch_operator_expr(str: &str) -> IResult<&str, MergePatchOperator> {
+    map(
+        operator_pair("$mergePatch", cut(arguments((expression, expression)))),
+        MergePatchOperator::from,
+    )(str)
+}
+
+pub fn diff_operator_expr(str: &str) -> IResult<&str, DiffOperator> {
+    operator_pair(
+        "$diff",
+        cut(alt((
+            map(arguments((expression, expression)), DiffOperator::from),
+            map_opt(
+                optional_named_arguments((
+                    operator_pair("from", expression),
+                    operator_pair("to", expression),
+                    operator_pair("arrays", array_diff),
+                )),
+                |(from, to, arrays)| {
+                    Some(DiffOperator {
+                        from: from?,
+                        to: to?,
+                        arrays: arrays.unwrap_or_default(),
+                    })
+                },
+            ),
+        ))),
+    )(str)
+}
+
+/// `"index"`, `"lcs"` or `{ "key": "<field>" }`
+pub fn array_diff(str: &str) -> IResult<&str, ArrayDiff> {
+    alt((
+        value(ArrayDiff::Index, tag(r#""index""#)),
+        value(ArrayDiff::Lcs, tag(r#""lcs""#)),
+        map(
+            delimited(ws(char('{')), operator_pair("key", field), ws(char('}'))),
+            ArrayDiff::Key,
+        ),
+    ))(str)
+}
+
+pub fn json_path_operator_expr(str: &str) -> IResult<&str, JsonPathOperator> {
+    map(
+        operator_pair(
+            "$jsonPath",
+            cut(arguments((expression, map_res(string, |path| JsonPath::from_str(&path))))),
+        ),
+        JsonPathOperator::from,
+    )(str)
+}
+
 pub fn match_operator_expr(str: &str) -> IResult<&str, MatchOperator> {
     operator_pair(
         "$match",
@@ -42,3 +108,17 @@ pub fn match_operator_expr(str: &str) -> IResult<&str, MatchOperator> {
         ))),
     )(str)
 }
+
+pub fn to_object_id_operator_expr(str: &str) -> IResult<&str, ToObjectIdOperator> {
+    map(
+        operator_pair("$toObjectId", cut(expression)),
+        ToObjectIdOperator::from,
+    )(str)
+}
+
+pub fn to_date_operator_expr(str: &str) -> IResult<&str, ToDateOperator> {
+    map(
+        operator_pair("$toDate", cut(expression)),
+        ToDateOperator::from,
+    )(str)
+}
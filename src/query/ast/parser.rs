@@ -1,15 +1,16 @@
 use crate::query::ast::expression::{ExprFieldPath, ExprLiteral, ExprOperator, ExprVariable, Expression, NullLiteral, NumberLiteral, StringLiteral, BoolLiteral, ArrayLiteral, ObjectLiteral};
-use crate::query::ast::operators::parser::{eq_operator_expr, gt_operator_expr, lt_operator_expr, match_operator_expr};
-use crate::query::parser::{array_of, escaped_string, field_path, number, object, object_of, string, boolean, ws, predicate};
+use crate::query::ast::operators::parser::{diff_operator_expr, eq_operator_expr, json_path_operator_expr, gt_operator_expr, lt_operator_expr, match_operator_expr, merge_patch_operator_expr, patch_operator_expr, to_date_operator_expr, to_object_id_operator_expr};
+use crate::query::parser::{array_of, escaped_string, field_path, number, object, object_of, string, boolean, unescaped, ws, predicate};
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::character::complete::char;
-use nom::combinator::{all_consuming, cut, map, map_parser, peek, verify};
+use nom::combinator::{all_consuming, cut, map, map_opt, peek, verify};
 use nom::sequence::{delimited, preceded};
 use nom::IResult;
 use crate::query::ast::Predicate;
 use crate::query::Script;
-use crate::query::utils::{separated_permutation, separated_tuple, SeparatedPermutation, SeparatedTuple};
+use crate::{bson, Dynamic};
+use crate::query::utils::{separated_optional_permutation, separated_permutation, separated_tuple, SeparatedOptionalPermutation, SeparatedPermutation, SeparatedTuple};
 
 pub fn arguments<'a, O>(args: impl SeparatedTuple<&'a str, O, nom::error::Error<&'a str>>) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
     delimited(
@@ -27,6 +28,14 @@ pub fn named_arguments<'a, O>(args: impl SeparatedPermutation<&'a str, O, nom::e
     )
 }
 
+pub fn optional_named_arguments<'a, O>(args: impl SeparatedOptionalPermutation<&'a str, O, nom::error::Error<&'a str>>) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    delimited(
+        ws(char('{')),
+        separated_optional_permutation(ws(char(',')), args),
+        cut(ws(char('}'))),
+    )
+}
+
 pub fn script(str: &str) -> IResult<&str, Script>{
     map(all_consuming(ws(expression)), Script::from)(str)
 }
@@ -39,6 +48,7 @@ pub fn expression(str: &str) -> IResult<&str, Expression> {
     alt((
         map(variable_expr, Expression::from),
         map(field_path_expr, Expression::from),
+        map(extended_json_expr, Expression::Precomputed),
         map(operator_expr, Expression::from),
         map(literal_expr, Expression::from),
     ))(str)
@@ -79,6 +89,15 @@ fn object_literal(str: &str) -> IResult<&str, ObjectLiteral>{
     map(ws(object_of(expression)), ObjectLiteral::from)(str)
 }
 
+/// BSON value written in Extended JSON, which would otherwise be parsed as an operator.
+/// Its first key is checked before the object is parsed, so operators aren't parsed twice.
+fn extended_json_expr(str: &str) -> IResult<&str, Dynamic> {
+    preceded(
+        peek(preceded(ws(char('{')), verify(escaped_string, |key: &str| bson::EXTENDED_JSON_KEYS.contains(&key)))),
+        map_opt(ws(object), |object| bson::from_extended_json(&object).map(|value| Dynamic::from(&value))),
+    )(str)
+}
+
 fn operator_expr(str: &str) -> IResult<&str, ExprOperator> {
     delimited(
         preceded(ws(char('{')), verify(peek(escaped_string), |str: &str| str.starts_with('$'))),
@@ -86,24 +105,24 @@ fn operator_expr(str: &str) -> IResult<&str, ExprOperator> {
             map(gt_operator_expr, ExprOperator::from),
             map(lt_operator_expr, ExprOperator::from),
             map(eq_operator_expr, ExprOperator::from),
-            map(match_operator_expr, ExprOperator::from)
+            map(match_operator_expr, ExprOperator::from),
+            map(patch_operator_expr, ExprOperator::from),
+            map(merge_patch_operator_expr, ExprOperator::from),
+            map(diff_operator_expr, ExprOperator::from),
+            map(json_path_operator_expr, ExprOperator::from),
+            map(to_object_id_operator_expr, ExprOperator::from),
+            map(to_date_operator_expr, ExprOperator::from),
         ))),
         ws(char('}')),
     )(str)
 }
 
 fn field_path_expr(str: &str) -> IResult<&str, ExprFieldPath> {
-    map_parser(
-        escaped_string,
-        map(preceded(char('$'), cut(field_path)), ExprFieldPath::from),
-    )(str)
+    unescaped(|str: &str| map(preceded(char('$'), cut(field_path)), ExprFieldPath::from)(str))(str)
 }
 
 fn variable_expr(str: &str) -> IResult<&str, ExprVariable> {
-    map_parser(
-        escaped_string,
-        map(preceded(tag("$$"), cut(field_path)), ExprVariable::from),
-    )(str)
+    unescaped(|str: &str| map(preceded(tag("$$"), cut(field_path)), ExprVariable::from)(str))(str)
 }
 
 
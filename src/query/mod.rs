@@ -1,6 +1,7 @@
 use crate::query::ast::expression::{ExprLiteral, Expression};
 use crate::query::ast::parser::{parse_predicate, script};
-use crate::{Dynamic, DynamicError, Object};
+use crate::{Dynamic, DynamicError, Object, ObjectComparison};
+use crate::patch::PatchError;
 use ahash::RandomState;
 use derive_more::From;
 use hashlink::LinkedHashMap;
@@ -19,6 +20,8 @@ use crate::query::ast::Predicate;
 pub mod ast;
 mod dynamic_object;
 pub mod parser;
+pub mod pipeline;
+pub mod update;
 pub mod utils;
 
 pub type ParseError = nom::error::Error<std::string::String>;
@@ -74,6 +77,8 @@ pub struct Context {
     map: Dynamic,
     root: u64,
     current: u64,
+    collections: LinkedHashMap<String, Vec<Dynamic>>,
+    object_comparison: ObjectComparison,
 }
 
 impl<K, V, const N: usize> From<[(K, V); N]> for Context
@@ -103,9 +108,54 @@ impl Context {
             map: Dynamic::Object(Object::from(map)),
             root,
             current,
+            collections: LinkedHashMap::new(),
+            object_comparison: ObjectComparison::default(),
         }
     }
 
+    /// Registers an in-memory collection which can be referenced by name from pipeline stages
+    pub fn register_collection(
+        &mut self,
+        name: impl Into<String>,
+        documents: impl IntoIterator<Item = Dynamic>,
+    ) -> Option<Vec<Dynamic>> {
+        self.collections
+            .insert(name.into(), documents.into_iter().collect())
+    }
+
+    pub fn get_collection(&self, name: &str) -> Option<&[Dynamic]> {
+        self.collections.get(name).map(Vec::as_slice)
+    }
+
+    /// Creates a context with its own copy of the variables, so it can be used independently
+    /// of this one, e.g. on another thread. Values of the variables are shared until they are modified.
+    pub fn fork(&self) -> Context {
+        let map = self
+            .map
+            .as_object()
+            .map(|object| object.to_map())
+            .unwrap_or_default();
+        let hasher = map.hasher();
+        let root = hasher.hash_one("ROOT");
+        let current = hasher.hash_one("CURRENT");
+        Context {
+            map: Dynamic::from(map),
+            root,
+            current,
+            collections: self.collections.clone(),
+            object_comparison: self.object_comparison,
+        }
+    }
+
+    /// Sets how predicates, expressions and stages compare objects, by default field by field in their order
+    pub fn set_object_comparison(&mut self, comparison: ObjectComparison) {
+        self.object_comparison = comparison;
+    }
+
+    pub fn object_comparison(&self) -> ObjectComparison {
+        self.object_comparison
+    }
+
     pub fn as_dynamic(&self) -> &Dynamic {
         &self.map
     }
@@ -146,11 +196,9 @@ impl Context {
         self.map
             .as_map()
             .and_then(|map| {
-                map.read().ok().and_then(|map| {
-                    map.raw_entry()
-                        .from_hash(self.root, |_| true)
-                        .map(|x| x.1.clone())
-                })
+                map.raw_entry()
+                    .from_hash(self.root, |_| true)
+                    .map(|x| x.1.clone())
             })
             .unwrap_or(Dynamic::Null)
     }
@@ -159,47 +207,43 @@ impl Context {
         self.map
             .as_map()
             .and_then(|map| {
-                map.read().ok().and_then(|map| {
-                    map.raw_entry()
-                        .from_hash(self.current, |_| true)
-                        .map(|x| x.1.clone())
-                })
+                map.raw_entry()
+                    .from_hash(self.current, |_| true)
+                    .map(|x| x.1.clone())
             })
             .unwrap_or(Dynamic::Null)
     }
 
-    pub fn set_root(&self, value: impl Into<Dynamic>) -> Option<Dynamic> {
+    pub fn set_root(&mut self, value: impl Into<Dynamic>) -> Option<Dynamic> {
+        let hash = self.root;
         self.map
-            .as_map()
+            .as_map_mut()
             .and_then(|map| {
-                map.write().ok().and_then(|mut map| {
-                    match map.raw_entry_mut()
-                        .from_hash(self.root, |_| true)
-                    {
-                        RawEntryMut::Occupied(mut occupied) => Some(occupied.replace_value(value.into())),
-                        RawEntryMut::Vacant(vacant) => {
-                            vacant.insert_hashed_nocheck(self.root, "ROOT".into(), value.into());
-                            None
-                        }
+                match map.raw_entry_mut()
+                    .from_hash(hash, |_| true)
+                {
+                    RawEntryMut::Occupied(mut occupied) => Some(occupied.replace_value(value.into())),
+                    RawEntryMut::Vacant(vacant) => {
+                        vacant.insert_hashed_nocheck(hash, "ROOT".into(), value.into());
+                        None
                     }
-                })
+                }
             })
     }
-    pub fn set_current(&self, value: impl Into<Dynamic>) -> Option<Dynamic> {
+    pub fn set_current(&mut self, value: impl Into<Dynamic>) -> Option<Dynamic> {
+        let hash = self.current;
         self.map
-            .as_map()
+            .as_map_mut()
             .and_then(|map| {
-                map.write().ok().and_then(|mut map| {
-                    match map.raw_entry_mut()
-                        .from_hash(self.current, |_| true)
-                    {
-                        RawEntryMut::Occupied(mut occupied) => Some(occupied.replace_value(value.into())),
-                        RawEntryMut::Vacant(vacant) => {
-                            vacant.insert_hashed_nocheck(self.current, "CURRENT".into(), value.into());
-                            None
-                        }
+                match map.raw_entry_mut()
+                    .from_hash(hash, |_| true)
+                {
+                    RawEntryMut::Occupied(mut occupied) => Some(occupied.replace_value(value.into())),
+                    RawEntryMut::Vacant(vacant) => {
+                        vacant.insert_hashed_nocheck(hash, "CURRENT".into(), value.into());
+                        None
                     }
-                })
+                }
             })
     }
 
@@ -213,12 +257,49 @@ impl Context {
             S: Fn(&mut Context) -> Result<O, E>,
             E: From<DynamicError>,
     {
-        let prev_variable = self.set_current(value).unwrap_or(Dynamic::Null);
+        let prev_variable = self.set_current(value);
         let result = scope(self);
-        self.set_current(prev_variable);
+        self.restore_current(prev_variable);
         result
     }
 
+    /// Runs the scope without a current value, so `$exists` can tell a missing field from a null value.
+    /// `get_current` returns null in the scope.
+    pub fn set_missing_in_scope<S, O, E>(&mut self, scope: S) -> Result<O, E>
+        where
+            S: Fn(&mut Context) -> Result<O, E>,
+    {
+        let hash = self.current;
+        let prev_variable = self.map.as_map_mut().and_then(|map| match map.raw_entry_mut().from_hash(hash, |_| true) {
+            RawEntryMut::Occupied(occupied) => Some(occupied.remove()),
+            RawEntryMut::Vacant(_) => None,
+        });
+        let result = scope(self);
+        self.restore_current(prev_variable);
+        result
+    }
+
+    /// Whether there is a current value, which isn't the case while a missing field is tested
+    pub fn has_current(&self) -> bool {
+        self.map
+            .as_map()
+            .is_some_and(|map| map.raw_entry().from_hash(self.current, |_| true).is_some())
+    }
+
+    fn restore_current(&mut self, prev_variable: Option<Dynamic>) {
+        match prev_variable {
+            Some(prev_variable) => {
+                self.set_current(prev_variable);
+            }
+            None => {
+                let hash = self.current;
+                if let Some(RawEntryMut::Occupied(occupied)) = self.map.as_map_mut().map(|map| map.raw_entry_mut().from_hash(hash, |_| true)) {
+                    occupied.remove();
+                }
+            }
+        }
+    }
+
 
     pub fn remove_variable(&mut self, key: &str) -> Result<Option<Dynamic>, DynamicError> {
         self.map.remove_object_field(key)
@@ -228,5 +309,15 @@ impl Context {
 #[derive(Debug, From)]
 pub enum EvalError {
     UndefinedVariable,
+    NoMatchingBucket,
+    NoPositionalMatch,
+    UndefinedArrayFilter,
+    /// The path can not be used to address values which are updated or removed
+    UnsupportedPath,
+    /// The text of a document isn't valid JSON
+    InvalidJson,
+    /// The value can't be converted into the requested type, e.g. by `$toObjectId`
+    ConversionFailed,
     DynamicError(DynamicError),
+    PatchError(PatchError),
 }
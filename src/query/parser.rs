@@ -1,13 +1,13 @@
 use smartstring::alias::String;
-use crate::query::ast::{AndOperator, ArrayIndex, BetweenOperator, Field, FieldOperator, GteOperator, InnerField, InOperator, LeafValue, LteOperator, MemberAccess, NeOperator, NotOperator, Operator, OrOperator, Predicate, Value, Variable, VariablePath};
+use crate::query::ast::{AndOperator, ArrayIndex, BetweenOperator, ElemMatchOperator, ExistsOperator, Field, IsEmptyOperator, FieldOperator, GteOperator, InnerField, InOperator, LeafValue, LteOperator, MemberAccess, NegativeIndex, NeOperator, NotOperator, Operator, OrOperator, Positional, Predicate, SizeOperator, Slice, Value, Variable, VariablePath};
 use nom::branch::alt;
 use nom::bytes::complete::{escaped, escaped_transform, is_not, tag, take};
 use nom::character::complete::{
-    char as character, hex_digit1, i64, multispace0, none_of, one_of, u64,
+    alphanumeric1, char as character, hex_digit1, i64, multispace0, none_of, one_of, u64,
 };
 
 use nom::combinator::{
-    all_consuming, cond, cut, eof, flat_map, map, map_opt, map_parser, map_res, not, opt,
+    all_consuming, cond, cut, eof, flat_map, map, map_opt, map_parser, map_res, not, opt, verify,
     value as get_value,
 };
 use nom::error::ParseError;
@@ -20,7 +20,7 @@ use nom::{error, IResult, Parser};
 use std::iter::once;
 use std::ops::{Deref, Range};
 use hashlink::LinkedHashMap;
-use crate::Number;
+use crate::{bson, Number};
 use crate::query::ast::{EqOperator, GtOperator, LtOperator};
 use crate::query::ast::parser::{arguments, expression};
 
@@ -48,7 +48,7 @@ pub fn value(str: &str) -> IResult<&str, Value> {
         map(string, Value::from),
         map(boolean,Value::from),
         map(array, Value::from),
-        map(object, Value::from),
+        map(object, |object| bson::from_extended_json(&object).unwrap_or(Value::Object(object))),
         map(null, |_|Value::Null),
     ))(str)
 }
@@ -75,6 +75,10 @@ pub fn operator(str: &str) -> IResult<&str, Operator> {
         map(not_operator, Operator::from),
         map(and_operator, Operator::from),
         map(or_operator, Operator::from),
+        map(exists_operator, Operator::from),
+        map(is_empty_operator, Operator::from),
+        map(size_operator, Operator::from),
+        map(elem_match_operator, Operator::from),
     ))(str)
 }
 
@@ -153,6 +157,33 @@ pub fn or_operator(str: &str) -> IResult<&str, OrOperator> {
     )(str)
 }
 
+pub fn exists_operator(str: &str) -> IResult<&str, ExistsOperator> {
+    map(
+        operator_pair("$exists", cut(boolean)),
+        ExistsOperator::from,
+    )(str)
+}
+
+pub fn is_empty_operator(str: &str) -> IResult<&str, IsEmptyOperator> {
+    map(
+        operator_pair("$isEmpty", cut(boolean)),
+        IsEmptyOperator::from,
+    )(str)
+}
+
+pub fn size_operator(str: &str) -> IResult<&str, SizeOperator> {
+    map(
+        operator_pair("$size", cut(u64)),
+        |size| SizeOperator::from(size as usize),
+    )(str)
+}
+
+pub fn elem_match_operator(str: &str) -> IResult<&str, ElemMatchOperator> {
+    map(
+        operator_pair("$elemMatch", cut(predicate)),
+        ElemMatchOperator::from,
+    )(str)
+}
 
 pub fn operator_pair<'a, O, E: ParseError<&'a str>>(
     name: &'a str,
@@ -193,6 +224,20 @@ pub fn object_of<'a, O>(
     )
 }
 
+/// Object whose keys are fields, e.g. `{ "a.b": 1, "c": 2 }`
+pub fn fields_of<'a, O>(
+    element: impl Parser<&'a str, O, nom::error::Error<&'a str>>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<(Field, O)>, error::Error<&'a str>> {
+    delimited(
+        ws(character('{')),
+        separated_list0(
+            ws(character(',')),
+            separated_pair(ws(field), character(':'), ws(element)),
+        ),
+        cut(ws(character('}'))),
+    )
+}
+
 pub fn field_operator(str: &str) -> IResult<&str, FieldOperator> {
     map(
         separated_pair(ws(field), character(':'), ws(predicate)),
@@ -200,17 +245,25 @@ pub fn field_operator(str: &str) -> IResult<&str, FieldOperator> {
     )(str)
 }
 pub fn field(str: &str) -> IResult<&str, Field> {
-    map_parser(
-        escaped_string,
-        map(preceded(not(character('$')), field_path), Field::from),
-    )(str)
+    unescaped(|str: &str| map(preceded(not(character('$')), field_path), Field::from)(str))(str)
+}
+
+/// Applies the parser to the contents of a string after its escape sequences are resolved
+pub fn unescaped<'a, O>(parser: impl Fn(&str) -> IResult<&str, O>) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    move |str: &'a str| {
+        let (rest, string) = string(str)?;
+        match parser(&string) {
+            Ok((_, output)) => Ok((rest, output)),
+            Err(error) => Err(error.map(|error| error::Error::new(str, error.code))),
+        }
+    }
 }
 
 pub fn field_path(str: &str) -> IResult<&str, VariablePath> {
-    flat_map(is_not(".[]"), |str: &str| {
+    flat_map(member_name, |member: String| {
         fold_many0(
             preceded(not(eof), cut(inner_field)),
-            move || VariablePath::BaseVariable(Variable::from(str)),
+            move || VariablePath::BaseVariable(Variable::from(member.clone())),
             |base, field| VariablePath::InnerField {
                 base: Box::new(base),
                 field,
@@ -220,11 +273,30 @@ pub fn field_path(str: &str) -> IResult<&str, VariablePath> {
 }
 pub fn inner_field(str: &str) -> IResult<&str, InnerField, error::Error<&str>> {
     alt((
+        map(positional, InnerField::Positional),
+        map(preceded(tag(".."), member_name), |member| {
+            InnerField::Descendant(MemberAccess { member })
+        }),
+        get_value(InnerField::MemberWildcard, terminated(tag(".*"), not(none_of(".[")))),
         map(preceded(character('.'), is_not(".[]")), |member: &str| {
             InnerField::MemberAccess(MemberAccess {
                 member: member.into(),
             })
         }),
+        map(quoted_member, |member| InnerField::MemberAccess(MemberAccess { member })),
+        get_value(InnerField::ElementWildcard, tag("[*]")),
+        map(
+            delimited(pair(character('['), ws(character('-'))), u64, ws(character(']'))),
+            |index| InnerField::NegativeIndex(NegativeIndex { index: index as usize }),
+        ),
+        map(
+            delimited(
+                character('['),
+                separated_pair(ws(opt(i64)), character(':'), ws(opt(i64))),
+                character(']'),
+            ),
+            |(start, end)| InnerField::Slice(Slice { start, end }),
+        ),
         map(
             delimited(character('['), ws(u64), character(']')),
             |index| {
@@ -236,6 +308,30 @@ pub fn inner_field(str: &str) -> IResult<&str, InnerField, error::Error<&str>> {
     ))(str)
 }
 
+/// Member name which is either unquoted, or quoted as `["name"]` when it contains `.`, `[` or `]`
+pub fn member_name(str: &str) -> IResult<&str, String, error::Error<&str>> {
+    alt((quoted_member, map(is_not(".[]"), String::from)))(str)
+}
+
+fn quoted_member(str: &str) -> IResult<&str, String, error::Error<&str>> {
+    delimited(character('['), ws(string), character(']'))(str)
+}
+
+pub fn positional(str: &str) -> IResult<&str, Positional, error::Error<&str>> {
+    alt((
+        get_value(Positional::All, tag(".$[]")),
+        map(
+            delimited(
+                tag(".$["),
+                verify(alphanumeric1, |identifier: &str| identifier.starts_with(|char: char| char.is_ascii_lowercase())),
+                character(']'),
+            ),
+            |identifier: &str| Positional::Filtered(identifier.into()),
+        ),
+        get_value(Positional::First, terminated(tag(".$"), not(none_of(".[")))),
+    ))(str)
+}
+
 pub fn array(str: &str) -> IResult<&str, Vec<Value>> {
     array_of(value)(str)
 }
@@ -0,0 +1,202 @@
+use derive_more::From;
+use hashlink::{LinkedHashMap, LinkedHashSet};
+use smartstring::alias::String;
+use std::cmp::Ordering;
+use crate::{Dynamic, Number, ObjectComparison};
+use crate::ord::OrdDynamic;
+use crate::query::ast::expression::Expression;
+use crate::query::{Context, EvalError};
+use crate::query::pipeline::eval_with_document;
+
+#[derive(Debug)]
+pub enum Accumulator {
+    Sum(Expression),
+    Avg(Expression),
+    Min(Expression),
+    Max(Expression),
+    First(Expression),
+    Last(Expression),
+    Push(Expression),
+    AddToSet(Expression),
+    Count,
+}
+
+/// Named accumulators which are computed for every group of documents
+#[derive(Debug, From)]
+pub struct Output(pub LinkedHashMap<String, Accumulator>);
+
+impl Output {
+    pub fn init(&self) -> Vec<AccumulatorState> {
+        self.0.values().map(Accumulator::init).collect()
+    }
+
+    pub fn accumulate(
+        &self,
+        states: &mut [AccumulatorState],
+        document: &Dynamic,
+        context: &mut Context,
+    ) -> Result<(), EvalError> {
+        for (accumulator, state) in self.0.values().zip(states.iter_mut()) {
+            accumulator.accumulate(state, document, context)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&self, states: Vec<AccumulatorState>, document: &mut LinkedHashMap<String, Dynamic>) {
+        for (name, state) in self.0.keys().zip(states) {
+            document.insert(name.clone(), state.finish());
+        }
+    }
+}
+
+impl Default for Output {
+    /// `{ "count": { "$sum": 1 } }`, which is used when a stage has no explicit output
+    fn default() -> Self {
+        Output(LinkedHashMap::from_iter([("count".into(), Accumulator::Count)]))
+    }
+}
+
+impl Accumulator {
+    pub fn init(&self) -> AccumulatorState {
+        match self {
+            Accumulator::Sum(_) => AccumulatorState::Sum(Number::Int(0)),
+            Accumulator::Avg(_) => AccumulatorState::Avg { sum: 0.0, count: 0 },
+            Accumulator::Min(_) => AccumulatorState::Min(None),
+            Accumulator::Max(_) => AccumulatorState::Max(None),
+            Accumulator::First(_) => AccumulatorState::First(None),
+            Accumulator::Last(_) => AccumulatorState::Last(None),
+            Accumulator::Push(_) => AccumulatorState::Push(Vec::new()),
+            Accumulator::AddToSet(_) => AccumulatorState::AddToSet(LinkedHashSet::new()),
+            Accumulator::Count => AccumulatorState::Count(0),
+        }
+    }
+
+    pub fn accumulate(
+        &self,
+        state: &mut AccumulatorState,
+        document: &Dynamic,
+        context: &mut Context,
+    ) -> Result<(), EvalError> {
+        state.accumulate(self.value(document, context)?, context.object_comparison());
+
+        Ok(())
+    }
+
+    /// Evaluates the value which the accumulator collects from the document
+    pub fn value(&self, document: &Dynamic, context: &mut Context) -> Result<Dynamic, EvalError> {
+        match self {
+            Accumulator::Sum(expression)
+            | Accumulator::Avg(expression)
+            | Accumulator::Min(expression)
+            | Accumulator::Max(expression)
+            | Accumulator::First(expression)
+            | Accumulator::Last(expression)
+            | Accumulator::Push(expression)
+            | Accumulator::AddToSet(expression) => eval_with_document(expression, document, context),
+            Accumulator::Count => Ok(Dynamic::Null),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AccumulatorState {
+    Sum(Number),
+    Avg { sum: f64, count: i64 },
+    Min(Option<Dynamic>),
+    Max(Option<Dynamic>),
+    First(Option<Dynamic>),
+    Last(Option<Dynamic>),
+    Push(Vec<Dynamic>),
+    AddToSet(LinkedHashSet<OrdDynamic>),
+    Count(i64),
+}
+
+impl AccumulatorState {
+    pub fn accumulate(&mut self, value: Dynamic, comparison: ObjectComparison) {
+        match self {
+            AccumulatorState::Sum(sum) => {
+                if let Dynamic::Number(number) = value {
+                    *sum = *sum + number;
+                }
+            }
+            AccumulatorState::Avg { sum, count } => {
+                if let Dynamic::Number(number) = value {
+                    *sum += number.as_f64();
+                    *count += 1;
+                }
+            }
+            AccumulatorState::Min(min) => {
+                if !value.is_null() && min.as_ref().is_none_or(|min| value.total_cmp_with(min, comparison) == Ordering::Less) {
+                    *min = Some(value);
+                }
+            }
+            AccumulatorState::Max(max) => {
+                if !value.is_null() && max.as_ref().is_none_or(|max| value.total_cmp_with(max, comparison) == Ordering::Greater) {
+                    *max = Some(value);
+                }
+            }
+            AccumulatorState::First(first) => {
+                if first.is_none() {
+                    *first = Some(value);
+                }
+            }
+            AccumulatorState::Last(last) => *last = Some(value),
+            AccumulatorState::Push(array) => array.push(value),
+            AccumulatorState::AddToSet(set) => {
+                let value = OrdDynamic(value);
+                if !set.contains(&value) {
+                    set.insert(value);
+                }
+            }
+            AccumulatorState::Count(count) => *count += 1,
+        }
+    }
+
+    /// Combines the state with the state of documents which follow the already accumulated ones
+    pub fn merge(&mut self, other: AccumulatorState, comparison: ObjectComparison) {
+        match (self, other) {
+            (AccumulatorState::Sum(sum), AccumulatorState::Sum(other)) => *sum = *sum + other,
+            (AccumulatorState::Avg { sum, count }, AccumulatorState::Avg { sum: other_sum, count: other_count }) => {
+                *sum += other_sum;
+                *count += other_count;
+            }
+            (AccumulatorState::Min(_), AccumulatorState::Min(None))
+            | (AccumulatorState::Max(_), AccumulatorState::Max(None))
+            | (AccumulatorState::First(Some(_)), AccumulatorState::First(_)) => {}
+            (state @ AccumulatorState::Min(_), AccumulatorState::Min(Some(value)))
+            | (state @ AccumulatorState::Max(_), AccumulatorState::Max(Some(value)))
+            | (state @ AccumulatorState::First(None), AccumulatorState::First(Some(value))) => {
+                state.accumulate(value, comparison)
+            }
+            (AccumulatorState::Last(last), AccumulatorState::Last(other)) => {
+                if other.is_some() {
+                    *last = other;
+                }
+            }
+            (AccumulatorState::Push(array), AccumulatorState::Push(other)) => array.extend(other),
+            (state @ AccumulatorState::AddToSet(_), AccumulatorState::AddToSet(other)) => {
+                for value in other {
+                    state.accumulate(value.0, comparison);
+                }
+            }
+            (AccumulatorState::Count(count), AccumulatorState::Count(other)) => *count += other,
+            _ => {}
+        }
+    }
+
+    pub fn finish(self) -> Dynamic {
+        match self {
+            AccumulatorState::Sum(sum) => Dynamic::from(sum),
+            AccumulatorState::Avg { count: 0, .. } => Dynamic::Null,
+            AccumulatorState::Avg { sum, count } => Dynamic::from(Number::Float(sum / count as f64)),
+            AccumulatorState::Min(value)
+            | AccumulatorState::Max(value)
+            | AccumulatorState::First(value)
+            | AccumulatorState::Last(value) => value.unwrap_or(Dynamic::Null),
+            AccumulatorState::Push(array) => Dynamic::from(array),
+            AccumulatorState::AddToSet(set) => Dynamic::from(set.into_iter().map(Dynamic::from).collect::<Vec<_>>()),
+            AccumulatorState::Count(count) => Dynamic::from(count),
+        }
+    }
+}
@@ -0,0 +1,997 @@
+pub mod accumulator;
+pub mod parallel;
+pub mod parser;
+pub mod window;
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use ahash::RandomState;
+use derive_more::From;
+use hashlink::LinkedHashMap;
+use nom::Finish;
+use smartstring::alias::String;
+use crate::{Array, Dynamic, DynamicError, ObjectComparison};
+use crate::ord::OrdDynamic;
+use crate::query::ast::expression::Expression;
+use crate::query::ast::{Field, InnerField, Predicate, Value, VariablePath};
+use crate::query::pipeline::accumulator::{AccumulatorState, Output};
+use crate::query::pipeline::parser::parse_pipeline;
+use crate::query::pipeline::window::SetWindowFieldsStage;
+use crate::query::{Context, Eval, EvalError, ParseError};
+
+pub trait Execute {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError>;
+}
+
+#[derive(From, Debug)]
+pub struct Pipeline(Vec<Stage>);
+
+impl Pipeline {
+    pub fn execute_with_context(&self, input: impl IntoIterator<Item = Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut documents = input.into_iter().collect();
+        for stage in &self.0 {
+            documents = stage.execute_with_context(documents, context)?;
+        }
+
+        Ok(documents)
+    }
+
+    pub fn execute(&self, input: impl IntoIterator<Item = Dynamic>) -> Result<Vec<Dynamic>, EvalError> {
+        let mut context = Context::new();
+
+        self.execute_with_context(input, &mut context)
+    }
+}
+
+impl Execute for Pipeline {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        Pipeline::execute_with_context(self, input, context)
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = ParseError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        parse_pipeline(string)
+            .map_err(|x| x.to_owned())
+            .finish()
+            .map(|(_, x)| x)
+    }
+}
+
+#[derive(From, Debug)]
+pub enum Stage {
+    Match(MatchStage),
+    Project(ProjectStage),
+    AddFields(AddFieldsStage),
+    Unwind(UnwindStage),
+    Group(GroupStage),
+    Sort(SortStage),
+    Facet(FacetStage),
+    Bucket(BucketStage),
+    BucketAuto(BucketAutoStage),
+    SortByCount(SortByCountStage),
+    SetWindowFields(SetWindowFieldsStage),
+    ReplaceRoot(ReplaceRootStage),
+    Unset(UnsetStage),
+    Count(CountStage),
+    Sample(SampleStage),
+    UnionWith(UnionWithStage),
+    GraphLookup(GraphLookupStage),
+}
+
+impl Execute for Stage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        match self {
+            Stage::Match(match_stage) => match_stage.execute_with_context(input, context),
+            Stage::Project(project) => project.execute_with_context(input, context),
+            Stage::AddFields(add_fields) => add_fields.execute_with_context(input, context),
+            Stage::Unwind(unwind) => unwind.execute_with_context(input, context),
+            Stage::Group(group) => group.execute_with_context(input, context),
+            Stage::Sort(sort) => sort.execute_with_context(input, context),
+            Stage::Facet(facet) => facet.execute_with_context(input, context),
+            Stage::Bucket(bucket) => bucket.execute_with_context(input, context),
+            Stage::BucketAuto(bucket_auto) => bucket_auto.execute_with_context(input, context),
+            Stage::SortByCount(sort_by_count) => sort_by_count.execute_with_context(input, context),
+            Stage::SetWindowFields(set_window_fields) => set_window_fields.execute_with_context(input, context),
+            Stage::ReplaceRoot(replace_root) => replace_root.execute_with_context(input, context),
+            Stage::Unset(unset) => unset.execute_with_context(input, context),
+            Stage::Count(count) => count.execute_with_context(input, context),
+            Stage::Sample(sample) => sample.execute_with_context(input, context),
+            Stage::UnionWith(union_with) => union_with.execute_with_context(input, context),
+            Stage::GraphLookup(graph_lookup) => graph_lookup.execute_with_context(input, context),
+        }
+    }
+}
+
+/// Evaluates an expression with the document bound to both `$$ROOT` and `$$CURRENT`
+pub fn eval_with_document(expression: &Expression, document: &Dynamic, context: &mut Context) -> Result<Dynamic, EvalError> {
+    let prev_root = context.set_root(document.clone()).unwrap_or(Dynamic::Null);
+    let result = context.set_current_in_scope(document.clone(), |context| {
+        expression.eval_with_context(context)
+    });
+    context.set_root(prev_root);
+    result
+}
+
+/// Evaluates an expression like [`eval_with_document`], a field path to a missing field evaluates to null
+fn eval_or_null(expression: &Expression, document: &Dynamic, context: &mut Context) -> Result<Dynamic, EvalError> {
+    Ok(eval_if_present(expression, document, context)?.unwrap_or(Dynamic::Null))
+}
+
+/// Evaluates an expression like [`eval_with_document`], a field path to a missing field evaluates to `None`
+fn eval_if_present(expression: &Expression, document: &Dynamic, context: &mut Context) -> Result<Option<Dynamic>, EvalError> {
+    match eval_with_document(expression, document, context) {
+        Err(EvalError::UndefinedVariable) if matches!(expression, Expression::FieldPath(_)) => Ok(None),
+        result => result.map(Some),
+    }
+}
+
+fn compare(first: &Dynamic, second: &Dynamic, comparison: ObjectComparison) -> Ordering {
+    first.total_cmp_with(second, comparison)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// Sort specification in the form of `{ "field": 1, "other.field": -1 }`
+#[derive(From, Debug)]
+pub struct SortBy(pub Vec<(Field, SortOrder)>);
+
+impl SortBy {
+    pub fn keys(&self, document: &Dynamic) -> Vec<Dynamic> {
+        self.0.iter().map(|(field, _)| field.resolve(document)).collect()
+    }
+
+    pub fn compare(&self, first: &[Dynamic], second: &[Dynamic], comparison: ObjectComparison) -> Ordering {
+        self.0
+            .iter()
+            .zip(first.iter().zip(second))
+            .map(|((_, order), (first, second))| match order {
+                SortOrder::Ascending => compare(first, second, comparison),
+                SortOrder::Descending => compare(second, first, comparison),
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+#[derive(From, Debug)]
+pub struct MatchStage(pub Predicate);
+
+impl Execute for MatchStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut output = Vec::with_capacity(input.len());
+        for document in input {
+            if self.0.test_with_context(document.clone(), context)? {
+                output.push(document)
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+#[derive(Debug)]
+pub enum Projection {
+    Include,
+    Exclude,
+    Computed(Expression),
+}
+
+/// Either keeps only the included and computed fields of every document, or removes the excluded ones.
+/// `_id` is kept unless it is excluded explicitly, and it is the only field which can be excluded
+/// next to included fields.
+#[derive(From, Debug)]
+pub struct ProjectStage(pub Vec<(Field, Projection)>);
+
+fn is_id(field: &Field) -> bool {
+    matches!(field.path(), VariablePath::BaseVariable(variable) if variable.field == "_id")
+}
+
+impl ProjectStage {
+    fn is_exclusion(&self) -> bool {
+        self.0.iter().all(|(_, projection)| matches!(projection, Projection::Exclude))
+    }
+}
+
+impl Execute for ProjectStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut output = Vec::with_capacity(input.len());
+        for document in input {
+            if self.is_exclusion() {
+                output.push(self.0.iter().try_fold(document, |document, (field, _)| without_path(&document, field.path()))?);
+                continue;
+            }
+
+            let mut projected = Dynamic::from(LinkedHashMap::new());
+            if !self.0.iter().any(|(field, _)| is_id(field)) {
+                if let Some(id) = document.get_object_field("_id") {
+                    projected = with_path(&projected, &VariablePath::BaseVariable("_id".into()), id)?;
+                }
+            }
+            for (field, projection) in &self.0 {
+                let value = match projection {
+                    Projection::Include => field.path().resolve(&document),
+                    Projection::Exclude => None,
+                    Projection::Computed(expression) => eval_if_present(expression, &document, context)?,
+                };
+                if let Some(value) = value {
+                    projected = with_path(&projected, field.path(), value)?;
+                }
+            }
+            output.push(projected);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Adds the computed fields to every document, replacing existing fields.
+/// Used for both `$addFields` and `$set`.
+#[derive(From, Debug)]
+pub struct AddFieldsStage(pub Vec<(Field, Expression)>);
+
+impl Execute for AddFieldsStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut output = Vec::with_capacity(input.len());
+        for document in input {
+            let mut result = document.clone();
+            for (field, expression) in &self.0 {
+                if let Some(value) = eval_if_present(expression, &document, context)? {
+                    result = with_path(&result, field.path(), value)?;
+                }
+            }
+            output.push(result);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Emits a copy of the document for every item of the array at the path, with the array replaced by the item.
+/// Values which aren't arrays are emitted as they are. Documents where the value is missing, null
+/// or an empty array are dropped unless `preserve_null_and_empty_arrays` is set.
+#[derive(Debug)]
+pub struct UnwindStage {
+    pub path: Field,
+    pub include_array_index: Option<Field>,
+    pub preserve_null_and_empty_arrays: bool,
+}
+
+impl From<(Field, Option<Field>, bool)> for UnwindStage {
+    fn from((path, include_array_index, preserve_null_and_empty_arrays): (Field, Option<Field>, bool)) -> Self {
+        UnwindStage {
+            path,
+            include_array_index,
+            preserve_null_and_empty_arrays,
+        }
+    }
+}
+
+impl UnwindStage {
+    fn with_index(&self, document: Dynamic, index: Dynamic) -> Result<Dynamic, EvalError> {
+        match self.include_array_index {
+            Some(ref field) => with_path(&document, field.path(), index),
+            None => Ok(document),
+        }
+    }
+}
+
+impl Execute for UnwindStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, _: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut output = Vec::with_capacity(input.len());
+        for document in input {
+            match self.path.path().resolve(&document) {
+                Some(Dynamic::Array(array)) if !array.is_empty() => {
+                    for (index, item) in array.iter().enumerate() {
+                        let unwound = with_path(&document, self.path.path(), item)?;
+                        output.push(self.with_index(unwound, Dynamic::from(index as i64))?);
+                    }
+                }
+                None | Some(Dynamic::Null) | Some(Dynamic::Array(_)) => {
+                    if self.preserve_null_and_empty_arrays {
+                        output.push(self.with_index(document, Dynamic::Null)?);
+                    }
+                }
+                Some(_) => output.push(self.with_index(document, Dynamic::Null)?),
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Accumulator states of every group, in the order in which the groups were first seen
+pub type GroupStates = LinkedHashMap<OrdDynamic, Vec<AccumulatorState>>;
+
+/// Groups documents by the value of the `_id` expression and emits a document with the accumulated output
+/// for every group, in the order in which the groups were first seen
+#[derive(Debug)]
+pub struct GroupStage {
+    pub id: Expression,
+    pub output: Output,
+}
+
+impl GroupStage {
+    pub fn partial(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<GroupStates, EvalError> {
+        let mut groups = GroupStates::new();
+        for document in &input {
+            let id = OrdDynamic(eval_or_null(&self.id, document, context)?);
+            match groups.get_mut(&id) {
+                Some(states) => self.output.accumulate(states, document, context)?,
+                None => {
+                    let mut states = self.output.init();
+                    self.output.accumulate(&mut states, document, context)?;
+                    groups.insert(id, states);
+                }
+            }
+        }
+
+        Ok(groups)
+    }
+
+    /// Merges the groups of contiguous parts of the input, given in the order of the parts
+    pub fn finish(&self, partials: impl IntoIterator<Item = GroupStates>, comparison: ObjectComparison) -> Vec<Dynamic> {
+        let mut partials = partials.into_iter();
+        let mut groups = partials.next().unwrap_or_default();
+        for partial in partials {
+            for (id, other) in partial {
+                match groups.get_mut(&id) {
+                    Some(states) => {
+                        for (state, other) in states.iter_mut().zip(other) {
+                            state.merge(other, comparison);
+                        }
+                    }
+                    None => {
+                        groups.insert(id, other);
+                    }
+                }
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(id, states)| {
+                let mut document = LinkedHashMap::new();
+                document.insert("_id".into(), id.0);
+                self.output.finish(states, &mut document);
+                Dynamic::from(document)
+            })
+            .collect()
+    }
+}
+
+impl Execute for GroupStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let groups = self.partial(input, context)?;
+
+        Ok(self.finish([groups], context.object_comparison()))
+    }
+}
+
+/// Documents together with their sort keys, sorted by the keys
+pub type SortedDocuments = Vec<(Vec<Dynamic>, Dynamic)>;
+
+/// Sorts documents by their keys, documents with equal keys keep their order
+#[derive(From, Debug)]
+pub struct SortStage(pub SortBy);
+
+impl SortStage {
+    pub fn partial(&self, input: Vec<Dynamic>, comparison: ObjectComparison) -> SortedDocuments {
+        let mut documents = input
+            .into_iter()
+            .map(|document| (self.0.keys(&document), document))
+            .collect::<Vec<_>>();
+        documents.sort_by(|(first, _), (second, _)| self.0.compare(first, second, comparison));
+
+        documents
+    }
+
+    /// Merges sorted contiguous parts of the input, given in the order of the parts.
+    /// Documents of earlier parts come first when their keys are equal, like in a stable sort.
+    pub fn finish(&self, partials: impl IntoIterator<Item = SortedDocuments>, comparison: ObjectComparison) -> Vec<Dynamic> {
+        let mut partials = partials
+            .into_iter()
+            .map(|partial| partial.into_iter().peekable())
+            .collect::<Vec<_>>();
+        let mut output = Vec::new();
+        loop {
+            let mut next: Option<(usize, &Vec<Dynamic>)> = None;
+            for (index, partial) in partials.iter_mut().enumerate() {
+                let Some((keys, _)) = partial.peek() else { continue };
+                if next.is_none_or(|(_, next_keys)| self.0.compare(keys, next_keys, comparison).is_lt()) {
+                    next = Some((index, keys));
+                }
+            }
+            let Some((index, _)) = next else { break };
+            if let Some((_, document)) = partials[index].next() {
+                output.push(document);
+            }
+        }
+
+        output
+    }
+}
+
+impl Execute for SortStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let comparison = context.object_comparison();
+
+        Ok(self.finish([self.partial(input, comparison)], comparison))
+    }
+}
+
+/// Runs every sub-pipeline over the same buffered input and emits a single document
+/// with the result of each sub-pipeline stored under its name.
+#[derive(From, Debug)]
+pub struct FacetStage(pub LinkedHashMap<String, Pipeline>);
+
+impl Execute for FacetStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut facets = LinkedHashMap::with_capacity(self.0.len());
+        for (name, pipeline) in &self.0 {
+            let result = pipeline.execute_with_context(input.iter().cloned(), context)?;
+            facets.insert(name.clone(), Dynamic::from(result));
+        }
+
+        Ok(vec![Dynamic::from(facets)])
+    }
+}
+
+#[derive(Debug)]
+pub struct BucketStage {
+    pub group_by: Expression,
+    pub boundaries: Vec<Value>,
+    pub default: Option<Value>,
+    pub output: Output,
+}
+
+impl From<(Expression, Vec<Value>, Option<Value>, Option<Output>)> for BucketStage {
+    fn from((group_by, boundaries, default, output): (Expression, Vec<Value>, Option<Value>, Option<Output>)) -> Self {
+        BucketStage {
+            group_by,
+            boundaries,
+            default,
+            output: output.unwrap_or_default(),
+        }
+    }
+}
+
+/// Accumulated state of every bucket, which can be computed over parts of the input and merged afterwards
+#[derive(Debug, Clone)]
+pub struct BucketStates {
+    buckets: Vec<Option<Vec<AccumulatorState>>>,
+    default: Option<Vec<AccumulatorState>>,
+}
+
+impl BucketStage {
+    pub fn partial(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<BucketStates, EvalError> {
+        let boundaries = self.boundaries.iter().map(Dynamic::from).collect::<Vec<_>>();
+        let mut states = BucketStates {
+            buckets: vec![None; boundaries.len().saturating_sub(1)],
+            default: None,
+        };
+
+        for document in &input {
+            let value = eval_or_null(&self.group_by, document, context)?;
+            let bucket = boundaries
+                .windows(2)
+                .position(|bounds| {
+                    let comparison = context.object_comparison();
+                    bounds[0].compare_with(&value, comparison).is_some_and(Ordering::is_le)
+                        && value.compare_with(&bounds[1], comparison) == Some(Ordering::Less)
+                });
+            let states = match bucket {
+                Some(index) => &mut states.buckets[index],
+                None if self.default.is_some() => &mut states.default,
+                None => return Err(EvalError::NoMatchingBucket),
+            };
+            let states = states.get_or_insert_with(|| self.output.init());
+            self.output.accumulate(states, document, context)?;
+        }
+
+        Ok(states)
+    }
+
+    pub fn finish(&self, partials: impl IntoIterator<Item = BucketStates>, comparison: ObjectComparison) -> Vec<Dynamic> {
+        let mut partials = partials.into_iter();
+        let Some(mut states) = partials.next() else {
+            return Vec::new();
+        };
+        for partial in partials {
+            for (bucket, other) in states.buckets.iter_mut().zip(partial.buckets) {
+                merge_states(bucket, other, comparison);
+            }
+            merge_states(&mut states.default, partial.default, comparison);
+        }
+
+        let buckets = states
+            .buckets
+            .into_iter()
+            .zip(&self.boundaries)
+            .filter_map(|(states, lower_bound)| Some((Dynamic::from(lower_bound), states?)))
+            .chain(states.default.zip(self.default.as_ref().map(Dynamic::from)).map(|(states, id)| (id, states)));
+
+        buckets
+            .map(|(id, states)| {
+                let mut document = LinkedHashMap::new();
+                document.insert("_id".into(), id);
+                self.output.finish(states, &mut document);
+                Dynamic::from(document)
+            })
+            .collect()
+    }
+}
+
+fn merge_states(states: &mut Option<Vec<AccumulatorState>>, other: Option<Vec<AccumulatorState>>, comparison: ObjectComparison) {
+    match (states, other) {
+        (Some(states), Some(other)) => {
+            for (state, other) in states.iter_mut().zip(other) {
+                state.merge(other, comparison);
+            }
+        }
+        (states @ None, other) => *states = other,
+        (Some(_), None) => {}
+    }
+}
+
+impl Execute for BucketStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let states = self.partial(input, context)?;
+
+        Ok(self.finish([states], context.object_comparison()))
+    }
+}
+
+/// Sorts documents by the `group_by` value and splits them into the requested number of buckets
+/// with roughly the same number of documents. Equal values always end up in the same bucket.
+#[derive(Debug)]
+pub struct BucketAutoStage {
+    pub group_by: Expression,
+    pub buckets: usize,
+    pub output: Output,
+}
+
+impl From<(Expression, usize, Option<Output>)> for BucketAutoStage {
+    fn from((group_by, buckets, output): (Expression, usize, Option<Output>)) -> Self {
+        BucketAutoStage {
+            group_by,
+            buckets,
+            output: output.unwrap_or_default(),
+        }
+    }
+}
+
+impl Execute for BucketAutoStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut values = Vec::with_capacity(input.len());
+        for document in input {
+            values.push((eval_or_null(&self.group_by, &document, context)?, document));
+        }
+        let comparison = context.object_comparison();
+        values.sort_by(|(first, _), (second, _)| compare(first, second, comparison));
+
+        let bucket_size = values.len().div_ceil(self.buckets.max(1));
+        let mut buckets: Vec<(Dynamic, Dynamic, Vec<_>)> = Vec::with_capacity(self.buckets);
+        for (value, document) in values {
+            match buckets.last_mut() {
+                Some((_, max, bucket)) if bucket.len() < bucket_size || max.eq_with(&value, comparison) => {
+                    *max = value;
+                    bucket.push(document);
+                }
+                _ => buckets.push((value.clone(), value, vec![document])),
+            }
+        }
+
+        let bounds = buckets
+            .iter()
+            .skip(1)
+            .map(|(min, _, _)| min.clone())
+            .map(Some)
+            .chain([None])
+            .collect::<Vec<_>>();
+
+        let mut output = Vec::with_capacity(buckets.len());
+        for ((min, max, documents), next_min) in buckets.into_iter().zip(bounds) {
+            let mut states = self.output.init();
+            for document in &documents {
+                self.output.accumulate(&mut states, document, context)?;
+            }
+            let mut id = LinkedHashMap::new();
+            id.insert("min".into(), min);
+            id.insert("max".into(), next_min.unwrap_or(max));
+
+            let mut document = LinkedHashMap::new();
+            document.insert("_id".into(), Dynamic::from(id));
+            self.output.finish(states, &mut document);
+            output.push(Dynamic::from(document));
+        }
+
+        Ok(output)
+    }
+}
+
+/// Adds to the count of the group, keeping groups in the order in which they were first seen
+fn add_count(groups: &mut LinkedHashMap<OrdDynamic, i64>, key: Dynamic, count: i64) {
+    let key = OrdDynamic(key);
+    match groups.get_mut(&key) {
+        Some(group_count) => *group_count += count,
+        None => {
+            groups.insert(key, count);
+        }
+    }
+}
+
+/// Groups documents by the value of the expression and sorts the groups by their size in descending order
+#[derive(From, Debug)]
+pub struct SortByCountStage(pub Expression);
+
+impl SortByCountStage {
+    /// Counts documents of every group in the order in which the groups were first seen
+    pub fn partial(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<(Dynamic, i64)>, EvalError> {
+        let mut groups: LinkedHashMap<OrdDynamic, i64> = LinkedHashMap::new();
+        for document in &input {
+            let value = eval_or_null(&self.0, document, context)?;
+            add_count(&mut groups, value, 1);
+        }
+
+        Ok(groups.into_iter().map(|(key, count)| (key.0, count)).collect())
+    }
+
+    pub fn finish(&self, partials: impl IntoIterator<Item = Vec<(Dynamic, i64)>>) -> Vec<Dynamic> {
+        let mut counts: LinkedHashMap<OrdDynamic, i64> = LinkedHashMap::new();
+        for (key, partial_count) in partials.into_iter().flatten() {
+            add_count(&mut counts, key, partial_count);
+        }
+        let mut groups: Vec<(Dynamic, i64)> = counts.into_iter().map(|(key, count)| (key.0, count)).collect();
+        groups.sort_by(|(_, first), (_, second)| second.cmp(first));
+
+        groups
+            .into_iter()
+            .map(|(id, count)| {
+                let mut document = LinkedHashMap::new();
+                document.insert("_id".into(), id);
+                document.insert("count".into(), Dynamic::from(count));
+                Dynamic::from(document)
+            })
+            .collect()
+    }
+}
+
+impl Execute for SortByCountStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let groups = self.partial(input, context)?;
+
+        Ok(self.finish([groups]))
+    }
+}
+
+/// Replaces every document with the result of the expression, which has to evaluate to an object.
+/// Used for both `$replaceRoot` and `$replaceWith`.
+#[derive(From, Debug)]
+pub struct ReplaceRootStage(pub Expression);
+
+impl Execute for ReplaceRootStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let mut output = Vec::with_capacity(input.len());
+        for document in &input {
+            let new_root = eval_with_document(&self.0, document, context)?;
+            if !new_root.is_object() {
+                return Err(EvalError::from(DynamicError::NotAnObject));
+            }
+            output.push(new_root)
+        }
+
+        Ok(output)
+    }
+}
+
+#[derive(From, Debug)]
+pub struct UnsetStage(pub Vec<Field>);
+
+impl Execute for UnsetStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, _: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        input
+            .into_iter()
+            .map(|document| {
+                self.0
+                    .iter()
+                    .try_fold(document, |document, field| without_path(&document, field.path()))
+            })
+            .collect()
+    }
+}
+
+/// Returns a copy of the document without the value at the path.
+/// Only objects and arrays along the path are copied, the input document is left untouched.
+/// A member of an array is removed from every object in the array, as in `items.secret`.
+/// The path has to end with a member and can only address single items of arrays,
+/// other paths fail with `EvalError::UnsupportedPath`.
+fn without_path(document: &Dynamic, path: &VariablePath) -> Result<Dynamic, EvalError> {
+    let keys = keys(path)?;
+    if !matches!(keys.last(), Some(Key::Member(_))) {
+        return Err(EvalError::UnsupportedPath);
+    }
+
+    Ok(without_keys(document, &keys))
+}
+
+/// Segment of a path which addresses a single value
+enum Key<'a> {
+    Member(&'a str),
+    Index(usize),
+}
+
+/// Splits the path into keys, paths with wildcards or slices are unsupported
+fn keys(path: &VariablePath) -> Result<Vec<Key<'_>>, EvalError> {
+    match path {
+        VariablePath::BaseVariable(variable) => Ok(vec![Key::Member(&variable.field)]),
+        VariablePath::InnerField { base, field } => {
+            let mut keys = keys(base)?;
+            keys.push(match field {
+                InnerField::MemberAccess(member_access) => Key::Member(&member_access.member),
+                InnerField::ArrayIndex(array_index) => Key::Index(array_index.index),
+                _ => return Err(EvalError::UnsupportedPath),
+            });
+            Ok(keys)
+        }
+    }
+}
+
+fn without_keys(value: &Dynamic, keys: &[Key]) -> Dynamic {
+    let Some((key, rest)) = keys.split_first() else {
+        return value.clone();
+    };
+    match (value, key) {
+        (Dynamic::Object(object), Key::Member(member)) => match object.get(member) {
+            Some(field) => {
+                let mut map = object.to_map();
+                if rest.is_empty() {
+                    map.remove(*member);
+                } else {
+                    map.insert((*member).into(), without_keys(&field, rest));
+                }
+                Dynamic::from(map)
+            }
+            None => value.clone(),
+        },
+        (Dynamic::Array(array), Key::Member(_)) => Dynamic::from(
+            array
+                .iter()
+                .map(|item| if item.is_object() { without_keys(&item, keys) } else { item })
+                .collect::<Vec<_>>(),
+        ),
+        (Dynamic::Array(array), Key::Index(index)) => {
+            let mut items = array.items().into_owned();
+            match items.get_mut(*index) {
+                Some(item) => *item = without_keys(item, rest),
+                None => return value.clone(),
+            }
+            Dynamic::from(items)
+        }
+        _ => value.clone(),
+    }
+}
+
+/// Returns a copy of the document with the value at the path replaced, copying only objects along the path.
+fn with_path(document: &Dynamic, path: &VariablePath, value: Dynamic) -> Result<Dynamic, EvalError> {
+    match path {
+        VariablePath::BaseVariable(variable) => {
+            let mut map = document.as_object().map(|object| object.to_map()).unwrap_or_default();
+            map.insert(variable.field.clone(), value);
+            Ok(Dynamic::from(map))
+        }
+        VariablePath::InnerField { base, field } => {
+            let parent = base.resolve(document).unwrap_or(Dynamic::Null);
+            let parent = match field {
+                InnerField::MemberAccess(member_access) => {
+                    with_path(&parent, &VariablePath::BaseVariable(member_access.member.clone().into()), value)?
+                }
+                InnerField::ArrayIndex(array_index) => {
+                    let mut array = parent
+                        .as_dynamic_array()
+                        .map(Array::to_items)
+                        .unwrap_or_default();
+                    if let Some(item) = array.get_mut(array_index.index) {
+                        *item = value;
+                    }
+                    Dynamic::from(array)
+                }
+                _ => return Err(EvalError::UnsupportedPath),
+            };
+            with_path(document, base, parent)
+        }
+    }
+}
+
+/// Emits a single document with the number of input documents stored under the given name
+#[derive(From, Debug)]
+pub struct CountStage(pub String);
+
+impl CountStage {
+    pub fn finish(&self, partials: impl IntoIterator<Item = usize>) -> Vec<Dynamic> {
+        let count = partials.into_iter().sum::<usize>();
+        if count == 0 {
+            return Vec::new();
+        }
+        let mut document = LinkedHashMap::new();
+        document.insert(self.0.clone(), Dynamic::from(count as i64));
+
+        vec![Dynamic::from(document)]
+    }
+}
+
+impl Execute for CountStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, _: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        Ok(self.finish([input.len()]))
+    }
+}
+
+/// Picks `size` random documents with reservoir sampling.
+/// The same seed always produces the same sample for the same input.
+#[derive(Debug)]
+pub struct SampleStage {
+    pub size: usize,
+    pub seed: Option<u64>,
+}
+
+impl From<(usize, Option<u64>)> for SampleStage {
+    fn from((size, seed): (usize, Option<u64>)) -> Self {
+        SampleStage { size, seed }
+    }
+}
+
+impl Execute for SampleStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, _: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let seed = self
+            .seed
+            .unwrap_or_else(|| RandomState::new().hash_one(input.len()));
+        let mut random = SplitMix64(seed);
+        let mut reservoir = Vec::with_capacity(self.size.min(input.len()));
+        for (index, document) in input.into_iter().enumerate() {
+            if index < self.size {
+                reservoir.push(document);
+            } else {
+                let position = (random.next() % (index as u64 + 1)) as usize;
+                if position < self.size {
+                    reservoir[position] = document;
+                }
+            }
+        }
+
+        Ok(reservoir)
+    }
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut value = self.0;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D049BB133111EB);
+        value ^ (value >> 31)
+    }
+}
+
+/// Appends the documents of a registered collection, optionally processed by a pipeline.
+/// Unknown collections are treated as empty.
+#[derive(Debug)]
+pub struct UnionWithStage {
+    pub collection: String,
+    pub pipeline: Option<Pipeline>,
+}
+
+impl From<(String, Option<Pipeline>)> for UnionWithStage {
+    fn from((collection, pipeline): (String, Option<Pipeline>)) -> Self {
+        UnionWithStage { collection, pipeline }
+    }
+}
+
+impl Execute for UnionWithStage {
+    fn execute_with_context(&self, mut input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let documents = context
+            .get_collection(&self.collection)
+            .map(<[Dynamic]>::to_vec)
+            .unwrap_or_default();
+        let documents = match self.pipeline {
+            Some(ref pipeline) => pipeline.execute_with_context(documents, context)?,
+            None => documents,
+        };
+        input.extend(documents);
+
+        Ok(input)
+    }
+}
+
+/// Recursively searches a registered collection breadth-first, starting with the values of `start_with`
+/// and following `connect_from_field` of every found document to `connect_to_field` of other documents.
+/// Every document of the collection is visited at most once, so cycles in the graph are not followed.
+#[derive(Debug)]
+pub struct GraphLookupStage {
+    pub from: String,
+    pub start_with: Expression,
+    pub connect_from_field: Field,
+    pub connect_to_field: Field,
+    pub r#as: Field,
+    pub max_depth: Option<u64>,
+    pub depth_field: Option<Field>,
+    pub restrict_search_with_match: Option<Predicate>,
+}
+
+impl Execute for GraphLookupStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let collection = context
+            .get_collection(&self.from)
+            .map(<[Dynamic]>::to_vec)
+            .unwrap_or_default();
+        // Documents of the collection which can be found, by the values of their `connect_to_field`
+        let mut candidates = Vec::with_capacity(collection.len());
+        let mut connected = HashMap::<OrdDynamic, Vec<usize>>::new();
+        for document in collection {
+            let matches = match self.restrict_search_with_match {
+                Some(ref predicate) => predicate.test_with_context(document.clone(), context)?,
+                None => true,
+            };
+            if matches {
+                for value in values(self.connect_to_field.resolve(&document)) {
+                    connected.entry(OrdDynamic(value)).or_default().push(candidates.len());
+                }
+                candidates.push(document);
+            }
+        }
+
+        let mut output = Vec::with_capacity(input.len());
+        for document in input {
+            // Documents without the field of `start_with` have no start values
+            let start_with = eval_or_null(&self.start_with, &document, context)?;
+            let mut searched = HashSet::new();
+            let mut frontier = values(start_with).into_iter().map(OrdDynamic).filter(|value| searched.insert(value.clone())).collect::<Vec<_>>();
+            let mut visited = HashSet::new();
+            let mut found = Vec::new();
+            let mut depth = 0;
+            while !frontier.is_empty() && self.max_depth.is_none_or(|max_depth| depth <= max_depth) {
+                // Documents found at the same depth keep the order of the collection
+                let mut indices = frontier
+                    .iter()
+                    .filter_map(|value| connected.get(value))
+                    .flatten()
+                    .copied()
+                    .filter(|&index| visited.insert(index))
+                    .collect::<Vec<_>>();
+                indices.sort_unstable();
+                let mut next_frontier = Vec::new();
+                for index in indices {
+                    let candidate = &candidates[index];
+                    let connect_from = values(self.connect_from_field.resolve(candidate)).into_iter().map(OrdDynamic);
+                    next_frontier.extend(connect_from.filter(|value| searched.insert(value.clone())));
+                    found.push(match self.depth_field {
+                        Some(ref depth_field) => with_path(candidate, depth_field.path(), Dynamic::from(depth as i64))?,
+                        None => candidate.clone(),
+                    });
+                }
+                frontier = next_frontier;
+                depth += 1;
+            }
+            output.push(with_path(&document, self.r#as.path(), Dynamic::from(found))?);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Elements of an array or the value itself, missing values are ignored
+fn values(value: Dynamic) -> Vec<Dynamic> {
+    match value {
+        Dynamic::Null => Vec::new(),
+        Dynamic::Array(array) => array.to_vec(),
+        value => vec![value],
+    }
+}
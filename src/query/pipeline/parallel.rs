@@ -0,0 +1,155 @@
+use std::num::NonZeroUsize;
+use std::{panic, thread};
+use crate::{Dynamic, ObjectComparison};
+use crate::query::pipeline::{BucketStates, Execute, GroupStates, Pipeline, SortedDocuments, Stage};
+use crate::query::{Context, EvalError};
+
+/// Result of a grouping stage computed over one part of the input
+enum Partial {
+    Documents(Vec<Dynamic>),
+    Bucket(BucketStates),
+    SortByCount(Vec<(Dynamic, i64)>),
+    Count(usize),
+    Group(GroupStates),
+    Sort(SortedDocuments),
+}
+
+impl Partial {
+    fn into_documents(self) -> Vec<Dynamic> {
+        match self {
+            Partial::Documents(documents) => documents,
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Stage {
+    /// Whether the stage processes every document independently of the other documents
+    pub fn is_stateless(&self) -> bool {
+        matches!(
+            self,
+            Stage::Match(_)
+                | Stage::Project(_)
+                | Stage::AddFields(_)
+                | Stage::Unwind(_)
+                | Stage::ReplaceRoot(_)
+                | Stage::Unset(_)
+        )
+    }
+
+    /// Whether the stage can be computed over parts of the input and merged afterwards
+    pub fn is_mergeable(&self) -> bool {
+        matches!(
+            self,
+            Stage::Bucket(_) | Stage::SortByCount(_) | Stage::Count(_) | Stage::Group(_) | Stage::Sort(_)
+        )
+    }
+
+    fn partial(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Partial, EvalError> {
+        match self {
+            Stage::Bucket(bucket) => bucket.partial(input, context).map(Partial::Bucket),
+            Stage::SortByCount(sort_by_count) => sort_by_count.partial(input, context).map(Partial::SortByCount),
+            Stage::Count(_) => Ok(Partial::Count(input.len())),
+            Stage::Group(group) => group.partial(input, context).map(Partial::Group),
+            Stage::Sort(sort) => Ok(Partial::Sort(sort.partial(input, context.object_comparison()))),
+            stage => stage.execute_with_context(input, context).map(Partial::Documents),
+        }
+    }
+
+    fn finish(&self, partials: Vec<Partial>, comparison: ObjectComparison) -> Vec<Dynamic> {
+        let partials = partials.into_iter();
+        match self {
+            Stage::Bucket(bucket) => bucket.finish(partials.filter_map(|partial| match partial {
+                Partial::Bucket(states) => Some(states),
+                _ => None,
+            }), comparison),
+            Stage::SortByCount(sort_by_count) => sort_by_count.finish(partials.filter_map(|partial| match partial {
+                Partial::SortByCount(groups) => Some(groups),
+                _ => None,
+            })),
+            Stage::Count(count) => count.finish(partials.filter_map(|partial| match partial {
+                Partial::Count(count) => Some(count),
+                _ => None,
+            })),
+            Stage::Group(group) => group.finish(partials.filter_map(|partial| match partial {
+                Partial::Group(groups) => Some(groups),
+                _ => None,
+            }), comparison),
+            Stage::Sort(sort) => sort.finish(partials.filter_map(|partial| match partial {
+                Partial::Sort(documents) => Some(documents),
+                _ => None,
+            }), comparison),
+            _ => partials.flat_map(Partial::into_documents).collect(),
+        }
+    }
+}
+
+impl Pipeline {
+    /// Executes the pipeline using all available cores, see [`Pipeline::execute_parallel_with_context`]
+    pub fn execute_parallel(&self, input: impl IntoIterator<Item = Dynamic>) -> Result<Vec<Dynamic>, EvalError> {
+        let threads = thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+        let mut context = Context::new();
+
+        self.execute_parallel_with_context(input, &mut context, threads)
+    }
+
+    /// Splits the input into contiguous parts and runs the stateless prefix of the pipeline
+    /// on every part in a separate thread, each with its own fork of the context.
+    /// A grouping stage right after the prefix is computed partially by every thread and merged afterwards.
+    /// The remaining stages are executed sequentially. The order of documents is the same as with
+    /// [`Pipeline::execute_with_context`].
+    pub fn execute_parallel_with_context(
+        &self,
+        input: impl IntoIterator<Item = Dynamic>,
+        context: &mut Context,
+        threads: NonZeroUsize,
+    ) -> Result<Vec<Dynamic>, EvalError> {
+        let input = input.into_iter().collect::<Vec<_>>();
+        let prefix_length = self.0.iter().take_while(|stage| stage.is_stateless()).count();
+        let (prefix, rest) = self.0.split_at(prefix_length);
+        let (merged, rest) = match rest.split_first() {
+            Some((stage, rest)) if stage.is_mergeable() => (Some(stage), rest),
+            _ => (None, rest),
+        };
+
+        let chunk_size = input.len().div_ceil(threads.get()).max(1);
+        let mut input = input.into_iter();
+        let chunks = std::iter::from_fn(|| {
+            let chunk = input.by_ref().take(chunk_size).collect::<Vec<_>>();
+            (!chunk.is_empty()).then_some(chunk)
+        });
+
+        let partials = thread::scope(|scope| {
+            let handles = chunks
+                .map(|chunk| {
+                    let mut context = context.fork();
+                    scope.spawn(move || {
+                        let mut documents = chunk;
+                        for stage in prefix {
+                            documents = stage.execute_with_context(documents, &mut context)?;
+                        }
+                        match merged {
+                            Some(stage) => stage.partial(documents, &mut context),
+                            None => Ok(Partial::Documents(documents)),
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|error| panic::resume_unwind(error)))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        let mut documents = match merged {
+            Some(stage) => stage.finish(partials, context.object_comparison()),
+            None => partials.into_iter().flat_map(Partial::into_documents).collect(),
+        };
+        for stage in rest {
+            documents = stage.execute_with_context(documents, context)?;
+        }
+
+        Ok(documents)
+    }
+}
@@ -0,0 +1,496 @@
+use super::{AddFieldsStage, BucketAutoStage, BucketStage, CountStage, FacetStage, GraphLookupStage, GroupStage, MatchStage, Pipeline, ProjectStage, Projection, ReplaceRootStage, SampleStage, SortBy, SortByCountStage, SortOrder, SortStage, Stage, UnionWithStage, UnsetStage, UnwindStage};
+use crate::query::pipeline::window::{SetWindowFieldsStage, Window, WindowBound, WindowFunction, WindowOutput};
+use crate::query::ast::parser::{arguments, expression, optional_named_arguments};
+use crate::query::pipeline::accumulator::{Accumulator, Output};
+use crate::query::parser::{array, array_of, boolean, escaped_string, field, field_path, fields_of, number, object_of, operator_pair, predicate, string, unescaped, value, ws};
+use crate::query::ast::Field;
+use crate::query::ast::expression::Expression;
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::Number;
+use crate::Dynamic;
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, i64, u64 as unsigned};
+use nom::combinator::{all_consuming, cut, map, map_opt, peek, value as get_value, verify};
+use nom::multi::{separated_list0, separated_list1};
+use nom::number::complete::double;
+use nom::sequence::{delimited, pair, preceded, separated_pair};
+use nom::IResult;
+
+pub fn parse_pipeline(str: &str) -> IResult<&str, Pipeline> {
+    all_consuming(ws(pipeline))(str)
+}
+
+pub fn pipeline(str: &str) -> IResult<&str, Pipeline> {
+    map(array_of(stage), Pipeline::from)(str)
+}
+
+pub fn stage(str: &str) -> IResult<&str, Stage> {
+    delimited(
+        preceded(ws(char('{')), verify(peek(escaped_string), |str: &str| str.starts_with('$'))),
+        cut(alt((
+            map(match_stage, Stage::from),
+            map(project_stage, Stage::from),
+            map(add_fields_stage, Stage::from),
+            map(unwind_stage, Stage::from),
+            map(group_stage, Stage::from),
+            map(sort_stage, Stage::from),
+            map(facet_stage, Stage::from),
+            map(bucket_auto_stage, Stage::from),
+            map(bucket_stage, Stage::from),
+            map(sort_by_count_stage, Stage::from),
+            map(set_window_fields_stage, Stage::from),
+            map(replace_root_stage, Stage::from),
+            map(unset_stage, Stage::from),
+            map(count_stage, Stage::from),
+            map(sample_stage, Stage::from),
+            map(union_with_stage, Stage::from),
+            map(graph_lookup_stage, Stage::from),
+        ))),
+        ws(char('}')),
+    )(str)
+}
+
+pub fn match_stage(str: &str) -> IResult<&str, MatchStage> {
+    map(
+        operator_pair("$match", cut(predicate)),
+        MatchStage::from,
+    )(str)
+}
+
+pub fn project_stage(str: &str) -> IResult<&str, ProjectStage> {
+    map(
+        operator_pair(
+            "$project",
+            cut(verify(fields_of(projection), |fields: &Vec<(Field, Projection)>| {
+                let is_exclusion = fields.iter().all(|(_, projection)| matches!(projection, Projection::Exclude));
+                !fields.is_empty()
+                    && (is_exclusion || fields.iter().all(|(field, projection)| {
+                        !matches!(projection, Projection::Exclude) || super::is_id(field)
+                    }))
+            })),
+        ),
+        ProjectStage::from,
+    )(str)
+}
+
+pub fn projection(str: &str) -> IResult<&str, Projection> {
+    alt((
+        map_opt(number, |number| match number {
+            Number::Int(1) => Some(Projection::Include),
+            Number::Int(0) => Some(Projection::Exclude),
+            _ => None,
+        }),
+        map(boolean, |include| if include { Projection::Include } else { Projection::Exclude }),
+        map(expression, Projection::Computed),
+    ))(str)
+}
+
+pub fn add_fields_stage(str: &str) -> IResult<&str, AddFieldsStage> {
+    map(
+        alt((
+            operator_pair("$addFields", cut(fields_of(expression))),
+            operator_pair("$set", cut(fields_of(expression))),
+        )),
+        AddFieldsStage::from,
+    )(str)
+}
+
+pub fn unwind_stage(str: &str) -> IResult<&str, UnwindStage> {
+    map(
+        operator_pair(
+            "$unwind",
+            cut(alt((
+                map(unwind_path, |path| (path, None, false)),
+                map_opt(
+                    optional_named_arguments((
+                        operator_pair("path", unwind_path),
+                        operator_pair("includeArrayIndex", field),
+                        operator_pair("preserveNullAndEmptyArrays", boolean),
+                    )),
+                    |(path, include_array_index, preserve)| Some((path?, include_array_index, preserve.unwrap_or(false))),
+                ),
+            ))),
+        ),
+        UnwindStage::from,
+    )(str)
+}
+
+/// Field path of the array which is unwound, in the form of `"$field"`
+fn unwind_path(str: &str) -> IResult<&str, Field> {
+    unescaped(|str: &str| map(preceded(char('$'), field_path), Field::from)(str))(str)
+}
+
+/// Field of a `$group` stage, either the `_id` expression or a named accumulator
+enum GroupField {
+    Id(Expression),
+    Output(String, Accumulator),
+}
+
+pub fn group_stage(str: &str) -> IResult<&str, GroupStage> {
+    operator_pair(
+        "$group",
+        cut(map_opt(
+            delimited(
+                ws(char('{')),
+                separated_list0(
+                    ws(char(',')),
+                    alt((
+                        map(operator_pair("_id", cut(expression)), GroupField::Id),
+                        map(
+                            separated_pair(ws(string), char(':'), ws(accumulator)),
+                            |(name, accumulator)| GroupField::Output(name, accumulator),
+                        ),
+                    )),
+                ),
+                cut(ws(char('}'))),
+            ),
+            |fields| {
+                let mut id = None;
+                let mut output = LinkedHashMap::new();
+                for field in fields {
+                    match field {
+                        GroupField::Id(_) if id.is_some() => return None,
+                        GroupField::Id(expression) => id = Some(expression),
+                        GroupField::Output(name, accumulator) => {
+                            output.insert(name, accumulator);
+                        }
+                    }
+                }
+                Some(GroupStage { id: id?, output: Output::from(output) })
+            },
+        )),
+    )(str)
+}
+
+pub fn sort_stage(str: &str) -> IResult<&str, SortStage> {
+    map(operator_pair("$sort", cut(sort_by)), SortStage::from)(str)
+}
+
+pub fn facet_stage(str: &str) -> IResult<&str, FacetStage> {
+    map(
+        operator_pair("$facet", cut(object_of(pipeline))),
+        FacetStage::from,
+    )(str)
+}
+
+pub fn bucket_stage(str: &str) -> IResult<&str, BucketStage> {
+    map(
+        operator_pair(
+            "$bucket",
+            cut(map_opt(
+                optional_named_arguments((
+                    operator_pair("groupBy", expression),
+                    operator_pair("boundaries", verify(array, |boundaries: &Vec<_>| {
+                        let boundaries = boundaries.iter().map(Dynamic::from).collect::<Vec<_>>();
+                        boundaries.len() >= 2 && boundaries.windows(2).all(|bounds| bounds[0] < bounds[1])
+                    })),
+                    operator_pair("default", value),
+                    operator_pair("output", output),
+                )),
+                |(group_by, boundaries, default, output)| Some((group_by?, boundaries?, default, output)),
+            )),
+        ),
+        BucketStage::from,
+    )(str)
+}
+
+pub fn bucket_auto_stage(str: &str) -> IResult<&str, BucketAutoStage> {
+    map(
+        operator_pair(
+            "$bucketAuto",
+            cut(map_opt(
+                optional_named_arguments((
+                    operator_pair("groupBy", expression),
+                    operator_pair("buckets", verify(unsigned, |buckets| *buckets > 0)),
+                    operator_pair("output", output),
+                )),
+                |(group_by, buckets, output)| Some((group_by?, buckets? as usize, output)),
+            )),
+        ),
+        BucketAutoStage::from,
+    )(str)
+}
+
+pub fn sort_by_count_stage(str: &str) -> IResult<&str, SortByCountStage> {
+    map(
+        operator_pair("$sortByCount", cut(expression)),
+        SortByCountStage::from,
+    )(str)
+}
+
+pub fn set_window_fields_stage(str: &str) -> IResult<&str, SetWindowFieldsStage> {
+    map(
+        operator_pair(
+            "$setWindowFields",
+            cut(verify(
+                map_opt(
+                    optional_named_arguments((
+                        operator_pair("partitionBy", expression),
+                        operator_pair("sortBy", sort_by),
+                        operator_pair("output", object_of(window_output)),
+                    )),
+                    |(partition_by, sort_by, output)| Some((partition_by, sort_by, output?)),
+                ),
+                |(_, sort_by, output)| {
+                    sort_by.is_some() || output.values().all(|output| {
+                        !output.function.requires_sort_by() && !matches!(output.window, Some(Window::Range(..)))
+                    })
+                },
+            )),
+        ),
+        SetWindowFieldsStage::from,
+    )(str)
+}
+
+pub fn sort_by(str: &str) -> IResult<&str, SortBy> {
+    map(
+        delimited(
+            ws(char('{')),
+            separated_list1(
+                ws(char(',')),
+                separated_pair(ws(field), char(':'), ws(sort_order)),
+            ),
+            cut(ws(char('}'))),
+        ),
+        SortBy::from,
+    )(str)
+}
+
+pub fn sort_order(str: &str) -> IResult<&str, SortOrder> {
+    map_opt(i64, |order| match order {
+        1 => Some(SortOrder::Ascending),
+        -1 => Some(SortOrder::Descending),
+        _ => None,
+    })(str)
+}
+
+pub fn window_output(str: &str) -> IResult<&str, WindowOutput> {
+    map(
+        map_opt(
+            optional_named_arguments((window_function, operator_pair("window", window))),
+            |(function, window)| Some((function?, window)),
+        ),
+        WindowOutput::from,
+    )(str)
+}
+
+pub fn window_function(str: &str) -> IResult<&str, WindowFunction> {
+    alt((
+        map(accumulator_operator, WindowFunction::Accumulator),
+        map(operator_pair("$rank", cut(empty_object)), |_| WindowFunction::Rank),
+        map(operator_pair("$denseRank", cut(empty_object)), |_| WindowFunction::DenseRank),
+        map(operator_pair("$documentNumber", cut(empty_object)), |_| WindowFunction::DocumentNumber),
+        operator_pair(
+            "$shift",
+            cut(map_opt(
+                optional_named_arguments((
+                    operator_pair("output", expression),
+                    operator_pair("by", i64),
+                    operator_pair("default", expression),
+                )),
+                |(output, by, default)| Some(WindowFunction::Shift { output: output?, by: by?, default }),
+            )),
+        ),
+        operator_pair(
+            "$derivative",
+            cut(map_opt(
+                optional_named_arguments((operator_pair("input", expression),)),
+                |(input,)| Some(WindowFunction::Derivative(input?)),
+            )),
+        ),
+        operator_pair(
+            "$integral",
+            cut(map_opt(
+                optional_named_arguments((operator_pair("input", expression),)),
+                |(input,)| Some(WindowFunction::Integral(input?)),
+            )),
+        ),
+        operator_pair(
+            "$expMovingAvg",
+            cut(map_opt(
+                optional_named_arguments((
+                    operator_pair("input", expression),
+                    operator_pair("N", verify(unsigned, |n| *n > 0)),
+                    operator_pair("alpha", verify(double, |alpha| *alpha > 0.0 && *alpha < 1.0)),
+                )),
+                |(input, n, alpha)| {
+                    let alpha = match (n, alpha) {
+                        (Some(n), None) => 2.0 / (n as f64 + 1.0),
+                        (None, Some(alpha)) => alpha,
+                        _ => return None,
+                    };
+                    Some(WindowFunction::ExpMovingAvg { input: input?, alpha })
+                },
+            )),
+        ),
+    ))(str)
+}
+
+pub fn window(str: &str) -> IResult<&str, Window> {
+    delimited(
+        ws(char('{')),
+        alt((
+            map(
+                operator_pair("documents", cut(arguments((document_bound, document_bound)))),
+                |(lower, upper)| Window::Documents(lower, upper),
+            ),
+            map(
+                operator_pair("range", cut(arguments((range_bound, range_bound)))),
+                |(lower, upper)| Window::Range(lower, upper),
+            ),
+        )),
+        cut(ws(char('}'))),
+    )(str)
+}
+
+pub fn document_bound(str: &str) -> IResult<&str, WindowBound> {
+    alt((
+        named_bound,
+        map(i64, |offset| WindowBound::Offset(Number::Int(offset))),
+    ))(str)
+}
+
+pub fn range_bound(str: &str) -> IResult<&str, WindowBound> {
+    alt((named_bound, map(number, WindowBound::Offset)))(str)
+}
+
+fn named_bound(str: &str) -> IResult<&str, WindowBound> {
+    alt((
+        get_value(WindowBound::Unbounded, tag(r#""unbounded""#)),
+        get_value(WindowBound::Current, tag(r#""current""#)),
+    ))(str)
+}
+
+pub fn replace_root_stage(str: &str) -> IResult<&str, ReplaceRootStage> {
+    map(
+        alt((
+            operator_pair(
+                "$replaceRoot",
+                cut(delimited(
+                    ws(char('{')),
+                    operator_pair("newRoot", cut(expression)),
+                    cut(ws(char('}'))),
+                )),
+            ),
+            operator_pair("$replaceWith", cut(expression)),
+        )),
+        ReplaceRootStage::from,
+    )(str)
+}
+
+pub fn unset_stage(str: &str) -> IResult<&str, UnsetStage> {
+    map(
+        operator_pair(
+            "$unset",
+            cut(alt((map(field, |field| vec![field]), array_of(field)))),
+        ),
+        UnsetStage::from,
+    )(str)
+}
+
+pub fn count_stage(str: &str) -> IResult<&str, CountStage> {
+    map(
+        operator_pair(
+            "$count",
+            cut(verify(string, |name: &str| !name.is_empty() && !name.starts_with('$') && !name.contains('.'))),
+        ),
+        CountStage::from,
+    )(str)
+}
+
+pub fn sample_stage(str: &str) -> IResult<&str, SampleStage> {
+    map(
+        operator_pair(
+            "$sample",
+            cut(map_opt(
+                optional_named_arguments((
+                    operator_pair("size", unsigned),
+                    operator_pair("seed", unsigned),
+                )),
+                |(size, seed)| Some((size? as usize, seed)),
+            )),
+        ),
+        SampleStage::from,
+    )(str)
+}
+
+pub fn union_with_stage(str: &str) -> IResult<&str, UnionWithStage> {
+    map(
+        operator_pair(
+            "$unionWith",
+            cut(alt((
+                map(string, |collection| (collection, None)),
+                map_opt(
+                    optional_named_arguments((
+                        operator_pair("coll", string),
+                        operator_pair("pipeline", pipeline),
+                    )),
+                    |(collection, pipeline)| Some((collection?, pipeline)),
+                ),
+            ))),
+        ),
+        UnionWithStage::from,
+    )(str)
+}
+
+pub fn graph_lookup_stage(str: &str) -> IResult<&str, GraphLookupStage> {
+    operator_pair(
+        "$graphLookup",
+        cut(map_opt(
+            optional_named_arguments((
+                operator_pair("from", string),
+                operator_pair("startWith", expression),
+                operator_pair("connectFromField", field),
+                operator_pair("connectToField", field),
+                operator_pair("as", field),
+                operator_pair("maxDepth", unsigned),
+                operator_pair("depthField", field),
+                operator_pair("restrictSearchWithMatch", predicate),
+            )),
+            |(from, start_with, connect_from_field, connect_to_field, r#as, max_depth, depth_field, restrict_search_with_match)| {
+                Some(GraphLookupStage {
+                    from: from?,
+                    start_with: start_with?,
+                    connect_from_field: connect_from_field?,
+                    connect_to_field: connect_to_field?,
+                    r#as: r#as?,
+                    max_depth,
+                    depth_field,
+                    restrict_search_with_match,
+                })
+            },
+        )),
+    )(str)
+}
+
+pub fn output(str: &str) -> IResult<&str, Output> {
+    map(object_of(accumulator), Output::from)(str)
+}
+
+pub fn accumulator(str: &str) -> IResult<&str, Accumulator> {
+    delimited(
+        ws(char('{')),
+        accumulator_operator,
+        cut(ws(char('}'))),
+    )(str)
+}
+
+pub fn accumulator_operator(str: &str) -> IResult<&str, Accumulator> {
+    alt((
+        map(operator_pair("$sum", cut(expression)), Accumulator::Sum),
+        map(operator_pair("$avg", cut(expression)), Accumulator::Avg),
+        map(operator_pair("$min", cut(expression)), Accumulator::Min),
+        map(operator_pair("$max", cut(expression)), Accumulator::Max),
+        map(operator_pair("$first", cut(expression)), Accumulator::First),
+        map(operator_pair("$last", cut(expression)), Accumulator::Last),
+        map(operator_pair("$push", cut(expression)), Accumulator::Push),
+        map(operator_pair("$addToSet", cut(expression)), Accumulator::AddToSet),
+        map(operator_pair("$count", cut(empty_object)), |_| Accumulator::Count),
+    ))(str)
+}
+
+pub fn empty_object(str: &str) -> IResult<&str, ()> {
+    get_value((), pair(ws(char('{')), ws(char('}'))))(str)
+}
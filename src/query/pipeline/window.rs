@@ -0,0 +1,509 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::ops::Range;
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::{Dynamic, Number, ObjectComparison};
+use crate::ord::OrdDynamic;
+use crate::query::ast::expression::Expression;
+use crate::query::pipeline::accumulator::Accumulator;
+use crate::query::pipeline::{eval_with_document, Execute, SortBy, SortOrder};
+use crate::query::{Context, EvalError};
+
+/// Adds fields computed over a window of neighbouring documents in the same partition.
+/// Documents are emitted grouped by partition and ordered by `sortBy` inside each partition.
+#[derive(Debug)]
+pub struct SetWindowFieldsStage {
+    pub partition_by: Option<Expression>,
+    pub sort_by: Option<SortBy>,
+    pub output: LinkedHashMap<String, WindowOutput>,
+}
+
+impl From<(Option<Expression>, Option<SortBy>, LinkedHashMap<String, WindowOutput>)> for SetWindowFieldsStage {
+    fn from((partition_by, sort_by, output): (Option<Expression>, Option<SortBy>, LinkedHashMap<String, WindowOutput>)) -> Self {
+        SetWindowFieldsStage {
+            partition_by,
+            sort_by,
+            output,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WindowOutput {
+    pub function: WindowFunction,
+    pub window: Option<Window>,
+}
+
+impl From<(WindowFunction, Option<Window>)> for WindowOutput {
+    fn from((function, window): (WindowFunction, Option<Window>)) -> Self {
+        WindowOutput { function, window }
+    }
+}
+
+#[derive(Debug)]
+pub enum WindowFunction {
+    Accumulator(Accumulator),
+    Rank,
+    DenseRank,
+    DocumentNumber,
+    Shift {
+        output: Expression,
+        by: i64,
+        default: Option<Expression>,
+    },
+    Derivative(Expression),
+    Integral(Expression),
+    ExpMovingAvg {
+        input: Expression,
+        alpha: f64,
+    },
+}
+
+impl WindowFunction {
+    /// Whether the function can only be computed over sorted documents
+    pub fn requires_sort_by(&self) -> bool {
+        matches!(
+            self,
+            WindowFunction::Rank
+                | WindowFunction::DenseRank
+                | WindowFunction::Derivative(_)
+                | WindowFunction::Integral(_)
+                | WindowFunction::ExpMovingAvg { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowBound {
+    Unbounded,
+    Current,
+    Offset(Number),
+}
+
+/// Window bounds, either as document offsets from the current document
+/// or as an offset from the `sortBy` value of the current document
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Documents(WindowBound, WindowBound),
+    Range(WindowBound, WindowBound),
+}
+
+impl Window {
+    /// Returns inclusive bounds of the window for the document at `index`, or `None` if the window is empty
+    fn bounds(&self, index: usize, sort_keys: &RangeKeys) -> Option<(usize, usize)> {
+        let last = sort_keys.keys.len().checked_sub(1)?;
+        let (lower, upper) = match *self {
+            Window::Documents(lower, upper) => {
+                let offset = |bound, unbounded| match bound {
+                    WindowBound::Unbounded => unbounded,
+                    WindowBound::Current => index as i64,
+                    WindowBound::Offset(offset) => index as i64 + offset.as_f64() as i64,
+                };
+                (offset(lower, 0).max(0), offset(upper, last as i64).min(last as i64))
+            }
+            Window::Range(lower, upper) => {
+                let current = sort_keys.key(index)?;
+                let bound = |bound| match bound {
+                    WindowBound::Offset(offset) => current + offset.as_f64(),
+                    _ => current,
+                };
+                let lower = match lower {
+                    WindowBound::Unbounded => 0,
+                    lower => sort_keys.first_from(bound(lower))?,
+                };
+                let upper = match upper {
+                    WindowBound::Unbounded => last,
+                    upper => sort_keys.last_up_to(bound(upper))?,
+                };
+                (lower as i64, upper as i64)
+            }
+        };
+
+        (lower <= upper).then_some((lower as usize, upper as usize))
+    }
+}
+
+/// Numeric values of the first `sortBy` field of a partition, which range windows are bounded by.
+/// Numbers are next to each other in the sorted partition and ascend once they are multiplied
+/// by the direction of the sort, so bounds are found with a binary search.
+struct RangeKeys<'a> {
+    keys: &'a [Option<f64>],
+    numbers: Range<usize>,
+    direction: f64,
+}
+
+impl<'a> RangeKeys<'a> {
+    fn new(keys: &'a [Option<f64>], order: SortOrder) -> Self {
+        let start = keys.iter().position(Option::is_some).unwrap_or(keys.len());
+        let end = keys.iter().rposition(Option::is_some).map_or(start, |last| last + 1);
+        let direction = match order {
+            SortOrder::Ascending => 1.0,
+            SortOrder::Descending => -1.0,
+        };
+        RangeKeys { keys, numbers: start..end, direction }
+    }
+
+    /// Value of the document in the direction of the sort
+    fn key(&self, index: usize) -> Option<f64> {
+        self.keys[index].map(|key| key * self.direction)
+    }
+
+    /// Number of numeric documents before the first one whose value in the direction of the sort
+    /// satisfies the predicate, which has to hold for all later documents
+    fn partition_point(&self, predicate: impl Fn(f64) -> bool) -> usize {
+        self.numbers.start
+            + self.keys[self.numbers.clone()]
+                .partition_point(|key| key.is_none_or(|key| !predicate(key * self.direction)))
+    }
+
+    /// Index of the first document whose value is at least the bound
+    fn first_from(&self, bound: f64) -> Option<usize> {
+        let index = self.partition_point(|key| key >= bound);
+        (index < self.numbers.end).then_some(index)
+    }
+
+    /// Index of the last document whose value is at most the bound
+    fn last_up_to(&self, bound: f64) -> Option<usize> {
+        let index = self.partition_point(|key| key > bound);
+        (index > self.numbers.start).then(|| index - 1)
+    }
+}
+
+impl Execute for SetWindowFieldsStage {
+    fn execute_with_context(&self, input: Vec<Dynamic>, context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        // Sort keys and documents of every partition, in the order in which the partitions were first seen
+        let mut partitions: LinkedHashMap<OrdDynamic, Vec<(Vec<Dynamic>, Dynamic)>> = LinkedHashMap::new();
+        for document in input {
+            let key = match self.partition_by {
+                Some(ref partition_by) => eval_with_document(partition_by, &document, context)?,
+                None => Dynamic::Null,
+            };
+            let sort_keys = self.sort_by.as_ref().map(|sort_by| sort_by.keys(&document)).unwrap_or_default();
+            let key = OrdDynamic(key);
+            match partitions.get_mut(&key) {
+                Some(partition) => partition.push((sort_keys, document)),
+                None => {
+                    partitions.insert(key, vec![(sort_keys, document)]);
+                }
+            }
+        }
+
+        let order = self
+            .sort_by
+            .as_ref()
+            .and_then(|sort_by| sort_by.0.first())
+            .map_or(SortOrder::Ascending, |(_, order)| *order);
+        let mut output = Vec::new();
+        for (_, mut partition) in partitions {
+            if let Some(ref sort_by) = self.sort_by {
+                let comparison = context.object_comparison();
+                partition.sort_by(|(first, _), (second, _)| sort_by.compare(first, second, comparison));
+            }
+            let (sort_keys, documents): (Vec<_>, Vec<_>) = partition.into_iter().unzip();
+
+            let mut fields = vec![LinkedHashMap::new(); documents.len()];
+            for (name, window_output) in &self.output {
+                let values = window_output.compute(&sort_keys, order, &documents, context)?;
+                for (fields, value) in fields.iter_mut().zip(values) {
+                    fields.insert(name.clone(), value);
+                }
+            }
+
+            for (document, fields) in documents.into_iter().zip(fields) {
+                let mut document = document.as_object().map(|object| object.to_map()).unwrap_or_default();
+                document.extend(fields);
+                output.push(Dynamic::from(document));
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+impl WindowOutput {
+    /// Computes the output for the documents of a sorted partition, `order` being the order of the first `sortBy` field
+    fn compute(&self, sort_keys: &[Vec<Dynamic>], order: SortOrder, documents: &[Dynamic], context: &mut Context) -> Result<Vec<Dynamic>, EvalError> {
+        let numeric_keys = sort_keys
+            .iter()
+            .map(|keys| keys.first().and_then(Dynamic::as_number).map(Number::as_f64))
+            .collect::<Vec<_>>();
+        let range_keys = RangeKeys::new(&numeric_keys, order);
+        let window = self.window.unwrap_or(Window::Documents(WindowBound::Unbounded, WindowBound::Unbounded));
+        let windows = (0..documents.len()).map(|index| window.bounds(index, &range_keys));
+
+        let values = match self.function {
+            WindowFunction::Accumulator(ref accumulator) => {
+                let mut values = Vec::with_capacity(documents.len());
+                for document in documents {
+                    values.push(accumulator.value(document, context)?);
+                }
+                let mut sliding = SlidingWindow::new(accumulator, &values, context.object_comparison());
+                windows
+                    .map(|bounds| match bounds {
+                        Some((lower, upper)) => {
+                            sliding.slide(lower, upper);
+                            sliding.finish()
+                        }
+                        None => accumulator.init().finish(),
+                    })
+                    .collect()
+            }
+            WindowFunction::Rank => {
+                let mut rank = 0;
+                (0..documents.len())
+                    .map(|index| {
+                        if index == 0 || sort_keys[index] != sort_keys[index - 1] {
+                            rank = index + 1;
+                        }
+                        Dynamic::from(rank as i64)
+                    })
+                    .collect()
+            }
+            WindowFunction::DenseRank => {
+                let mut rank = 0;
+                (0..documents.len())
+                    .map(|index| {
+                        if index == 0 || sort_keys[index] != sort_keys[index - 1] {
+                            rank += 1;
+                        }
+                        Dynamic::from(rank)
+                    })
+                    .collect()
+            }
+            WindowFunction::DocumentNumber => (1..=documents.len() as i64).map(Dynamic::from).collect(),
+            WindowFunction::Shift { ref output, by, ref default } => {
+                let mut values = Vec::with_capacity(documents.len());
+                for index in 0..documents.len() as i64 {
+                    let value = match documents.get((index + by) as usize).filter(|_| index + by >= 0) {
+                        Some(document) => eval_with_document(output, document, context)?,
+                        None => match default {
+                            Some(default) => eval_with_document(default, &documents[index as usize], context)?,
+                            None => Dynamic::Null,
+                        },
+                    };
+                    values.push(value);
+                }
+                values
+            }
+            WindowFunction::Derivative(ref input) => {
+                let points = points(input, &numeric_keys, documents, context)?;
+                windows
+                    .map(|bounds| {
+                        let (lower, upper) = bounds.filter(|(lower, upper)| lower < upper)?;
+                        let ((x1, y1), (x2, y2)) = (points[lower]?, points[upper]?);
+                        (x2 != x1).then(|| Dynamic::from(Number::Float((y2 - y1) / (x2 - x1))))
+                    })
+                    .map(|value| value.unwrap_or(Dynamic::Null))
+                    .collect()
+            }
+            WindowFunction::Integral(ref input) => {
+                let points = points(input, &numeric_keys, documents, context)?;
+                // Area up to every point, so the area of a window is the difference of two areas
+                let mut areas = Vec::with_capacity(points.len());
+                let (mut area, mut previous) = (0.0, None);
+                for point in &points {
+                    if let Some((x, y)) = *point {
+                        if let Some((previous_x, previous_y)) = previous {
+                            area += (x - previous_x) * (previous_y + y) / 2.0;
+                        }
+                        previous = Some((x, y));
+                    }
+                    areas.push(area);
+                }
+                // Index of the first point at or after every document
+                let mut next_points = vec![None; points.len()];
+                let mut next = None;
+                for index in (0..points.len()).rev() {
+                    if points[index].is_some() {
+                        next = Some(index);
+                    }
+                    next_points[index] = next;
+                }
+                windows
+                    .map(|bounds| {
+                        let (lower, upper) = bounds?;
+                        let area = match next_points[lower].filter(|&first| first <= upper) {
+                            Some(first) => areas[upper] - areas[first],
+                            None => 0.0,
+                        };
+                        Some(Dynamic::from(Number::Float(area)))
+                    })
+                    .map(|value| value.unwrap_or(Dynamic::Null))
+                    .collect()
+            }
+            WindowFunction::ExpMovingAvg { ref input, alpha } => {
+                let mut average: Option<f64> = None;
+                let mut values = Vec::with_capacity(documents.len());
+                for document in documents {
+                    let value = eval_with_document(input, document, context)?;
+                    let value = match value.as_number() {
+                        Some(number) => {
+                            let number = number.as_f64();
+                            let next = average.map_or(number, |average| alpha * number + (1.0 - alpha) * average);
+                            average = Some(next);
+                            Dynamic::from(Number::Float(next))
+                        }
+                        None => Dynamic::Null,
+                    };
+                    values.push(value);
+                }
+                values
+            }
+        };
+
+        Ok(values)
+    }
+}
+
+/// Pairs the numeric `sortBy` value of every document with the numeric value of the input expression
+fn points(input: &Expression, numeric_keys: &[Option<f64>], documents: &[Dynamic], context: &mut Context) -> Result<Vec<Option<(f64, f64)>>, EvalError> {
+    let mut points = Vec::with_capacity(documents.len());
+    for (key, document) in numeric_keys.iter().zip(documents) {
+        let value = eval_with_document(input, document, context)?;
+        points.push(key.zip(value.as_number().map(Number::as_f64)));
+    }
+
+    Ok(points)
+}
+
+/// Accumulator over a window which moves forward through the values of a partition. Values are added
+/// when the window reaches them and removed when it passes them, so each value is handled once
+/// instead of accumulating every window again.
+struct SlidingWindow<'a> {
+    accumulator: &'a Accumulator,
+    values: &'a [Dynamic],
+    comparison: ObjectComparison,
+    /// Indices of the values in the window
+    range: Range<usize>,
+    state: SlidingState,
+}
+
+enum SlidingState {
+    Sum { ints: i128, floats: f64, float_count: usize },
+    Avg { sum: f64, count: i64 },
+    /// Indices of the values which can still become the minimum or maximum, the first being the current one
+    Extreme(VecDeque<usize>),
+    /// Number of times every distinct value is in the window. As in Mongo, the order of the values is unspecified.
+    AddToSet(LinkedHashMap<OrdDynamic, usize>),
+    /// `$first`, `$last`, `$push` and `$count` are read from the range of the window
+    Range,
+}
+
+impl SlidingState {
+    fn new(accumulator: &Accumulator) -> Self {
+        match accumulator {
+            Accumulator::Sum(_) => SlidingState::Sum { ints: 0, floats: 0.0, float_count: 0 },
+            Accumulator::Avg(_) => SlidingState::Avg { sum: 0.0, count: 0 },
+            Accumulator::Min(_) | Accumulator::Max(_) => SlidingState::Extreme(VecDeque::new()),
+            Accumulator::AddToSet(_) => SlidingState::AddToSet(LinkedHashMap::new()),
+            Accumulator::First(_) | Accumulator::Last(_) | Accumulator::Push(_) | Accumulator::Count => SlidingState::Range,
+        }
+    }
+}
+
+impl<'a> SlidingWindow<'a> {
+    fn new(accumulator: &'a Accumulator, values: &'a [Dynamic], comparison: ObjectComparison) -> Self {
+        SlidingWindow { accumulator, values, comparison, range: 0..0, state: SlidingState::new(accumulator) }
+    }
+
+    /// Moves the window to the inclusive bounds. Windows which move backwards are accumulated again.
+    fn slide(&mut self, lower: usize, upper: usize) {
+        if lower < self.range.start || upper + 1 < self.range.end {
+            self.range = lower..lower;
+            self.state = SlidingState::new(self.accumulator);
+        }
+        while self.range.end <= upper {
+            self.add(self.range.end);
+            self.range.end += 1;
+        }
+        while self.range.start < lower {
+            self.remove(self.range.start);
+            self.range.start += 1;
+        }
+    }
+
+    fn add(&mut self, index: usize) {
+        let value = &self.values[index];
+        // Candidates which are replaced by the value, later equal values don't replace earlier ones
+        let replaces = match self.accumulator {
+            Accumulator::Max(_) => Ordering::Greater,
+            _ => Ordering::Less,
+        };
+        match (&mut self.state, value) {
+            (SlidingState::Sum { ints, .. }, Dynamic::Number(Number::Int(int))) => *ints += *int as i128,
+            (SlidingState::Sum { floats, float_count, .. }, Dynamic::Number(Number::Float(float))) => {
+                *floats += float;
+                *float_count += 1;
+            }
+            (SlidingState::Avg { sum, count }, Dynamic::Number(number)) => {
+                *sum += number.as_f64();
+                *count += 1;
+            }
+            (SlidingState::Extreme(candidates), value) if !value.is_null() => {
+                while candidates.back().is_some_and(|&back| value.total_cmp_with(&self.values[back], self.comparison) == replaces) {
+                    candidates.pop_back();
+                }
+                candidates.push_back(index);
+            }
+            (SlidingState::AddToSet(counts), value) => {
+                let value = OrdDynamic(value.clone());
+                match counts.get_mut(&value) {
+                    Some(count) => *count += 1,
+                    None => {
+                        counts.insert(value, 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn remove(&mut self, index: usize) {
+        match (&mut self.state, &self.values[index]) {
+            (SlidingState::Sum { ints, .. }, Dynamic::Number(Number::Int(int))) => *ints -= *int as i128,
+            (SlidingState::Sum { floats, float_count, .. }, Dynamic::Number(Number::Float(float))) => {
+                *float_count -= 1;
+                // Rounding errors don't outlast the floats of the window
+                *floats = if *float_count == 0 { 0.0 } else { *floats - float };
+            }
+            (SlidingState::Avg { sum, count }, Dynamic::Number(number)) => {
+                *count -= 1;
+                *sum = if *count == 0 { 0.0 } else { *sum - number.as_f64() };
+            }
+            (SlidingState::Extreme(candidates), _) => {
+                if candidates.front() == Some(&index) {
+                    candidates.pop_front();
+                }
+            }
+            (SlidingState::AddToSet(counts), value) => {
+                let value = OrdDynamic(value.clone());
+                if let Some(count) = counts.get_mut(&value) {
+                    *count -= 1;
+                    if *count == 0 {
+                        counts.remove(&value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&self) -> Dynamic {
+        let window = &self.values[self.range.clone()];
+        match (&self.state, self.accumulator) {
+            (SlidingState::Sum { ints, float_count: 0, .. }, _) => {
+                Dynamic::from(i64::try_from(*ints).map_or(Number::Float(*ints as f64), Number::Int))
+            }
+            (SlidingState::Sum { ints, floats, .. }, _) => Dynamic::from(Number::Float(*ints as f64 + floats)),
+            (SlidingState::Avg { count: 0, .. }, _) => Dynamic::Null,
+            (SlidingState::Avg { sum, count }, _) => Dynamic::from(Number::Float(sum / *count as f64)),
+            (SlidingState::Extreme(candidates), _) => candidates.front().map_or(Dynamic::Null, |&index| self.values[index].clone()),
+            (SlidingState::AddToSet(counts), _) => Dynamic::from(counts.keys().map(|value| value.0.clone()).collect::<Vec<_>>()),
+            (SlidingState::Range, Accumulator::First(_)) => window.first().cloned().unwrap_or(Dynamic::Null),
+            (SlidingState::Range, Accumulator::Last(_)) => window.last().cloned().unwrap_or(Dynamic::Null),
+            (SlidingState::Range, Accumulator::Push(_)) => Dynamic::from(window.to_vec()),
+            (SlidingState::Range, _) => Dynamic::from(window.len() as i64),
+        }
+    }
+}
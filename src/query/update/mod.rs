@@ -0,0 +1,607 @@
+pub mod parser;
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+use derive_more::From;
+use hashlink::LinkedHashMap;
+use nom::Finish;
+use smallvec::SmallVec;
+use smartstring::alias::String;
+use crate::{Dynamic, DynamicError, DynamicObjectMut, Number, ObjectComparison};
+use crate::query::ast::{Field, InnerField, Positional, Predicate, Value, VariablePath};
+use crate::query::pipeline::{SortBy, SortOrder};
+use crate::query::update::parser::{parse_array_filters, parse_update};
+use crate::query::{Context, EvalError, ParseError};
+
+/// Mongo-style update document, e.g. `{ "$set": { "a.b": 1 }, "$inc": { "count": 1 } }`.
+/// Operators are applied in the order in which they are written.
+#[derive(From, Debug)]
+pub struct Update(pub Vec<UpdateOperator>);
+
+impl Update {
+    /// Applies the update to a document matched by the query. The query resolves `$` path segments
+    /// and the array filters resolve `$[identifier]` path segments.
+    pub fn apply_with_filters(
+        &self,
+        document: &mut Dynamic,
+        query: Option<&Predicate>,
+        array_filters: &ArrayFilters,
+        context: &mut Context,
+    ) -> Result<(), EvalError> {
+        let filters = Filters { query, array_filters };
+
+        self.apply_operators(document, &filters, false, context)
+    }
+
+    /// Applies the update to every document matched by the query. If no document matches,
+    /// a new document is seeded from the fixed fields of the query, updated with `$setOnInsert` honored
+    /// and appended. Returns whether a document was inserted.
+    pub fn upsert_with_context(
+        &self,
+        documents: &mut Vec<Dynamic>,
+        query: &Predicate,
+        array_filters: &ArrayFilters,
+        context: &mut Context,
+    ) -> Result<bool, EvalError> {
+        let filters = Filters { query: Some(query), array_filters };
+        let mut matched = false;
+        for document in documents.iter_mut() {
+            if query.test_with_context(document.clone(), context)? {
+                self.apply_operators(document, &filters, false, context)?;
+                matched = true;
+            }
+        }
+        if matched {
+            return Ok(false);
+        }
+
+        let mut document = Dynamic::from(LinkedHashMap::<String, Dynamic>::new());
+        for (field, value) in query.fixed_fields() {
+            for keys in filters.expand(&document, field.path(), context)? {
+                set(&mut document, &keys, Dynamic::from(&value))?;
+            }
+        }
+        self.apply_operators(&mut document, &filters, true, context)?;
+        documents.push(document);
+
+        Ok(true)
+    }
+
+    pub fn upsert(&self, documents: &mut Vec<Dynamic>, query: &Predicate) -> Result<bool, EvalError> {
+        let mut context = Context::new();
+
+        self.upsert_with_context(documents, query, &ArrayFilters::default(), &mut context)
+    }
+
+    fn apply_operators(&self, document: &mut Dynamic, filters: &Filters, inserting: bool, context: &mut Context) -> Result<(), EvalError> {
+        for operator in &self.0 {
+            if inserting || !matches!(operator, UpdateOperator::SetOnInsert(_)) {
+                operator.apply(document, filters, context)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn apply_with_context(&self, document: &mut Dynamic, context: &mut Context) -> Result<(), EvalError> {
+        self.apply_with_filters(document, None, &ArrayFilters::default(), context)
+    }
+
+    pub fn apply(&self, document: &mut Dynamic) -> Result<(), EvalError> {
+        let mut context = Context::new();
+
+        self.apply_with_context(document, &mut context)
+    }
+
+    /// Applies the update to a Rust object, whose modified fields are written back with `DynamicObjectMut`.
+    /// The object is left unchanged if the update fails.
+    pub fn apply_to<T>(&self, object: &mut T) -> Result<(), EvalError>
+    where
+        T: DynamicObjectMut + Clone + 'static,
+    {
+        let mut document = Dynamic::from(object.clone());
+        self.apply(&mut document)?;
+        let mut updated = object.clone();
+        for &field in object.fields().iter() {
+            let value = document.get_object_field(field);
+            if value == object.get_field(field) {
+                continue;
+            }
+            match value {
+                Some(value) => updated.set_field(field, value)?,
+                None => updated.remove_field(field)?,
+            };
+        }
+        *object = updated;
+
+        Ok(())
+    }
+}
+
+impl FromStr for Update {
+    type Err = ParseError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        parse_update(string)
+            .map_err(|x| x.to_owned())
+            .finish()
+            .map(|(_, x)| x)
+    }
+}
+
+#[derive(Debug)]
+pub enum UpdateOperator {
+    Set(Vec<(Field, Value)>),
+    /// Same as `$set`, but only applied to documents inserted by an upsert
+    SetOnInsert(Vec<(Field, Value)>),
+    Unset(Vec<Field>),
+    Inc(Vec<(Field, Number)>),
+    Mul(Vec<(Field, Number)>),
+    Min(Vec<(Field, Value)>),
+    Max(Vec<(Field, Value)>),
+    Rename(Vec<(Field, Field)>),
+    Push(Vec<(Field, Push)>),
+    Pull(Vec<(Field, Predicate)>),
+    PullAll(Vec<(Field, Vec<Value>)>),
+    AddToSet(Vec<(Field, Vec<Value>)>),
+    Pop(Vec<(Field, PopPosition)>),
+}
+
+/// Items appended by `$push` together with the `$position`, `$sort` and `$slice` modifiers
+#[derive(Debug)]
+pub struct Push {
+    pub each: Vec<Value>,
+    pub position: Option<i64>,
+    pub sort: Option<PushSort>,
+    pub slice: Option<i64>,
+}
+
+impl From<Value> for Push {
+    fn from(value: Value) -> Self {
+        Push {
+            each: vec![value],
+            position: None,
+            sort: None,
+            slice: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PushSort {
+    Value(SortOrder),
+    Fields(SortBy),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PopPosition {
+    First,
+    Last,
+}
+
+impl UpdateOperator {
+    fn apply(&self, document: &mut Dynamic, filters: &Filters, context: &mut Context) -> Result<(), EvalError> {
+        match self {
+            UpdateOperator::Set(fields) | UpdateOperator::SetOnInsert(fields) => {
+                for (field, value) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        set(document, &keys, Dynamic::from(value))?;
+                    }
+                }
+            }
+            UpdateOperator::Unset(fields) => {
+                for field in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        remove(document, &keys)?;
+                    }
+                }
+            }
+            UpdateOperator::Inc(fields) => {
+                for (field, number) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        let value = match get(document, &keys) {
+                            None => *number,
+                            Some(Dynamic::Number(value)) => value + *number,
+                            Some(_) => return Err(EvalError::from(DynamicError::NotANumber)),
+                        };
+                        set(document, &keys, Dynamic::from(value))?;
+                    }
+                }
+            }
+            UpdateOperator::Mul(fields) => {
+                for (field, number) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        let value = match get(document, &keys) {
+                            None => *number * Number::Int(0),
+                            Some(Dynamic::Number(value)) => value * *number,
+                            Some(_) => return Err(EvalError::from(DynamicError::NotANumber)),
+                        };
+                        set(document, &keys, Dynamic::from(value))?;
+                    }
+                }
+            }
+            UpdateOperator::Min(fields) | UpdateOperator::Max(fields) => {
+                let replace_when = match self {
+                    UpdateOperator::Min(_) => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                for (field, value) in fields {
+                    let value = Dynamic::from(value);
+                    for keys in filters.expand(document, field.path(), context)? {
+                        let replace = get(document, &keys)
+                            .is_none_or(|current| value.total_cmp_with(&current, context.object_comparison()) == replace_when);
+                        if replace {
+                            set(document, &keys, value.clone())?;
+                        }
+                    }
+                }
+            }
+            UpdateOperator::Rename(fields) => {
+                for (from, to) in fields {
+                    let from = filters.expand(document, from.path(), context)?;
+                    let to = filters.expand(document, to.path(), context)?;
+                    for (from, to) in from.iter().zip(&to) {
+                        if let Some(value) = remove(document, from)? {
+                            set(document, to, value)?;
+                        }
+                    }
+                }
+            }
+            UpdateOperator::Push(fields) => {
+                for (field, push) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        let mut array = get_array(document, &keys)?;
+                        push.apply(&mut array, context.object_comparison());
+                        set(document, &keys, Dynamic::from(array))?;
+                    }
+                }
+            }
+            UpdateOperator::Pull(fields) => {
+                for (field, predicate) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        let array = get_array(document, &keys)?;
+                        let mut retained = SmallVec::with_capacity(array.len());
+                        for item in array {
+                            if !predicate.test_with_context(item.clone(), context)? {
+                                retained.push(item);
+                            }
+                        }
+                        set(document, &keys, Dynamic::from(retained))?;
+                    }
+                }
+            }
+            UpdateOperator::PullAll(fields) => {
+                for (field, values) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        let mut array = get_array(document, &keys)?;
+                        array.retain(|item| !values.iter().any(|value| value.eq(item)));
+                        set(document, &keys, Dynamic::from(array))?;
+                    }
+                }
+            }
+            UpdateOperator::AddToSet(fields) => {
+                for (field, values) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        let mut array = get_array(document, &keys)?;
+                        for value in values {
+                            let value = Dynamic::from(value);
+                            if !array.iter().any(|item| item.total_cmp_with(&value, context.object_comparison()).is_eq()) {
+                                array.push(value);
+                            }
+                        }
+                        set(document, &keys, Dynamic::from(array))?;
+                    }
+                }
+            }
+            UpdateOperator::Pop(fields) => {
+                for (field, position) in fields {
+                    for keys in filters.expand(document, field.path(), context)? {
+                        if get(document, &keys).is_none() {
+                            continue;
+                        }
+                        let mut array = get_array(document, &keys)?;
+                        if !array.is_empty() {
+                            match position {
+                                PopPosition::First => drop(array.remove(0)),
+                                PopPosition::Last => drop(array.pop()),
+                            }
+                        }
+                        set(document, &keys, Dynamic::from(array))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Push {
+    fn apply(&self, array: &mut SmallVec<Dynamic, 10>, comparison: ObjectComparison) {
+        let items = self.each.iter().map(Dynamic::from);
+        match self.position {
+            Some(position) => {
+                let length = array.len() as i64;
+                let position = if position < 0 { length + position } else { position };
+                let position = position.clamp(0, length) as usize;
+                let tail = array.drain(position..).collect::<Vec<_>>();
+                array.extend(items);
+                array.extend(tail);
+            }
+            None => array.extend(items),
+        }
+
+        match self.sort {
+            Some(PushSort::Value(order)) => array.sort_by(|first, second| {
+                let ordering = first.total_cmp_with(second, comparison);
+                match order {
+                    SortOrder::Ascending => ordering,
+                    SortOrder::Descending => ordering.reverse(),
+                }
+            }),
+            Some(PushSort::Fields(ref sort_by)) => {
+                let mut keyed = array
+                    .drain(..)
+                    .map(|item| (sort_by.keys(&item), item))
+                    .collect::<Vec<_>>();
+                keyed.sort_by(|(first, _), (second, _)| sort_by.compare(first, second, comparison));
+                array.extend(keyed.into_iter().map(|(_, item)| item));
+            }
+            None => {}
+        }
+
+        match self.slice {
+            Some(slice) if slice >= 0 => array.truncate(slice as usize),
+            Some(slice) => {
+                let skip = array.len().saturating_sub(slice.unsigned_abs() as usize);
+                drop(array.drain(..skip));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Named conditions which select the array items updated through `$[identifier]`,
+/// e.g. `[{ "item.score": { "$gte": 8 } }]` for the identifier `item`
+#[derive(From, Debug, Default)]
+pub struct ArrayFilters(pub LinkedHashMap<String, Predicate>);
+
+impl FromStr for ArrayFilters {
+    type Err = ParseError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        parse_array_filters(string)
+            .map_err(|x| x.to_owned())
+            .finish()
+            .map(|(_, x)| x)
+    }
+}
+
+/// Conditions which resolve positional segments of update paths
+struct Filters<'a> {
+    query: Option<&'a Predicate>,
+    array_filters: &'a ArrayFilters,
+}
+
+impl Filters<'_> {
+    /// Resolves positional, wildcard, slice and negative index segments of the path against the document,
+    /// returning a path for every array item which the path refers to
+    fn expand<'a>(&self, document: &Dynamic, path: &'a VariablePath, context: &mut Context) -> Result<Vec<Vec<Key<'a>>>, EvalError> {
+        let mut paths = vec![Vec::new()];
+        for segment in segments(path)? {
+            let key = match segment {
+                Segment::Key(key) => key,
+                Segment::Items(field) => {
+                    let mut expanded = Vec::new();
+                    for keys in paths {
+                        let items = match get(document, &keys) {
+                            Some(Dynamic::Array(array)) => array.to_items(),
+                            _ => return Err(EvalError::from(DynamicError::NotAnArray)),
+                        };
+                        for index in self.indices(document, &keys, field, items, context)? {
+                            let mut keys = keys.clone();
+                            keys.push(Key::Index(index));
+                            expanded.push(keys);
+                        }
+                    }
+                    paths = expanded;
+                    continue;
+                }
+            };
+            for keys in &mut paths {
+                keys.push(key);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    fn indices(
+        &self,
+        document: &Dynamic,
+        keys: &[Key],
+        field: &InnerField,
+        items: SmallVec<Dynamic, 10>,
+        context: &mut Context,
+    ) -> Result<Vec<usize>, EvalError> {
+        let positional = match field {
+            InnerField::Positional(positional) => positional,
+            InnerField::NegativeIndex(negative_index) => {
+                let index = items.len().checked_sub(negative_index.index).ok_or(DynamicError::IndexOutOfBounds)?;
+                return Ok(vec![index]);
+            }
+            InnerField::Slice(slice) => return Ok(slice.indices(items.len()).collect()),
+            _ => return Ok((0..items.len()).collect()),
+        };
+        match positional {
+            Positional::All => Ok((0..items.len()).collect()),
+            Positional::Filtered(identifier) => {
+                let filter = self.array_filters.0.get(identifier).ok_or(EvalError::UndefinedArrayFilter)?;
+                let mut indices = Vec::new();
+                for (index, item) in items.into_iter().enumerate() {
+                    let item = LinkedHashMap::<String, Dynamic>::from_iter([(identifier.clone(), item)]);
+                    if filter.test_with_context(item, context)? {
+                        indices.push(index);
+                    }
+                }
+                Ok(indices)
+            }
+            Positional::First => {
+                let query = self.query.ok_or(EvalError::NoPositionalMatch)?;
+                for (index, item) in items.into_iter().enumerate() {
+                    // The query is tested as if the array contained only the item
+                    if query.test_with_context(with_keys(document, keys, item), context)? {
+                        return Ok(vec![index]);
+                    }
+                }
+                Err(EvalError::NoPositionalMatch)
+            }
+        }
+    }
+}
+
+/// Segment of a path used to address values inside of a document
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key<'a> {
+    Member(&'a str),
+    Index(usize),
+}
+
+enum Segment<'a> {
+    Key(Key<'a>),
+    /// Segment which selects items of an array
+    Items(&'a InnerField),
+}
+
+/// Splits the path into segments. Member wildcards and recursive descent can not be updated.
+fn segments(path: &VariablePath) -> Result<Vec<Segment<'_>>, EvalError> {
+    match path {
+        VariablePath::BaseVariable(variable) => Ok(vec![Segment::Key(Key::Member(&variable.field))]),
+        VariablePath::InnerField { base, field } => {
+            let mut segments = segments(base)?;
+            segments.push(match field {
+                InnerField::MemberAccess(member_access) => Segment::Key(Key::Member(&member_access.member)),
+                InnerField::ArrayIndex(array_index) => Segment::Key(Key::Index(array_index.index)),
+                InnerField::MemberWildcard | InnerField::Descendant(_) => return Err(EvalError::UnsupportedPath),
+                field => Segment::Items(field),
+            });
+            Ok(segments)
+        }
+    }
+}
+
+fn get_key(value: &Dynamic, key: Key) -> Option<Dynamic> {
+    match (value, key) {
+        (Dynamic::Object(_), Key::Member(member)) => value.get_object_field(member),
+        (Dynamic::Array(_), Key::Index(index)) => value.get_array_item(index),
+        // Numeric members address array items, as in `items.0`
+        (Dynamic::Array(_), Key::Member(member)) => value.get_array_item(member.parse().ok()?),
+        _ => None,
+    }
+}
+
+fn get_key_mut<'a>(value: &'a mut Dynamic, key: Key) -> Option<&'a mut Dynamic> {
+    match (&*value, key) {
+        (Dynamic::Object(_), Key::Member(member)) => value.get_object_field_mut(member),
+        (Dynamic::Array(_), Key::Index(index)) => value.get_array_item_mut(index),
+        (Dynamic::Array(_), Key::Member(member)) => value.get_array_item_mut(member.parse().ok()?),
+        _ => None,
+    }
+}
+
+fn set_key(value: &mut Dynamic, key: Key, item: Dynamic) -> Result<Option<Dynamic>, DynamicError> {
+    match (&value, key) {
+        (Dynamic::Array(_), Key::Index(index)) => value.set_array_item(index, item),
+        (Dynamic::Array(_), Key::Member(member)) => {
+            let index = member.parse().map_err(|_| DynamicError::NotAnObject)?;
+            value.set_array_item(index, item)
+        }
+        (_, Key::Member(member)) => value.set_object_field(member, item),
+        (_, Key::Index(_)) => Err(DynamicError::NotAnArray),
+    }
+}
+
+fn get(document: &Dynamic, keys: &[Key]) -> Option<Dynamic> {
+    keys.iter()
+        .try_fold(document.clone(), |value, &key| get_key(&value, key))
+}
+
+/// Returns a copy of the document with the value at the path replaced, copying only containers along the path
+fn with_keys(document: &Dynamic, keys: &[Key], value: Dynamic) -> Dynamic {
+    let Some((&first, rest)) = keys.split_first() else {
+        return value;
+    };
+    let child = get_key(document, first).unwrap_or(Dynamic::Null);
+    let child = with_keys(&child, rest, value);
+    let mut document = match document {
+        Dynamic::Object(object) => Dynamic::from(object.to_map()),
+        Dynamic::Array(_) => document.clone(),
+        _ => return document.clone(),
+    };
+    let _ = set_key(&mut document, first, child);
+    document
+}
+
+/// Returns the array at the path, or an empty array if the path doesn't exist
+fn get_array(document: &Dynamic, keys: &[Key]) -> Result<SmallVec<Dynamic, 10>, DynamicError> {
+    match get(document, keys) {
+        None | Some(Dynamic::Null) => Ok(SmallVec::new()),
+        Some(Dynamic::Array(array)) => Ok(array.to_items()),
+        Some(_) => Err(DynamicError::NotAnArray),
+    }
+}
+
+/// Sets the value at the path, creating empty objects for the missing parents
+fn set(document: &mut Dynamic, keys: &[Key], value: Dynamic) -> Result<Option<Dynamic>, DynamicError> {
+    let Some((&first, rest)) = keys.split_first() else {
+        return Ok(None);
+    };
+    if rest.is_empty() {
+        return set_key(document, first, value);
+    }
+    match get_key(document, first) {
+        Some(Dynamic::Object(_) | Dynamic::Array(_)) => {}
+        Some(Dynamic::Null) | None => {
+            set_key(document, first, Dynamic::from(LinkedHashMap::<String, Dynamic>::new()))?;
+        }
+        Some(_) => return Err(DynamicError::NotAnObject),
+    }
+    match get_key_mut(document, first) {
+        Some(child) => set(child, rest, value),
+        // Fields of dynamic objects can't be borrowed, so a copy of the field is modified and written back
+        None => {
+            let mut child = get_key(document, first).ok_or(DynamicError::ImmutableObject)?;
+            let previous = set(&mut child, rest, value)?;
+            set_key(document, first, child)?;
+            Ok(previous)
+        }
+    }
+}
+
+/// Removes the value at the path. Items of arrays are replaced with null, so other items keep their position.
+fn remove(document: &mut Dynamic, keys: &[Key]) -> Result<Option<Dynamic>, DynamicError> {
+    let Some((&first, rest)) = keys.split_first() else {
+        return Ok(None);
+    };
+    if rest.is_empty() {
+        return match (&*document, first) {
+            (Dynamic::Object(_), Key::Member(member)) => document.remove_object_field(member),
+            (Dynamic::Array(_), key) if get_key(document, key).is_some() => set_key(document, key, Dynamic::Null),
+            _ => Ok(None),
+        };
+    }
+    if get_key(document, first).is_none() {
+        return Ok(None);
+    }
+    match get_key_mut(document, first) {
+        Some(child) => remove(child, rest),
+        None => {
+            let mut child = get_key(document, first).ok_or(DynamicError::ImmutableObject)?;
+            let previous = remove(&mut child, rest)?;
+            if previous.is_some() {
+                set_key(document, first, child)?;
+            }
+            Ok(previous)
+        }
+    }
+}
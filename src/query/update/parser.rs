@@ -0,0 +1,134 @@
+use super::{ArrayFilters, PopPosition, Push, PushSort, Update, UpdateOperator};
+use crate::query::ast::parser::optional_named_arguments;
+use crate::query::ast::{AndOperator, Operator, OrOperator, Predicate, Value};
+use crate::query::parser::{array, array_of, escaped_string, field, fields_of, number, operator_pair, predicate, value, ws};
+use crate::query::pipeline::parser::{sort_by, sort_order};
+use nom::branch::alt;
+use nom::character::complete::{char, i64};
+use nom::combinator::{all_consuming, cut, map, map_opt, peek, verify};
+use nom::multi::separated_list1;
+use nom::sequence::{delimited, preceded};
+use nom::IResult;
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+
+pub fn parse_update(str: &str) -> IResult<&str, Update> {
+    all_consuming(ws(update))(str)
+}
+
+pub fn update(str: &str) -> IResult<&str, Update> {
+    map(
+        delimited(
+            ws(char('{')),
+            separated_list1(ws(char(',')), update_operator),
+            cut(ws(char('}'))),
+        ),
+        Update::from,
+    )(str)
+}
+
+pub fn update_operator(str: &str) -> IResult<&str, UpdateOperator> {
+    alt((
+        map(operator_pair("$setOnInsert", cut(fields_of(value))), UpdateOperator::SetOnInsert),
+        map(operator_pair("$set", cut(fields_of(value))), UpdateOperator::Set),
+        map(
+            operator_pair("$unset", cut(fields_of(value))),
+            |fields| UpdateOperator::Unset(fields.into_iter().map(|(field, _)| field).collect()),
+        ),
+        map(operator_pair("$inc", cut(fields_of(number))), UpdateOperator::Inc),
+        map(operator_pair("$mul", cut(fields_of(number))), UpdateOperator::Mul),
+        map(operator_pair("$min", cut(fields_of(value))), UpdateOperator::Min),
+        map(operator_pair("$max", cut(fields_of(value))), UpdateOperator::Max),
+        map(operator_pair("$rename", cut(fields_of(field))), UpdateOperator::Rename),
+        map(operator_pair("$push", cut(fields_of(push))), UpdateOperator::Push),
+        map(operator_pair("$pullAll", cut(fields_of(array))), UpdateOperator::PullAll),
+        map(operator_pair("$pull", cut(fields_of(predicate))), UpdateOperator::Pull),
+        map(operator_pair("$addToSet", cut(fields_of(add_to_set))), UpdateOperator::AddToSet),
+        map(operator_pair("$pop", cut(fields_of(pop_position))), UpdateOperator::Pop),
+    ))(str)
+}
+
+pub fn push(str: &str) -> IResult<&str, Push> {
+    alt((
+        // Only objects which start with a modifier are modifiers, any other value is pushed as it is
+        preceded(
+            peek(preceded(
+                ws(char('{')),
+                verify(escaped_string, |key: &str| ["$each", "$position", "$sort", "$slice"].contains(&key)),
+            )),
+            cut(map_opt(
+                optional_named_arguments((
+                    operator_pair("$each", array),
+                    operator_pair("$position", i64),
+                    operator_pair("$sort", push_sort),
+                    operator_pair("$slice", i64),
+                )),
+                |(each, position, sort, slice)| {
+                    Some(Push {
+                        each: each?,
+                        position,
+                        sort,
+                        slice,
+                    })
+                },
+            )),
+        ),
+        map(value, Push::from),
+    ))(str)
+}
+
+pub fn push_sort(str: &str) -> IResult<&str, PushSort> {
+    alt((
+        map(sort_order, PushSort::Value),
+        map(sort_by, PushSort::Fields),
+    ))(str)
+}
+
+pub fn add_to_set(str: &str) -> IResult<&str, Vec<Value>> {
+    alt((
+        delimited(
+            ws(char('{')),
+            operator_pair("$each", cut(array)),
+            cut(ws(char('}'))),
+        ),
+        map(value, |value| vec![value]),
+    ))(str)
+}
+
+pub fn pop_position(str: &str) -> IResult<&str, PopPosition> {
+    map_opt(i64, |position| match position {
+        1 => Some(PopPosition::Last),
+        -1 => Some(PopPosition::First),
+        _ => None,
+    })(str)
+}
+
+pub fn parse_array_filters(str: &str) -> IResult<&str, ArrayFilters> {
+    all_consuming(ws(array_filters))(str)
+}
+
+pub fn array_filters(str: &str) -> IResult<&str, ArrayFilters> {
+    map(
+        array_of(map_opt(predicate, |predicate| Some((identifier(&predicate)?, predicate)))),
+        |filters| ArrayFilters::from(LinkedHashMap::from_iter(filters)),
+    )(str)
+}
+
+/// Returns the identifier which all fields of the filter start with
+fn identifier(predicate: &Predicate) -> Option<String> {
+    let Predicate::Operators(operators) = predicate else {
+        return None;
+    };
+    let mut identifiers = operators.iter().map(|operator| match operator {
+        Operator::Field(field_operator) => Some(field_operator.field.path().base().field.clone()),
+        Operator::And(AndOperator(predicates)) | Operator::Or(OrOperator(predicates)) => {
+            let mut identifiers = predicates.iter().map(identifier);
+            let first = identifiers.next()??;
+            identifiers.all(|identifier| identifier.as_ref() == Some(&first)).then_some(first)
+        }
+        _ => None,
+    });
+    let first = identifiers.next()??;
+
+    identifiers.all(|identifier| identifier.as_ref() == Some(&first)).then_some(first)
+}
@@ -42,7 +42,7 @@ macro_rules! impl_sep_tuple {
 }
 
 impl_sep_tuple!{
-    A,B,C,D,E,F,G
+    A,B,C,D,E,F,G,H
 }
 
 pub trait SeparatedPermutation<I, O, E> {
@@ -125,5 +125,86 @@ macro_rules! impl_sep_permutation_inner {
 }
 
 impl_sep_permutation!{
-    A,B,C,D,E,F,G
-}
\ No newline at end of file
+    A,B,C,D,E,F,G,H
+}
+
+pub trait SeparatedOptionalPermutation<I, O, E> {
+    /// Tries to apply all parsers in the tuple in various orders until none of the remaining ones succeed
+    fn optional_permutation<U, S: Parser<I,U,E>>(x: &mut Self, separator: &mut S, input: I) -> IResult<I, O, E>;
+}
+
+pub fn separated_optional_permutation<I,T: SeparatedOptionalPermutation<I,O,E>,E,O,O1, S: Parser<I,O1,E>>(mut separator: S, mut permutation: T) -> impl FnMut(I) -> IResult<I,O,E>{
+    move |input: I| T::optional_permutation(&mut permutation, &mut separator, input)
+}
+
+macro_rules! impl_sep_optional_permutation {
+    () => {};
+    ($t0:ident $(,$tn:ident)* ) => {
+        impl_sep_optional_permutation!($($tn),*);
+        impl_sep_optional_permutation_inner!($t0 $(,$tn)* );
+    };
+}
+
+macro_rules! impl_sep_optional_permutation_inner {
+    ($($tn:ident),+ ) => {
+        paste::paste!{
+            impl<$([<Out $tn>],)+ $($tn: Parser<Input,[<Out $tn>],Error>,)+ Input: Clone,Error: ParseError<Input>> SeparatedOptionalPermutation<Input,($(Option<[<Out $tn>]>,)*), Error> for ($($tn,)*)
+            {
+                fn optional_permutation<U, S: Parser<Input,U,Error>>(($(ref mut [<$tn:lower>],)*): &mut Self, #[allow(unused_variables,unused_mut)] mut separator: &mut S, mut input: Input) -> IResult<Input, ($(Option<[<Out $tn>]>,)*), Error> {
+                    $(
+                    let mut [<res_ $tn:lower>] = Option::<[<Out $tn>]>::None;
+                    )*
+                    let mut first = true;
+                    loop {
+                        match ($(&[<res_ $tn:lower>],)+) {
+                            ($(Some([<_res_ $tn:lower>]),)+) => break,
+                            _  if !first => {
+                                // No separator means that there are no more arguments
+                                match separator.parse(input.clone()) {
+                                    Ok((i, _)) => input = i,
+                                    Err(Err::Error(_)) => break,
+                                    Err(e) => return Err(e),
+                                }
+                            },
+                            _ => {}
+                        }
+                        let mut err: Option<Error> = None;
+                        $(
+                        if [<res_ $tn:lower>].is_none() {
+                            match [<$tn:lower>].parse(input.clone()) {
+                                Ok((i, o)) => {
+                                    input = i;
+                                    [<res_ $tn:lower>] = Some(o);
+                                    first = false;
+                                    continue;
+                                }
+                                Err(Err::Error(e)) => {
+                                    err = Some(match err {
+                                        Some(err) => err.or(e),
+                                        None => e,
+                                    });
+                                }
+                                Err(e) => return Err(e),
+                            };
+                        }
+                        )*
+
+                      // Nothing was parsed at all, so every argument is missing
+                      if first {
+                        break;
+                      }
+                      // A separator was consumed, but none of the remaining parsers accepted the input
+                      if let Some(err) = err {
+                        return Err(Err::Error(Error::append(input, ErrorKind::Permutation, err)));
+                      }
+                    }
+                    Ok((input,($([<res_ $tn:lower>],)+)))
+                }
+            }
+        }
+    };
+}
+
+impl_sep_optional_permutation!{
+    A,B,C,D,E,F,G,H
+}
@@ -0,0 +1,351 @@
+//! Lazy evaluation over raw JSON text, e.g. lines of NDJSON. Objects and arrays are scanned when they are
+//! first read and values are parsed when they are read, so unrelated parts of the text are only skipped.
+//! The text is only validated as far as it is read.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::query::ast::Predicate;
+use crate::query::{Context, EvalError};
+use crate::{Array, AsDynamicObjectMut, Dynamic, DynamicArray, DynamicObject, DynamicRef, Number, Object};
+
+fn skip_whitespace(bytes: &[u8], mut position: usize) -> usize {
+    while bytes.get(position).is_some_and(u8::is_ascii_whitespace) {
+        position += 1;
+    }
+    position
+}
+
+/// Returns the end of the string which starts at the position, after its closing quote
+fn skip_string(bytes: &[u8], mut position: usize) -> Option<usize> {
+    position += 1;
+    loop {
+        match *bytes.get(position)? {
+            b'"' => return Some(position + 1),
+            b'\\' => position += 2,
+            _ => position += 1,
+        }
+    }
+}
+
+/// Returns the end of the value which starts at the position, without parsing it.
+/// Literals and numbers are checked, the items of objects and arrays are only skipped.
+fn skip_value(bytes: &[u8], mut position: usize) -> Option<usize> {
+    match *bytes.get(position)? {
+        b'"' => skip_string(bytes, position),
+        b'{' | b'[' => {
+            let mut depth = 0usize;
+            loop {
+                match *bytes.get(position)? {
+                    b'"' => {
+                        position = skip_string(bytes, position)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(position + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                position += 1;
+            }
+        }
+        _ => {
+            let length = bytes[position..]
+                .iter()
+                .position(|byte| matches!(byte, b',' | b'}' | b']') || byte.is_ascii_whitespace())
+                .unwrap_or(bytes.len() - position);
+            let token = &bytes[position..position + length];
+            let valid = matches!(token, b"null" | b"true" | b"false")
+                || std::str::from_utf8(token).is_ok_and(|number| serde_json::from_str::<serde_json::Number>(number).is_ok());
+            valid.then_some(position + length)
+        }
+    }
+}
+
+/// Visits the ranges of the keys and values of the object which starts at the position,
+/// until the visitor returns `false`. Returns `None` if the object is malformed.
+fn scan_object(bytes: &[u8], start: usize, mut visit: impl FnMut(Range<usize>, Range<usize>) -> bool) -> Option<()> {
+    let mut position = skip_whitespace(bytes, start + 1);
+    if bytes.get(position) == Some(&b'}') {
+        return Some(());
+    }
+    loop {
+        if bytes.get(position) != Some(&b'"') {
+            return None;
+        }
+        let key_end = skip_string(bytes, position)?;
+        let key = position..key_end;
+        position = skip_whitespace(bytes, key_end);
+        if bytes.get(position) != Some(&b':') {
+            return None;
+        }
+        let value_start = skip_whitespace(bytes, position + 1);
+        let value_end = skip_value(bytes, value_start)?;
+        if !visit(key, value_start..value_end) {
+            return Some(());
+        }
+        position = skip_whitespace(bytes, value_end);
+        match *bytes.get(position)? {
+            b',' => position = skip_whitespace(bytes, position + 1),
+            b'}' => return Some(()),
+            _ => return None,
+        }
+    }
+}
+
+/// Visits the ranges of the items of the array which starts at the position. Returns `None` if the array is malformed.
+fn scan_array(bytes: &[u8], start: usize, mut visit: impl FnMut(Range<usize>)) -> Option<()> {
+    let mut position = skip_whitespace(bytes, start + 1);
+    if bytes.get(position) == Some(&b']') {
+        return Some(());
+    }
+    loop {
+        let end = skip_value(bytes, position)?;
+        visit(position..end);
+        position = skip_whitespace(bytes, end);
+        match *bytes.get(position)? {
+            b',' => position = skip_whitespace(bytes, position + 1),
+            b']' => return Some(()),
+            _ => return None,
+        }
+    }
+}
+
+/// Checks the value at the range, including the items of objects and arrays and the escapes of strings
+fn is_valid(text: &str, range: Range<usize>) -> bool {
+    let bytes = text.as_bytes();
+    let mut valid = true;
+    match bytes[range.start] {
+        b'{' => {
+            valid &= scan_object(bytes, range.start, |key, value| {
+                valid = string(text, key).is_some() && is_valid(text, value);
+                valid
+            })
+            .is_some();
+        }
+        b'[' => valid &= scan_array(bytes, range.start, |item| valid = valid && is_valid(text, item)).is_some(),
+        b'"' => valid = string(text, range).is_some(),
+        _ => {}
+    }
+    valid
+}
+
+/// Decodes the string at the range, which includes its quotes. Strings without escapes are borrowed.
+fn string(text: &str, range: Range<usize>) -> Option<Cow<'_, str>> {
+    let content = &text[range.start + 1..range.end - 1];
+    if !content.contains('\\') {
+        return Some(Cow::Borrowed(content));
+    }
+    serde_json::from_str(&text[range]).ok().map(Cow::Owned)
+}
+
+/// Parses the value at the range, which was checked by `skip_value`. Objects and arrays are exposed
+/// as lazily scanned dynamic objects and arrays.
+fn to_dynamic(text: &Arc<str>, range: Range<usize>) -> Dynamic {
+    match text.as_bytes()[range.start] {
+        b'{' => Dynamic::Object(Object::DynamicObject(Arc::new(RawObject {
+            text: text.clone(),
+            start: range.start,
+            members: OnceLock::new(),
+        }))),
+        b'[' => Dynamic::Array(Array::DynamicArray(Arc::new(RawArray {
+            text: text.clone(),
+            start: range.start,
+            items: OnceLock::new(),
+        }))),
+        b'"' => string(text, range).map_or(Dynamic::Null, |string| Dynamic::from(string.as_ref())),
+        _ => match &text[range] {
+            "null" => Dynamic::Null,
+            "true" => Dynamic::Bool(true),
+            "false" => Dynamic::Bool(false),
+            number => serde_json::from_str::<serde_json::Number>(number)
+                .map_or(Dynamic::Null, |number| Dynamic::from(Number::from(&number))),
+        },
+    }
+}
+
+/// Returns a view of the value at the range, which borrows strings without escapes from the text
+fn to_dynamic_ref(text: &Arc<str>, range: Range<usize>) -> DynamicRef<'_> {
+    if text.as_bytes()[range.start] == b'"' {
+        if let Some(Cow::Borrowed(string)) = string(text, range.clone()) {
+            return DynamicRef::String(string);
+        }
+    }
+    DynamicRef::Owned(to_dynamic(text, range))
+}
+
+/// Object of raw JSON text, whose members are scanned when it is first read
+struct RawObject {
+    text: Arc<str>,
+    start: usize,
+    members: OnceLock<Vec<(String, Range<usize>)>>,
+}
+
+impl RawObject {
+    fn members(&self) -> &[(String, Range<usize>)] {
+        self.members.get_or_init(|| {
+            let mut members = Vec::new();
+            let _ = scan_object(self.text.as_bytes(), self.start, |key, value| {
+                if let Some(key) = string(&self.text, key) {
+                    members.push((String::from(key.as_ref()), value));
+                }
+                true
+            });
+            members
+        })
+    }
+
+    fn get(&self, field: &str) -> Option<Range<usize>> {
+        self.members().iter().rev().find(|(key, _)| key == field).map(|(_, value)| value.clone())
+    }
+}
+
+impl AsDynamicObjectMut for RawObject {}
+
+impl DynamicObject for RawObject {
+    fn get_field(&self, field: &str) -> Option<Dynamic> {
+        Some(to_dynamic(&self.text, self.get(field)?))
+    }
+
+    fn fields(&self) -> Cow<'_, [&str]> {
+        Cow::Owned(self.members().iter().map(|(key, _)| key.as_str()).collect())
+    }
+
+    fn get_field_ref(&self, field: &str) -> Option<DynamicRef<'_>> {
+        Some(to_dynamic_ref(&self.text, self.get(field)?))
+    }
+}
+
+/// Array of raw JSON text, whose items are scanned when it is first read
+struct RawArray {
+    text: Arc<str>,
+    start: usize,
+    items: OnceLock<Vec<Range<usize>>>,
+}
+
+impl RawArray {
+    fn items(&self) -> &[Range<usize>] {
+        self.items.get_or_init(|| {
+            let mut items = Vec::new();
+            let _ = scan_array(self.text.as_bytes(), self.start, |item| items.push(item));
+            items
+        })
+    }
+}
+
+impl DynamicArray for RawArray {
+    fn len(&self) -> usize {
+        self.items().len()
+    }
+
+    fn get(&self, index: usize) -> Option<Dynamic> {
+        Some(to_dynamic(&self.text, self.items().get(index)?.clone()))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Dynamic> + '_> {
+        Box::new(self.items().iter().map(|item| to_dynamic(&self.text, item.clone())))
+    }
+
+    fn get_ref(&self, index: usize) -> Option<DynamicRef<'_>> {
+        Some(to_dynamic_ref(&self.text, self.items().get(index)?.clone()))
+    }
+}
+
+/// Returns the JSON text as a value which is parsed lazily, or `None` if the text isn't a single JSON value.
+/// Objects and arrays aren't scanned until they are read, so only their delimiters are checked.
+pub fn parse_lazy(text: impl Into<Arc<str>>) -> Option<Dynamic> {
+    let text = text.into();
+    let bytes = text.as_bytes();
+    let start = skip_whitespace(bytes, 0);
+    let end = bytes.iter().rposition(|byte| !byte.is_ascii_whitespace())? + 1;
+    let end = match bytes[start] {
+        b'{' | b'[' => {
+            let closing = if bytes[start] == b'{' { b'}' } else { b']' };
+            (start < end - 1 && bytes[end - 1] == closing).then_some(end)?
+        }
+        _ => skip_value(bytes, start).filter(|&value_end| value_end == end)?,
+    };
+    Some(to_dynamic(&text, start..end))
+}
+
+/// Copies the fields of the object which are in the set, as lazily parsed values, or returns `None`
+/// if any of them is malformed. The values of other fields are only skipped. Like `RawObject`,
+/// the last of duplicate keys wins, so the whole object is scanned.
+fn project(text: &str, fields: &HashSet<&str>) -> Option<Dynamic> {
+    let bytes = text.as_bytes();
+    let start = skip_whitespace(bytes, 0);
+    if bytes.get(start) != Some(&b'{') {
+        return parse_checked(text);
+    }
+    let mut map = LinkedHashMap::<String, Dynamic>::new();
+    let mut valid = true;
+    scan_object(bytes, start, |key, value| {
+        if let Some(key) = string(text, key).filter(|key| fields.contains(key.as_ref())) {
+            valid = is_valid(text, value.clone());
+            let value: Arc<str> = Arc::from(&text[value]);
+            map.replace(String::from(key.as_ref()), to_dynamic(&value, 0..value.len()));
+        }
+        valid
+    })?;
+    valid.then(|| Dynamic::from(map))
+}
+
+/// Returns the JSON text as a value which is parsed lazily, after checking all of it
+fn parse_checked(text: &str) -> Option<Dynamic> {
+    let document = parse_lazy(text)?;
+    let start = skip_whitespace(text.as_bytes(), 0);
+    is_valid(text, start..text.len()).then_some(document)
+}
+
+/// Predicate which tests documents of raw JSON text, e.g. lines of NDJSON.
+/// When the predicate only reads fields of the documents, the other fields are skipped without being parsed or copied.
+/// Only top-level fields are skipped: fields which are read are copied and checked as a whole, e.g. all of `service`
+/// for `service.region`. Documents whose read fields are malformed fail with `EvalError::InvalidJson`.
+pub struct RawFilter<'a> {
+    predicate: &'a Predicate,
+    /// Top-level fields read by the predicate, if it doesn't test whole documents
+    fields: Option<HashSet<&'a str>>,
+}
+
+impl<'a> RawFilter<'a> {
+    pub fn new(predicate: &'a Predicate) -> Self {
+        let fields = predicate
+            .paths()
+            .map(|paths| paths.into_iter().map(|path| path.base().field.as_str()).collect());
+
+        RawFilter { predicate, fields }
+    }
+
+    pub fn matches_with_context(&self, text: &str, context: &mut Context) -> Result<bool, EvalError> {
+        let document = match self.fields {
+            Some(ref fields) => project(text, fields),
+            None => parse_checked(text),
+        };
+
+        self.predicate.test_with_context(document.ok_or(EvalError::InvalidJson)?, context)
+    }
+
+    pub fn matches(&self, text: &str) -> Result<bool, EvalError> {
+        let mut context = Context::new();
+
+        self.matches_with_context(text, &mut context)
+    }
+
+    /// Returns the lines of NDJSON text which match the predicate. Blank lines are skipped.
+    pub fn filter_lines<'b>(&'b self, text: &'b str) -> impl Iterator<Item = Result<&'b str, EvalError>> + 'b {
+        let mut context = Context::new();
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(move |line| match self.matches_with_context(line, &mut context) {
+                Ok(matches) => matches.then_some(Ok(line)),
+                Err(error) => Some(Err(error)),
+            })
+    }
+}
@@ -0,0 +1,161 @@
+use hashlink::LinkedHashMap;
+use smartstring::alias::String;
+use crate::{Array, Dynamic, DynamicArray, DynamicObject, Number, Object};
+
+/// Borrowed view of a value, which refers to the data of a `Dynamic`, a `DynamicObject` or a `DynamicArray`
+/// instead of copying it. Values which can't be borrowed are owned.
+#[derive(Clone)]
+pub enum DynamicRef<'a> {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(&'a str),
+    Object(&'a dyn DynamicObject),
+    Array(&'a dyn DynamicArray),
+    Dynamic(&'a Dynamic),
+    Owned(Dynamic),
+}
+
+impl<'a> DynamicRef<'a> {
+    /// Returns the view of the field of an object
+    pub fn get_field(self, field: &str) -> Option<DynamicRef<'a>> {
+        match self {
+            DynamicRef::Object(object) => object.get_field_ref(field),
+            DynamicRef::Dynamic(Dynamic::Object(Object::Map(map))) => map.get(field).map(DynamicRef::from),
+            DynamicRef::Owned(value) => value.get_object_field(field).map(DynamicRef::Owned),
+            _ => None,
+        }
+    }
+
+    /// Returns the view of the item of an array
+    pub fn get_item(self, index: usize) -> Option<DynamicRef<'a>> {
+        match self {
+            DynamicRef::Array(array) => array.get_ref(index),
+            DynamicRef::Dynamic(Dynamic::Array(Array::Items(items))) => items.get(index).map(DynamicRef::from),
+            DynamicRef::Owned(value) => value.get_array_item(index).map(DynamicRef::Owned),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of items of an array
+    pub fn array_len(&self) -> Option<usize> {
+        match self {
+            DynamicRef::Array(array) => Some(array.len()),
+            DynamicRef::Dynamic(Dynamic::Array(array)) | DynamicRef::Owned(Dynamic::Array(array)) => Some(array.len()),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy of the item of an array, without copying the other items
+    pub fn array_item(&self, index: usize) -> Option<Dynamic> {
+        match self {
+            DynamicRef::Array(array) => array.get(index),
+            DynamicRef::Dynamic(value) => value.get_array_item(index),
+            DynamicRef::Owned(value) => value.get_array_item(index),
+            _ => None,
+        }
+    }
+
+    /// Copies the viewed value. Borrowed dynamic objects and arrays are copied into maps and arrays.
+    pub fn to_dynamic(&self) -> Dynamic {
+        match self {
+            DynamicRef::Null => Dynamic::Null,
+            DynamicRef::Bool(bool) => Dynamic::Bool(*bool),
+            DynamicRef::Number(number) => Dynamic::Number(*number),
+            DynamicRef::String(string) => Dynamic::from(*string),
+            DynamicRef::Object(object) => Dynamic::from(
+                object
+                    .fields()
+                    .iter()
+                    .filter_map(|&key| object.get_field(key).map(|value| (String::from(key), value)))
+                    .collect::<LinkedHashMap<_, _>>(),
+            ),
+            DynamicRef::Array(array) => Dynamic::from(array.iter().collect::<Vec<_>>()),
+            DynamicRef::Dynamic(value) => Dynamic::clone(value),
+            DynamicRef::Owned(value) => value.clone(),
+        }
+    }
+}
+
+impl<'a> From<&'a Dynamic> for DynamicRef<'a> {
+    fn from(value: &'a Dynamic) -> Self {
+        match value {
+            Dynamic::Null => DynamicRef::Null,
+            Dynamic::Bool(bool) => DynamicRef::Bool(*bool),
+            Dynamic::Number(number) => DynamicRef::Number(*number),
+            Dynamic::String(string) => DynamicRef::String(string.as_str()),
+            Dynamic::Object(Object::DynamicObject(object)) => DynamicRef::Object(object.as_ref()),
+            Dynamic::Array(Array::DynamicArray(array)) => DynamicRef::Array(array.as_ref()),
+            value => DynamicRef::Dynamic(value),
+        }
+    }
+}
+
+/// Values which can be viewed without copying them
+pub trait AsDynamicRef {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_>;
+}
+
+impl<T: DynamicObject> AsDynamicRef for T {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::Object(self)
+    }
+}
+
+impl<T> AsDynamicRef for Vec<T>
+where
+    Vec<T>: DynamicArray,
+{
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::Array(self)
+    }
+}
+
+impl AsDynamicRef for Dynamic {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::from(self)
+    }
+}
+
+impl AsDynamicRef for String {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::String(self.as_str())
+    }
+}
+
+impl AsDynamicRef for std::string::String {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::String(self.as_str())
+    }
+}
+
+impl AsDynamicRef for &str {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::String(self)
+    }
+}
+
+impl AsDynamicRef for bool {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::Bool(*self)
+    }
+}
+
+impl AsDynamicRef for Number {
+    fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+        DynamicRef::Number(*self)
+    }
+}
+
+macro_rules! number_as_dynamic_ref {
+    ($variant:ident as $target:ty: $($source:ty),*) => {
+        $(impl AsDynamicRef for $source {
+            fn as_dynamic_ref(&self) -> DynamicRef<'_> {
+                DynamicRef::Number(Number::$variant(*self as $target))
+            }
+        })*
+    };
+}
+
+number_as_dynamic_ref!(Int as i64: i8, i16, i32, i64, u8, u16, u32);
+number_as_dynamic_ref!(Float as f64: f32, f64);